@@ -0,0 +1,352 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet, VecDeque};
+
+use crate::audio::Sound;
+use crate::config::{MAX_STEP_HEIGHT, MELEE_RANGE, MONSTER_SENSE_DISTANCE, PLAYER_RADIUS, REPATH_TICKS, TICK_RATE};
+use crate::data::{GameData, PlayerData, Vec2};
+use crate::entity::SOLID;
+use crate::map::Map;
+use crate::sprite::State;
+
+/// How a monster moves and fights, distances in grid units.
+#[derive(Clone, Copy)]
+pub struct Monster {
+    pub health: i32,
+    /// Chance to flinch when hit.
+    pub pain_chance: f64,
+    /// Grid units per second.
+    pub speed: f64,
+    /// Highest damage of an attack, 0 if it can't attack that way.
+    pub melee_damage: u32,
+    pub ranged_damage: u32,
+    pub ranged_range: f64,
+    /// Chance to start a ranged attack on every tick it could.
+    pub ranged_chance: f64,
+    /// Chance that a ranged attack hits.
+    pub accuracy: f64,
+    /// Ticks until it can attack again.
+    pub cooldown: u32,
+    pub sounds: MonsterSounds
+}
+
+/// What a monster sounds like when it wakes up, attacks, flinches and dies.
+#[derive(Clone, Copy)]
+pub struct MonsterSounds {
+    pub sight: Sound,
+    pub melee: Sound,
+    pub ranged: Sound,
+    pub pain: Sound,
+    pub death: Sound
+}
+
+/// A cell of the map grid.
+pub type Cell = (i32, i32);
+
+pub fn cell(pos: &Vec2<f64>) -> Cell {
+    (pos.x.floor() as i32, pos.y.floor() as i32)
+}
+
+/// True if no wall of the grid is between the two points, uses the same ray traversal as the renderer.
+pub fn line_of_sight(map: &Map, from: &Vec2<f64>, to: &Vec2<f64>) -> bool {
+    let dir = Vec2::new(to.x - from.x, to.y - from.y);
+    if dir.x == 0.0 && dir.y == 0.0 {
+        return true;
+    }
+
+    // The direction isn't normalized, so the distance is how far from `from` to `to` the wall is.
+    map.cast_ray(from, &dir).dist >= 1.0
+}
+
+/// A* over the open cells, diagonal steps only where both cells next to them are open too.
+/// The path leaves out the start, None if the goal can't be reached.
+pub fn find_path(map: &Map, start: Cell, goal: Cell) -> Option<Vec<Cell>> {
+    if !map.is_open(goal.0, goal.1) {
+        return None;
+    }
+
+    // A straight step costs 10, a diagonal one 14.
+    let heuristic = |(x, y): Cell| {
+        let (dx, dy) = ((x - goal.0).abs(), (y - goal.1).abs());
+        10 * dx.max(dy) + 4 * dx.min(dy)
+    };
+
+    let mut open = BinaryHeap::from([Reverse((heuristic(start), start))]);
+    let mut costs = HashMap::from([(start, 0)]);
+    let mut came_from: HashMap<Cell, Cell> = HashMap::new();
+
+    while let Some(Reverse((_, current))) = open.pop() {
+        if current == goal {
+            let mut path = vec![goal];
+            let mut current = goal;
+            while let Some(&previous) = came_from.get(&current) {
+                if previous != start {
+                    path.push(previous);
+                }
+                current = previous;
+            }
+
+            path.reverse();
+            return Some(path);
+        }
+
+        for (dx, dy) in [(1, 0), (-1, 0), (0, 1), (0, -1), (1, 1), (1, -1), (-1, 1), (-1, -1)] {
+            let next = (current.0 + dx, current.1 + dy);
+            let diagonal = dx != 0 && dy != 0;
+
+            if !map.is_open(next.0, next.1)
+                || (diagonal && !(map.is_open(current.0 + dx, current.1) && map.is_open(current.0, current.1 + dy))) {
+                continue;
+            }
+
+            let cost = costs[&current] + if diagonal {14} else {10};
+            if costs.get(&next).is_some_and(|&old| old <= cost) {
+                continue;
+            }
+
+            costs.insert(next, cost);
+            came_from.insert(next, current);
+            open.push(Reverse((cost + heuristic(next), next)));
+        }
+    }
+
+    None
+}
+
+/// Wakes up every monster a noise can reach through open cells, walls stop it but distance doesn't.
+pub fn alert(game_data: &mut GameData, origin: &Vec2<f64>) {
+    if game_data.custom_level {
+        return;
+    }
+
+    let map = &game_data.map;
+    let mut heard = HashSet::from([cell(origin)]);
+    let mut queue = VecDeque::from([cell(origin)]);
+
+    while let Some((x, y)) = queue.pop_front() {
+        for next in [(x + 1, y), (x - 1, y), (x, y + 1), (x, y - 1)] {
+            if map.is_open(next.0, next.1) && heard.insert(next) {
+                queue.push_back(next);
+            }
+        }
+    }
+
+    for entity in &mut game_data.entities {
+        if entity.monster.is_some() && heard.contains(&cell(&entity.pos)) {
+            entity.alerted = true;
+        }
+    }
+}
+
+/// Update hook of the monsters: waits until it notices the player, then chases and attacks.
+pub fn think(index: usize, player: &mut PlayerData, game_data: &mut GameData) {
+    // Only the grid has cells to find paths on.
+    if game_data.custom_level {
+        return;
+    }
+
+    let entity = &mut game_data.entities[index];
+    let Some(monster) = entity.monster else {
+        return;
+    };
+
+    entity.cooldown = entity.cooldown.saturating_sub(1);
+
+    let to_player = Vec2::new(player.pos.x - entity.pos.x, player.pos.y - entity.pos.y);
+    let distance = to_player.x.hypot(to_player.y);
+    let sees = line_of_sight(&game_data.map, &entity.pos, &player.pos);
+
+    match entity.state {
+        State::Death => {},
+        State::Idle => {
+            // Only sees what is in front of it, but notices the player right behind it.
+            let in_front = entity.angle.cos() * to_player.x + entity.angle.sin() * to_player.y > 0.0;
+
+            if entity.alerted || (sees && (in_front || distance < MONSTER_SENSE_DISTANCE)) {
+                entity.alerted = true;
+                entity.set_state(State::Walk);
+                game_data.audio.play(monster.sounds.sight, Some(entity.pos));
+            }
+        },
+        State::Attack | State::Pain => {
+            entity.angle = to_player.y.atan2(to_player.x);
+
+            if entity.state_ticks >= entity.animation_length() {
+                entity.set_state(State::Walk);
+            }
+        },
+        State::Walk => {
+            if sees && entity.cooldown == 0 {
+                let rng = &mut game_data.rng;
+                let melee = monster.melee_damage > 0 && distance <= entity.radius + PLAYER_RADIUS + MELEE_RANGE;
+                let ranged = !melee && monster.ranged_damage > 0 && distance <= monster.ranged_range && rng.chance(monster.ranged_chance);
+
+                if melee || ranged {
+                    let damage = if melee {
+                        1 + rng.below(monster.melee_damage)
+                    } else if rng.chance(monster.accuracy) {
+                        1 + rng.below(monster.ranged_damage)
+                    } else {
+                        0
+                    };
+
+                    player.damage(damage as i32);
+                    game_data.audio.play(if melee {monster.sounds.melee} else {monster.sounds.ranged}, Some(entity.pos));
+                    if damage > 0 && !player.god {
+                        game_data.audio.play(Sound::PlayerPain, None);
+                    }
+
+                    entity.angle = to_player.y.atan2(to_player.x);
+                    entity.cooldown = monster.cooldown;
+                    entity.set_state(State::Attack);
+                    return;
+                }
+            }
+
+            chase(index, player, game_data, &monster);
+        }
+    }
+}
+
+/// Follows the path to the player a cell at a time, looking for a new one every REPATH_TICKS.
+fn chase(index: usize, player: &PlayerData, game_data: &mut GameData, monster: &Monster) {
+    let entity = &mut game_data.entities[index];
+
+    if entity.repath == 0 || entity.path.is_empty() {
+        entity.path = find_path(&game_data.map, cell(&entity.pos), cell(&player.pos)).unwrap_or_default();
+        entity.repath = REPATH_TICKS;
+    }
+    entity.repath -= 1;
+
+    let Some(&(x, y)) = entity.path.first() else {
+        return;
+    };
+
+    let pos = entity.pos;
+    let radius = entity.radius;
+    let target = Vec2::new(x as f64 + 0.5, y as f64 + 0.5);
+    let (dx, dy) = (target.x - pos.x, target.y - pos.y);
+    let length = dx.hypot(dy);
+    let step = monster.speed / TICK_RATE;
+
+    let reached = length <= step;
+    let new_pos = if reached {target} else {Vec2::new(pos.x + dx / length * step, pos.y + dy / length * step)};
+    entity.angle = dy.atan2(dx);
+
+    // The player and other solid things block it the same way they block the player, it just waits.
+    let dist = |a: &Vec2<f64>, b: &Vec2<f64>| (a.x - b.x).hypot(a.y - b.y);
+    let blocked = dist(&new_pos, &player.pos) < radius + PLAYER_RADIUS
+        || game_data.entities.iter().enumerate().any(|(i, other)| {
+            i != index && other.has_tag(SOLID) && other.height > MAX_STEP_HEIGHT
+                && dist(&new_pos, &other.pos) < other.radius + radius && dist(&new_pos, &other.pos) < dist(&pos, &other.pos)
+        });
+
+    if blocked {
+        return;
+    }
+
+    let entity = &mut game_data.entities[index];
+    entity.pos = new_pos;
+    if reached {
+        entity.path.remove(0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entity::{self, thing_type, Entity};
+    use crate::random::Rng;
+
+    /// Two rooms joined by a gap at (2, 4) and a third one that is walled in, indexed [x][y].
+    const GRID: [[i32; 6]; 7] = [
+        [1, 1, 1, 1, 1, 1],
+        [1, 0, 0, 0, 0, 1],
+        [1, 1, 1, 1, 0, 1],
+        [1, 0, 0, 0, 0, 1],
+        [1, 1, 1, 1, 1, 1],
+        [1, 0, 0, 0, 0, 1],
+        [1, 1, 1, 1, 1, 1]
+    ];
+
+    fn map() -> Map {
+        Map::from_grid(&GRID, None)
+    }
+
+    fn game_data() -> GameData {
+        GameData::new(false, 320, 200, map())
+    }
+
+    fn monster(kind: u16, x: f64, y: f64) -> Entity {
+        Entity::new(Vec2::new(x, y), 0.0, thing_type(kind).unwrap())
+    }
+
+    #[test]
+    fn paths_go_around_walls() {
+        let map = map();
+
+        // Straight through the gap, the walls next to it rule out the diagonals.
+        assert_eq!(find_path(&map, (1, 1), (3, 1)).unwrap(), [(1, 2), (1, 3), (1, 4), (2, 4), (3, 4), (3, 3), (3, 2), (3, 1)]);
+        assert_eq!(find_path(&map, (1, 1), (1, 2)).unwrap(), [(1, 2)]);
+        assert_eq!(find_path(&map, (1, 1), (1, 1)).unwrap(), [(1, 1)]);
+
+        assert!(find_path(&map, (1, 1), (2, 2)).is_none());
+        assert!(find_path(&map, (1, 1), (5, 2)).is_none());
+    }
+
+    #[test]
+    fn walls_block_the_sight() {
+        let map = map();
+
+        assert!(line_of_sight(&map, &Vec2::new(1.5, 1.5), &Vec2::new(1.5, 4.5)));
+        assert!(line_of_sight(&map, &Vec2::new(1.5, 4.5), &Vec2::new(3.5, 4.5)));
+        assert!(line_of_sight(&map, &Vec2::new(1.5, 1.5), &Vec2::new(1.5, 1.5)));
+
+        assert!(!line_of_sight(&map, &Vec2::new(1.5, 1.5), &Vec2::new(3.5, 1.5)));
+        assert!(!line_of_sight(&map, &Vec2::new(3.5, 3.5), &Vec2::new(5.5, 3.5)));
+    }
+
+    #[test]
+    fn noise_only_reaches_connected_cells() {
+        let mut game_data = game_data();
+        game_data.entities = vec![monster(3004, 1.5, 1.5), monster(3001, 5.5, 2.5), monster(3004, 3.5, 1.5)];
+
+        alert(&mut game_data, &Vec2::new(3.5, 3.5));
+
+        let alerted: Vec<bool> = game_data.entities.iter().map(|entity| entity.alerted).collect();
+        assert_eq!(alerted, [true, false, true]);
+    }
+
+    /// Everything the monsters and the player end up with after some ticks.
+    fn play(seed: u64, ticks: u32) -> Vec<(u64, u64, f64, i32, u32, usize)> {
+        let mut game_data = game_data();
+        game_data.rng = Rng::new(seed);
+        game_data.entities = vec![monster(3004, 1.5, 1.5), monster(3001, 3.5, 1.5), monster(3004, 1.5, 3.5)];
+
+        let mut player = game_data.new_player();
+        player.pos = Vec2::new(3.5, 4.5);
+        player.god = true;
+        alert(&mut game_data, &player.pos);
+
+        for _ in 0..ticks {
+            entity::tick(&mut player, &mut game_data);
+        }
+
+        game_data.entities.iter()
+            .map(|entity| (entity.pos.x.to_bits(), entity.pos.y.to_bits(), entity.angle, entity.health, entity.cooldown, entity.path.len()))
+            .chain([(game_data.rng.state(), 0, 0.0, player.health, 0, 0)])
+            .collect()
+    }
+
+    #[test]
+    fn the_same_seed_plays_the_same() {
+        let first = play(1234, 200);
+
+        assert_eq!(first, play(1234, 200));
+        // They attacked, which takes random numbers.
+        assert_ne!(first[3].0, Rng::new(1234).state());
+        // They found the player and got closer.
+        assert!(first[..3].iter().zip([(1.5, 1.5), (3.5, 1.5), (1.5, 3.5)]).any(|(state, start)| {
+            (f64::from_bits(state.0), f64::from_bits(state.1)) != start
+        }));
+    }
+}
@@ -0,0 +1,469 @@
+use std::f64::consts::TAU;
+use std::sync::Arc;
+
+use sdl2::audio::{AudioCallback, AudioDevice, AudioSpecDesired};
+use sdl2::Sdl;
+
+use crate::config::{MAX_CHANNELS, MIX_RATE, MIX_SAMPLES, OCCLUDED_VOLUME, SOUND_CLIP_DIST, SOUND_CLOSE_DIST, SOUND_SEPARATION};
+use crate::data::{GameData, PlayerData, Vec2};
+use crate::music::{MusicPlayer, Song};
+use crate::random::Rng;
+use crate::wad::Wad;
+
+/// Every sound effect of the game.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Sound {
+    Footstep,
+    Pistol,
+    Shotgun,
+    RocketLaunch,
+    Explosion,
+    ItemPickup,
+    PlayerPain,
+    PossessedSight,
+    ImpSight,
+    MonsterPain,
+    PossessedDeath,
+    ImpDeath,
+    Claw,
+    Fireball,
+    Switch,
+    Door,
+    Lift
+}
+
+impl Sound {
+    pub const ALL: [Sound; 17] = [
+        Sound::Footstep,
+        Sound::Pistol,
+        Sound::Shotgun,
+        Sound::RocketLaunch,
+        Sound::Explosion,
+        Sound::ItemPickup,
+        Sound::PlayerPain,
+        Sound::PossessedSight,
+        Sound::ImpSight,
+        Sound::MonsterPain,
+        Sound::PossessedDeath,
+        Sound::ImpDeath,
+        Sound::Claw,
+        Sound::Fireball,
+        Sound::Switch,
+        Sound::Door,
+        Sound::Lift
+    ];
+
+    /// The name for the `sound` console command.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Sound::Footstep => "footstep",
+            Sound::Pistol => "pistol",
+            Sound::Shotgun => "shotgun",
+            Sound::RocketLaunch => "rocket_launch",
+            Sound::Explosion => "explosion",
+            Sound::ItemPickup => "item_pickup",
+            Sound::PlayerPain => "player_pain",
+            Sound::PossessedSight => "possessed_sight",
+            Sound::ImpSight => "imp_sight",
+            Sound::MonsterPain => "monster_pain",
+            Sound::PossessedDeath => "possessed_death",
+            Sound::ImpDeath => "imp_death",
+            Sound::Claw => "claw",
+            Sound::Fireball => "fireball",
+            Sound::Switch => "switch",
+            Sound::Door => "door",
+            Sound::Lift => "lift"
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Sound> {
+        Sound::ALL.into_iter().find(|sound| sound.name() == name)
+    }
+
+    /// Doom's lump, Doom has no footsteps.
+    fn lump(&self) -> Option<&'static str> {
+        match self {
+            Sound::Footstep => None,
+            Sound::Pistol => Some("DSPISTOL"),
+            Sound::Shotgun => Some("DSSHOTGN"),
+            Sound::RocketLaunch => Some("DSRLAUNC"),
+            Sound::Explosion => Some("DSBAREXP"),
+            Sound::ItemPickup => Some("DSITEMUP"),
+            Sound::PlayerPain => Some("DSPLPAIN"),
+            Sound::PossessedSight => Some("DSPOSIT1"),
+            Sound::ImpSight => Some("DSBGSIT1"),
+            Sound::MonsterPain => Some("DSPOPAIN"),
+            Sound::PossessedDeath => Some("DSPODTH1"),
+            Sound::ImpDeath => Some("DSBGDTH1"),
+            Sound::Claw => Some("DSCLAW"),
+            Sound::Fireball => Some("DSFIRSHT"),
+            Sound::Switch => Some("DSSWTCHN"),
+            Sound::Door => Some("DSDOROPN"),
+            Sound::Lift => Some("DSSTNMOV")
+        }
+    }
+
+    /// Once all channels are busy a sound only replaces one that isn't more important.
+    fn priority(&self) -> u8 {
+        match self {
+            Sound::PlayerPain => 90,
+            Sound::Explosion => 80,
+            Sound::ItemPickup | Sound::PossessedDeath | Sound::ImpDeath => 70,
+            Sound::Pistol | Sound::Shotgun | Sound::RocketLaunch => 60,
+            Sound::MonsterPain | Sound::Claw | Sound::Fireball | Sound::Switch | Sound::Door | Sound::Lift => 50,
+            Sound::PossessedSight | Sound::ImpSight => 40,
+            Sound::Footstep => 10
+        }
+    }
+
+    /// Seconds, the pitch it starts and ends at and how much of it is noise (0.0 - 1.0), for the made up version.
+    fn synth(&self) -> (f64, f64, f64, f64) {
+        match self {
+            Sound::Footstep => (0.08, 90.0, 60.0, 0.7),
+            Sound::Pistol => (0.25, 400.0, 100.0, 0.9),
+            Sound::Shotgun => (0.5, 300.0, 60.0, 0.95),
+            Sound::RocketLaunch => (0.6, 200.0, 400.0, 0.8),
+            Sound::Explosion => (1.0, 80.0, 30.0, 1.0),
+            Sound::ItemPickup => (0.15, 600.0, 1200.0, 0.0),
+            Sound::PlayerPain => (0.35, 300.0, 150.0, 0.2),
+            Sound::PossessedSight => (0.5, 180.0, 120.0, 0.3),
+            Sound::ImpSight => (0.6, 140.0, 220.0, 0.4),
+            Sound::MonsterPain => (0.3, 250.0, 180.0, 0.3),
+            Sound::PossessedDeath => (0.7, 200.0, 60.0, 0.3),
+            Sound::ImpDeath => (0.8, 160.0, 50.0, 0.5),
+            Sound::Claw => (0.2, 500.0, 200.0, 0.8),
+            Sound::Fireball => (0.5, 250.0, 500.0, 0.7),
+            Sound::Switch => (0.1, 900.0, 700.0, 0.3),
+            Sound::Door => (0.8, 70.0, 110.0, 0.6),
+            Sound::Lift => (0.6, 60.0, 60.0, 0.8)
+        }
+    }
+
+    /// A sliding tone mixed with noise that fades out, used when there is no WAD with the real sound.
+    fn synthesize(&self) -> Vec<i16> {
+        let (length, from, to, noise) = self.synth();
+        let count = (length * MIX_RATE as f64) as usize;
+        let mut rng = Rng::new(1 + *self as u64);
+        let (mut phase, mut rumble) = (0.0, 0.0);
+
+        (0..count).map(|i| {
+            let t = i as f64 / count as f64;
+            phase += (from + (to - from) * t) / MIX_RATE as f64;
+
+            // The noise gets duller the lower the sound is.
+            rumble += (rng.unit() * 2.0 - 1.0 - rumble) * (from / 2000.0).min(1.0);
+            let envelope = (1.0 - t).powi(2) * (t * 200.0).min(1.0);
+            let sample = (phase * TAU).sin() * (1.0 - noise) + rumble * noise * 2.0;
+
+            (sample.clamp(-1.0, 1.0) * envelope * 0.5 * i16::MAX as f64) as i16
+        }).collect()
+    }
+}
+
+/// 8-bit unsigned samples at any rate to MIX_RATE, linearly interpolated.
+fn resample(rate: u32, samples: &[u8]) -> Vec<i16> {
+    let step = rate as f64 / MIX_RATE as f64;
+    let count = (samples.len() as f64 / step) as usize;
+    let sample = |i: usize| (samples[i.min(samples.len() - 1)] as f64 - 128.0) * 256.0;
+
+    (0..count).map(|i| {
+        let pos = i as f64 * step;
+        let (index, fraction) = (pos as usize, pos.fract());
+        (sample(index) * (1.0 - fraction) + sample(index + 1) * fraction) as i16
+    }).collect()
+}
+
+/// A sound being played, with the volume of both sides.
+pub struct Channel {
+    sound: Sound,
+    samples: Arc<Vec<i16>>,
+    position: usize,
+    left: f32,
+    right: f32
+}
+
+/// Runs in SDL's audio thread and adds up the channels and the music, the game starts sounds through AudioDevice::lock().
+#[derive(Default)]
+pub struct Mixer {
+    channels: Vec<Channel>,
+    pub music: MusicPlayer,
+    /// Reused for every callback, so the audio thread doesn't allocate.
+    music_buffer: Vec<f32>
+}
+
+impl AudioCallback for Mixer {
+    type Channel = i16;
+
+    fn callback(&mut self, out: &mut [i16]) {
+        self.mix(out);
+    }
+}
+
+impl Mixer {
+    /// Fills interleaved stereo samples, channels that are done get dropped.
+    pub fn mix(&mut self, out: &mut [i16]) {
+        self.music_buffer.clear();
+        self.music_buffer.resize(out.len(), 0.0);
+        self.music.mix(&mut self.music_buffer);
+
+        for (frame, music) in out.chunks_exact_mut(2).zip(self.music_buffer.chunks_exact(2)) {
+            let (mut left, mut right) = (music[0], music[1]);
+
+            for channel in &mut self.channels {
+                if let Some(&sample) = channel.samples.get(channel.position) {
+                    left += sample as f32 * channel.left;
+                    right += sample as f32 * channel.right;
+                    channel.position += 1;
+                }
+            }
+
+            frame[0] = left.clamp(i16::MIN as f32, i16::MAX as f32) as i16;
+            frame[1] = right.clamp(i16::MIN as f32, i16::MAX as f32) as i16;
+        }
+
+        self.channels.retain(|channel| channel.position < channel.samples.len());
+    }
+
+    /// Plays on a free channel. Once MAX_CHANNELS play, the least important one gets cut off
+    /// (the one furthest along of those), unless all of them are more important than the new sound.
+    fn start(&mut self, channel: Channel) {
+        if self.channels.len() < MAX_CHANNELS {
+            self.channels.push(channel);
+            return;
+        }
+
+        let weakest = self.channels.iter()
+            .enumerate()
+            .min_by_key(|(_, playing)| (playing.sound.priority(), std::cmp::Reverse(playing.position)))
+            .map(|(index, _)| index);
+
+        if let Some(index) = weakest.filter(|&index| self.channels[index].sound.priority() <= channel.sound.priority()) {
+            self.channels[index] = channel;
+        }
+    }
+}
+
+/// The audio device and the sounds, without a device (no audio, --nosound) playing does nothing.
+#[derive(Default)]
+pub struct Audio {
+    device: Option<AudioDevice<Mixer>>,
+    /// Indexed by Sound, mono at MIX_RATE.
+    sounds: Vec<Arc<Vec<i16>>>,
+    /// Sounds started since the last update() and where they come from, None for the player's own.
+    queue: Vec<(Sound, Option<Vec2<f64>>)>,
+    /// The file or lump of the song playing, so loading the same map again doesn't start it over.
+    pub music: Option<String>
+}
+
+impl Audio {
+    /// Opens the default device as 16-bit stereo, SDL converts to whatever the hardware wants.
+    /// The sounds are made up until a WAD brings the real ones.
+    pub fn open(&mut self, sdl_context: &Sdl) -> Result<(), String> {
+        let subsystem = sdl_context.audio()?;
+        let desired = AudioSpecDesired { freq: Some(MIX_RATE), channels: Some(2), samples: Some(MIX_SAMPLES) };
+        let device = subsystem.open_playback(None, &desired, |_| Mixer::default())?;
+        device.resume();
+
+        self.sounds = Sound::ALL.iter().map(|sound| Arc::new(sound.synthesize())).collect();
+        self.device = Some(device);
+
+        Ok(())
+    }
+
+    /// Takes the sounds the WAD has, the others stay as they are.
+    pub fn load_wad_sounds(&mut self, wad: &Wad) {
+        if self.device.is_none() {
+            return;
+        }
+
+        for sound in Sound::ALL {
+            // A broken lump keeps the made up sound, there's no reason to not load the map over it.
+            if let Some(Ok((rate, samples))) = sound.lump().map(|lump| wad.read_sound(lump)) {
+                if rate > 0 && !samples.is_empty() {
+                    self.sounds[sound as usize] = Arc::new(resample(rate, &samples));
+                }
+            }
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.device.is_some()
+    }
+
+    /// Crossfades to another song, None fades out to silence.
+    pub fn play_music(&mut self, song: Option<Song>) {
+        if let Some(device) = &mut self.device {
+            // Dropped here once the lock is let go, not in the audio thread.
+            let _dropped = device.lock().music.start(song);
+        }
+    }
+
+    /// Starts a sound with the next update(), `origin` None is right at the player without any panning.
+    pub fn play(&mut self, sound: Sound, origin: Option<Vec2<f64>>) {
+        if self.device.is_some() {
+            self.queue.push((sound, origin));
+        }
+    }
+}
+
+/// Left and right volume of a sound at `pos`, None if it is too far away to be heard.
+fn spatialize(player: &PlayerData, game_data: &GameData, pos: &Vec2<f64>) -> Option<(f64, f64)> {
+    let to_sound = Vec2::new(pos.x - player.pos.x, pos.y - player.pos.y);
+    let distance = to_sound.x.hypot(to_sound.y);
+    if distance >= SOUND_CLIP_DIST {
+        return None;
+    }
+
+    let mut volume = ((SOUND_CLIP_DIST - distance) / (SOUND_CLIP_DIST - SOUND_CLOSE_DIST)).min(1.0);
+
+    // Muffled behind a wall, the same ray as the shots.
+    if game_data.settings.sound_occlusion && game_data.cast_wall(&player.pos, &to_sound).0 < 1.0 {
+        volume *= OCCLUDED_VOLUME;
+    }
+
+    // -1.0 is all the way to the left and 1.0 to the right, the camera plane points to the right.
+    let pan = if distance > 0.0 {(to_sound.x * player.dir.y - to_sound.y * player.dir.x) / distance} else {0.0};
+
+    Some((volume * (1.0 - pan * SOUND_SEPARATION).min(1.0), volume * (1.0 + pan * SOUND_SEPARATION).min(1.0)))
+}
+
+/// Starts the sounds played since the last call, heard from where the player is now. Called once per frame.
+pub fn update(player: &PlayerData, game_data: &mut GameData) {
+    let queue = std::mem::take(&mut game_data.audio.queue);
+    let volume = game_data.settings.sound_volume;
+
+    let channels: Vec<Channel> = queue.into_iter()
+        .filter_map(|(sound, origin)| {
+            let (left, right) = match origin {
+                Some(pos) => spatialize(player, game_data, &pos)?,
+                None => (1.0, 1.0)
+            };
+
+            let samples = Arc::clone(game_data.audio.sounds.get(sound as usize)?);
+            Some(Channel { sound, samples, position: 0, left: (left * volume) as f32, right: (right * volume) as f32 })
+        })
+        .collect();
+
+    if let Some(device) = &mut game_data.audio.device {
+        let mut mixer = device.lock();
+        mixer.music.volume = game_data.settings.music_volume as f32;
+        for channel in channels {
+            mixer.start(channel);
+        }
+
+        // Songs that faded out get freed here, after the audio thread has the lock back.
+        let finished = mixer.music.take_finished();
+        drop(mixer);
+        drop(finished);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::Map;
+
+    /// A long room with one wall tile at (4, 1), the player looks along x so the right is -y.
+    fn game_data() -> GameData {
+        let mut grid = [[0; 5]; 24];
+        for (x, column) in grid.iter_mut().enumerate() {
+            for (y, tile) in column.iter_mut().enumerate() {
+                *tile = (x == 0 || x == 23 || y == 0 || y == 4 || (x, y) == (4, 1)) as i32;
+            }
+        }
+        GameData::new(false, 320, 200, Map::from_grid(&grid, None))
+    }
+
+    fn player(x: f64, y: f64) -> PlayerData {
+        PlayerData::new(Vec2::new(x, y), Vec2::new(1.0, 0.0), Vec2::new(0.0, -0.66))
+    }
+
+    fn channel(sound: Sound, samples: Vec<i16>, position: usize) -> Channel {
+        Channel { sound, samples: Arc::new(samples), position, left: 1.0, right: 1.0 }
+    }
+
+    fn volume(player: &PlayerData, game_data: &GameData, x: f64, y: f64) -> Option<(f64, f64)> {
+        spatialize(player, game_data, &Vec2::new(x, y)).map(|(left, right)| ((left * 1000.0).round() / 1000.0, (right * 1000.0).round() / 1000.0))
+    }
+
+    #[test]
+    fn busy_channels_cut_off_the_least_important_sound() {
+        let mut mixer = Mixer::default();
+        for position in 0..MAX_CHANNELS {
+            mixer.start(channel(Sound::Footstep, vec![0; 100], position));
+        }
+        assert_eq!(mixer.channels.len(), MAX_CHANNELS);
+
+        // Of the footsteps the one furthest along goes.
+        mixer.start(channel(Sound::Pistol, vec![0; 100], 0));
+        assert_eq!(mixer.channels.len(), MAX_CHANNELS);
+        assert!(mixer.channels[MAX_CHANNELS - 1].sound == Sound::Pistol);
+
+        // Equally important sounds replace each other, less important ones don't get in.
+        mixer.channels.iter_mut().for_each(|playing| *playing = channel(Sound::Explosion, vec![0; 100], 0));
+        mixer.start(channel(Sound::Shotgun, vec![0; 100], 0));
+        assert!(mixer.channels.iter().all(|playing| playing.sound == Sound::Explosion));
+
+        mixer.start(channel(Sound::PlayerPain, vec![0; 100], 0));
+        mixer.start(channel(Sound::Explosion, vec![1; 100], 0));
+        assert_eq!(mixer.channels.iter().filter(|playing| playing.sound == Sound::PlayerPain).count(), 1);
+        assert_eq!(mixer.channels.iter().filter(|playing| playing.samples[0] == 1).count(), 1);
+    }
+
+    #[test]
+    fn sounds_pan_and_fade_with_the_distance() {
+        let game_data = game_data();
+        let player = player(1.5, 2.5);
+
+        assert_eq!(volume(&player, &game_data, 3.5, 2.5), Some((1.0, 1.0)));
+        assert_eq!(volume(&player, &game_data, 1.5, 2.5), Some((1.0, 1.0)));
+
+        // Off to the right and to the left.
+        assert_eq!(volume(&player, &game_data, 1.5, 1.5), Some((0.25, 1.0)));
+        assert_eq!(volume(&player, &game_data, 1.5, 3.5), Some((1.0, 0.25)));
+
+        // Half way between SOUND_CLOSE_DIST and SOUND_CLIP_DIST.
+        assert_eq!(volume(&player, &game_data, 12.5, 2.5), Some((0.5, 0.5)));
+        assert_eq!(volume(&player, &game_data, 1.5 + SOUND_CLIP_DIST, 2.5), None);
+        assert_eq!(volume(&player, &game_data, 22.5, 2.5), None);
+    }
+
+    #[test]
+    fn walls_muffle_sounds() {
+        let mut game_data = game_data();
+        let player = player(1.5, 1.5);
+
+        assert_eq!(volume(&player, &game_data, 6.5, 1.5), Some((0.35, 0.35)));
+        // Past the wall tile the way is free, (19 - 29.sqrt()) / 16 on the louder side.
+        assert_eq!(volume(&player, &game_data, 6.5, 3.5).map(|(left, right)| left.max(right)), Some(0.851));
+
+        game_data.settings.sound_occlusion = false;
+        assert_eq!(volume(&player, &game_data, 6.5, 1.5), Some((0.875, 0.875)));
+    }
+
+    #[test]
+    fn resamples_to_the_mix_rate() {
+        assert_eq!(resample(MIX_RATE as u32, &[128, 0, 255, 192]), [0, -32768, 32512, 16384]);
+        assert_eq!(resample(MIX_RATE as u32 / 2, &[128, 192]), [0, 8192, 16384, 16384]);
+        assert_eq!(resample(MIX_RATE as u32 * 2, &[128, 0, 192, 255]), [0, 16384]);
+        assert!(resample(MIX_RATE as u32, &[]).is_empty());
+    }
+
+    #[test]
+    fn mixes_the_channels_and_drops_finished_ones() {
+        let mut mixer = Mixer::default();
+        mixer.start(Channel { right: 0.5, ..channel(Sound::Pistol, vec![1000, 2000], 0) });
+        mixer.start(Channel { right: 0.0, ..channel(Sound::Explosion, vec![30000, 32000, -32000, 100], 0) });
+
+        let mut out = [0; 6];
+        mixer.mix(&mut out);
+
+        // The second frame clips, the pistol is done after it.
+        assert_eq!(out, [31000, 500, 32767, 1000, -32000, 0]);
+        assert_eq!(mixer.channels.len(), 1);
+
+        mixer.mix(&mut out);
+        assert_eq!(out, [100, 0, 0, 0, 0, 0]);
+        assert!(mixer.channels.is_empty());
+    }
+}
@@ -0,0 +1,103 @@
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use sdl2::pixels::Color;
+
+use crate::config::{BENCH_TIME, BENCH_WARMUP_FRAMES};
+use crate::data::{GameData, LoadedTexture, PlayerData, Vec2};
+use crate::framebuffer::FrameBuffer;
+use crate::map::Map;
+use crate::rendering::render_view_canvas;
+
+const RESOLUTIONS: [(u32, u32); 3] = [(320, 200), (640, 480), (1280, 720)];
+
+/// A map and where the camera stands in it.
+struct Scene {
+    name: &'static str,
+    map: Map,
+    pos: Vec2<f64>,
+    dir: Vec2<f64>
+}
+
+/// Walls all around the edge of a width x height map, `inside` decides the tiles in between.
+fn walled_map(width: usize, height: usize, inside: impl Fn(usize, usize) -> i32) -> Map {
+    let tiles = (0..width)
+        .flat_map(|x| (0..height).map(move |y| (x, y)))
+        .map(|(x, y)| if x == 0 || y == 0 || x == width - 1 || y == height - 1 {1 + (x + y) as i32 % 4} else {inside(x, y)})
+        .collect();
+
+    Map { width, height, tiles, sky: None }
+}
+
+/// Wide open room, long rays over many empty cells; a corridor seen lengthwise, the most DDA steps per ray;
+/// and a room full of blocks, thin walls, diagonals and pillars, short rays that hit a lot of different walls.
+fn scenes() -> Vec<Scene> {
+    vec![
+        Scene { name: "open", map: walled_map(64, 64, |_, _| 0), pos: Vec2::new(2.5, 2.5), dir: Vec2::new(0.6, 0.8) },
+        Scene { name: "corridor", map: walled_map(256, 3, |_, _| 0), pos: Vec2::new(1.5, 1.5), dir: Vec2::new(1.0, 0.0) },
+        Scene {
+            name: "cluttered",
+            map: walled_map(32, 32, |x, y| if x % 2 == 0 && y % 2 == 0 {1 + (x * 7 + y * 3) as i32 % 8} else {0}),
+            pos: Vec2::new(1.5, 1.5),
+            dir: Vec2::new(0.8, 0.6)
+        }
+    ]
+}
+
+/// Checkerboard with a different color per wall type, made here so the numbers don't depend on the files in pics/.
+fn bench_texture(color: Color) -> LoadedTexture {
+    let pixels = (0..64 * 64).flat_map(|i| {
+        let dark = ((i % 64) / 8 + (i / 64) / 8) % 2 == 0;
+        let (r, g, b) = if dark {(color.r / 2, color.g / 2, color.b / 2)} else {(color.r, color.g, color.b)};
+        [r, g, b, 255]
+    }).collect();
+
+    LoadedTexture::new(64, 64, pixels)
+}
+
+/// Renders every scene at every resolution, flat and textured, through render_view_canvas() into a FrameBuffer.
+/// `cargo bench-raycaster` runs the game with --bench in release mode, a word after it only runs the cases containing it.
+pub fn run(filter: Option<&str>) -> Result<(), String> {
+    println!("{:<10} {:>10} {:<9} {:>14} {:>12} {:>10} {:>8}", "scene", "size", "mode", "ns/frame", "ns/column", "ns/pixel", "frames");
+
+    for scene in scenes() {
+        let mut game_data = GameData::new(false, 0, 0, scene.map);
+        game_data.textures = [Color::RED, Color::GREEN, Color::BLUE, Color::GRAY, Color::CYAN].into_iter().map(bench_texture).collect();
+
+        let cam = Vec2::new(scene.dir.y * 0.66, -scene.dir.x * 0.66);
+        let player = PlayerData::new(scene.pos, scene.dir, cam);
+
+        for (width, height) in RESOLUTIONS {
+            for textured in [false, true] {
+                let mode = if textured {"textured"} else {"flat"};
+                let case = format!("{} {}x{} {}", scene.name, width, height, mode);
+                if filter.is_some_and(|filter| !case.contains(filter)) {
+                    continue;
+                }
+
+                game_data.update_sizes((width, height));
+                let mut buffer = FrameBuffer::new(width, height);
+
+                for _ in 0..BENCH_WARMUP_FRAMES {
+                    render_view_canvas(&player, &mut buffer, &game_data, textured);
+                }
+
+                // As many frames as fit in BENCH_TIME, the rays are the same every frame so the average is stable.
+                let start = Instant::now();
+                let mut frames = 0;
+                while frames < BENCH_WARMUP_FRAMES || start.elapsed() < Duration::from_secs_f64(BENCH_TIME) {
+                    render_view_canvas(&player, black_box(&mut buffer), &game_data, textured);
+                    frames += 1;
+                }
+
+                let frame = start.elapsed().as_nanos() as f64 / frames as f64;
+                println!(
+                    "{:<10} {:>10} {:<9} {:>14.0} {:>12.1} {:>10.2} {:>8}",
+                    scene.name, format!("{}x{}", width, height), mode, frame, frame / width as f64, frame / (width * height) as f64, frames
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
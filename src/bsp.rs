@@ -0,0 +1,630 @@
+use sdl2::pixels::Color;
+
+use crate::config::{CEILING_COLOR, FLOOR_COLOR, MAX_BSP_DEPTH, NEAR_PLANE, SKY_FLAT, WAD_SCALE};
+use crate::data::{GameData, LoadedTexture, PlayerData, Vec2};
+use crate::framebuffer::FrameBuffer;
+use crate::level::{Level, LevelSector};
+use crate::profiler::{self, Stage};
+use crate::sky::draw_sky_column;
+use crate::sprite::draw_sprites;
+
+/// A piece of one side of a linedef, lines get cut into segs wherever a partition line crosses them.
+pub struct Seg {
+    pub a: Vec2<f64>,
+    pub b: Vec2<f64>,
+    /// The sidedef on the front (right) of the seg and the one behind it.
+    pub side: usize,
+    pub back_side: Option<usize>,
+    /// Distance from the start of the side to a, keeps the texture in place after splitting.
+    pub offset: f64
+}
+
+#[derive(Clone, Copy)]
+pub enum Child {
+    Node(usize),
+    Leaf(usize)
+}
+
+/// Partition line a-b, everything to the right of it is in front.
+pub struct Node {
+    pub a: Vec2<f64>,
+    pub b: Vec2<f64>,
+    pub front: Child,
+    pub back: Child
+}
+
+/// A convex piece of a sector, its segs can be drawn in any order.
+pub struct Subsector {
+    pub segs: Vec<Seg>,
+    pub sector: usize
+}
+
+pub struct Bsp {
+    pub nodes: Vec<Node>,
+    pub subsectors: Vec<Subsector>,
+    pub root: Child
+}
+
+enum Placement {
+    Front,
+    Back,
+    /// Crosses the partition at this fraction of the seg.
+    Split(f64)
+}
+
+impl Bsp {
+    /// Node builder, splits the segs recursively until every leaf is convex.
+    pub fn build(level: &Level) -> Self {
+        let mut segs = Vec::new();
+
+        for linedef in &level.linedefs {
+            let start = level.vertices[linedef.start];
+            let end = level.vertices[linedef.end];
+
+            if (end.x - start.x).abs() + (end.y - start.y).abs() < 1e-9 {
+                continue;
+            }
+
+            if let Some(front) = linedef.front {
+                segs.push(Seg { a: start, b: end, side: front, back_side: linedef.back, offset: 0.0 });
+            }
+            if let Some(back) = linedef.back {
+                segs.push(Seg { a: end, b: start, side: back, back_side: linedef.front, offset: 0.0 });
+            }
+        }
+
+        let mut bsp = Bsp { nodes: Vec::new(), subsectors: Vec::new(), root: Child::Leaf(0) };
+        bsp.root = bsp.build_child(segs, level, 0);
+        bsp
+    }
+
+    fn build_child(&mut self, segs: Vec<Seg>, level: &Level, depth: u32) -> Child {
+        let splitter = if depth < MAX_BSP_DEPTH {choose_splitter(&segs)} else {None};
+
+        let Some(splitter) = splitter else {
+            let sector = segs.first().map_or(0, |seg| level.sidedefs[seg.side].sector);
+            self.subsectors.push(Subsector { segs, sector });
+            return Child::Leaf(self.subsectors.len() - 1);
+        };
+
+        let a = segs[splitter].a;
+        let b = segs[splitter].b;
+
+        let mut front = Vec::new();
+        let mut back = Vec::new();
+
+        for seg in segs {
+            match classify(&seg, &a, &b) {
+                Placement::Front => front.push(seg),
+                Placement::Back => back.push(seg),
+                Placement::Split(t) => {
+                    let point = Vec2::new(seg.a.x + (seg.b.x - seg.a.x) * t, seg.a.y + (seg.b.y - seg.a.y) * t);
+                    let first = Seg { a: seg.a, b: point, side: seg.side, back_side: seg.back_side, offset: seg.offset };
+                    let second = Seg { a: point, b: seg.b, side: seg.side, back_side: seg.back_side, offset: seg.offset + length(&seg.a, &point) };
+
+                    if side(&a, &b, &seg.a) < 0.0 {
+                        front.push(first);
+                        back.push(second);
+                    } else {
+                        back.push(first);
+                        front.push(second);
+                    }
+                }
+            }
+        }
+
+        // Reserve the node first, the children get added behind it.
+        self.nodes.push(Node { a, b, front: Child::Leaf(0), back: Child::Leaf(0) });
+        let index = self.nodes.len() - 1;
+
+        self.nodes[index].front = self.build_child(front, level, depth + 1);
+        self.nodes[index].back = self.build_child(back, level, depth + 1);
+
+        Child::Node(index)
+    }
+
+    /// The subsector the position is in.
+    pub fn locate(&self, pos: &Vec2<f64>) -> &Subsector {
+        let mut child = self.root;
+
+        loop {
+            match child {
+                Child::Leaf(index) => return &self.subsectors[index],
+                Child::Node(index) => {
+                    let node = &self.nodes[index];
+                    child = if side(&node.a, &node.b, pos) <= 0.0 {node.front} else {node.back};
+                }
+            }
+        }
+    }
+}
+
+/// Picks the seg that splits the fewest others while keeping both sides balanced.
+/// None if all segs face each other, in that case they are convex.
+fn choose_splitter(segs: &[Seg]) -> Option<usize> {
+    // Big maps only try a sample of the segs, all of them if the sample couldn't split anything.
+    let stride = (segs.len() / 64).max(1);
+
+    let best = best_splitter(segs, stride);
+    if best.is_none() && stride > 1 {
+        return best_splitter(segs, 1);
+    }
+
+    best
+}
+
+fn best_splitter(segs: &[Seg], stride: usize) -> Option<usize> {
+    let mut best = None;
+    let mut best_cost = i32::MAX;
+
+    for (index, candidate) in segs.iter().enumerate().step_by(stride) {
+        let (mut front, mut back, mut splits) = (0i32, 0i32, 0i32);
+
+        for seg in segs {
+            match classify(seg, &candidate.a, &candidate.b) {
+                Placement::Front => front += 1,
+                Placement::Back => back += 1,
+                Placement::Split(_) => splits += 1
+            }
+        }
+
+        // Doesn't separate anything.
+        if back == 0 && splits == 0 {
+            continue;
+        }
+
+        let cost = splits * 8 + (front - back).abs();
+        if cost < best_cost {
+            best_cost = cost;
+            best = Some(index);
+        }
+    }
+
+    best
+}
+
+fn classify(seg: &Seg, a: &Vec2<f64>, b: &Vec2<f64>) -> Placement {
+    // Distances to the partition line, so the epsilon doesn't depend on the length of the splitter.
+    let partition_length = length(a, b);
+    let side_a = side(a, b, &seg.a) / partition_length;
+    let side_b = side(a, b, &seg.b) / partition_length;
+    let epsilon = 1e-6;
+
+    if side_a.abs() < epsilon && side_b.abs() < epsilon {
+        // On the partition line, the direction decides which way it faces.
+        let same_direction = (seg.b.x - seg.a.x) * (b.x - a.x) + (seg.b.y - seg.a.y) * (b.y - a.y) > 0.0;
+        return if same_direction {Placement::Front} else {Placement::Back};
+    }
+
+    if side_a < epsilon && side_b < epsilon {
+        Placement::Front
+    } else if side_a > -epsilon && side_b > -epsilon {
+        Placement::Back
+    } else {
+        Placement::Split(side_a / (side_a - side_b))
+    }
+}
+
+/// >0 if the point is to the left of a-b, <0 if it's to the right (in front).
+fn side(a: &Vec2<f64>, b: &Vec2<f64>, pos: &Vec2<f64>) -> f64 {
+    (b.x - a.x) * (pos.y - a.y) - (b.y - a.y) * (pos.x - a.x)
+}
+
+fn length(a: &Vec2<f64>, b: &Vec2<f64>) -> f64 {
+    ((b.x - a.x).powi(2) + (b.y - a.y).powi(2)).sqrt()
+}
+
+/// Column ranges that are completely covered by solid walls, sorted and inclusive.
+struct SolidSegs(Vec<(i32, i32)>);
+
+impl SolidSegs {
+    /// The parts of x0..=x1 that aren't covered yet.
+    fn visible(&self, x0: i32, x1: i32) -> Vec<(i32, i32)> {
+        let mut spans = Vec::new();
+        let mut start = x0;
+
+        for &(solid_start, solid_end) in &self.0 {
+            if solid_end < start {
+                continue;
+            }
+            if solid_start > x1 {
+                break;
+            }
+            if solid_start > start {
+                spans.push((start, solid_start - 1));
+            }
+            start = solid_end + 1;
+        }
+
+        if start <= x1 {
+            spans.push((start, x1));
+        }
+
+        spans
+    }
+
+    fn add(&mut self, x0: i32, x1: i32) {
+        self.0.push((x0, x1));
+        self.0.sort_unstable();
+
+        let mut merged: Vec<(i32, i32)> = Vec::with_capacity(self.0.len());
+        for &(start, end) in &self.0 {
+            match merged.last_mut() {
+                Some(last) if start <= last.1 + 1 => last.1 = last.1.max(end),
+                _ => merged.push((start, end))
+            }
+        }
+
+        self.0 = merged;
+    }
+
+    fn is_full(&self, width: i32) -> bool {
+        self.0.first().is_some_and(|&(start, end)| start <= 0 && end >= width - 1)
+    }
+}
+
+/// A floor or ceiling area with the same height, flat and light, collected while drawing the walls.
+struct Visplane<'a> {
+    height: f64,
+    flat: &'a str,
+    light: u8,
+    top: Vec<i32>,
+    bottom: Vec<i32>
+}
+
+/// Everything that's needed while drawing a single frame.
+struct BspView<'a> {
+    game_data: &'a GameData,
+    level: &'a Level,
+    player: &'a PlayerData,
+    textured: bool,
+    width: i32,
+    height: i32,
+    horizon: f64,
+    eye: f64,
+    inv_det: f64,
+    solid: SolidSegs,
+    y_top: Vec<i32>,
+    y_bottom: Vec<i32>,
+    planes: Vec<Visplane<'a>>
+}
+
+/// A wall end point in camera space, z is the depth and u the distance along the seg.
+struct ViewPoint {
+    x: f64,
+    z: f64,
+    u: f64
+}
+
+/// Classic Doom style renderer, walks the BSP front to back and stops once every column is covered.
+pub fn render_bsp(player: &PlayerData, buffer: &mut FrameBuffer, game_data: &GameData, textured: bool) {
+    profiler::scope!(Stage::RayCasting);
+    let level = &game_data.level;
+    if level.sectors.is_empty() {
+        return;
+    }
+
+    let width = buffer.width as i32;
+    let height = buffer.height as i32;
+
+    let mut view = BspView {
+        game_data,
+        level,
+        player,
+        textured,
+        width,
+        height,
+        horizon: (height / 2 - player.ver) as f64,
+        eye: player.eye(),
+        inv_det: 1.0 / (player.cam.x * player.dir.y - player.dir.x * player.cam.y),
+        solid: SolidSegs(Vec::new()),
+        y_top: vec![0; width as usize],
+        y_bottom: vec![height - 1; width as usize],
+        planes: Vec::new()
+    };
+
+    view.render_child(buffer, game_data.bsp.root);
+    view.draw_planes(buffer);
+
+    draw_sprites(player, game_data, (width, height), |x, y, depth, color| buffer.set_pixel_in_front(x, y, depth, color));
+}
+
+impl<'a> BspView<'a> {
+    fn render_child(&mut self, buffer: &mut FrameBuffer, child: Child) {
+        if self.solid.is_full(self.width) {
+            return;
+        }
+
+        match child {
+            Child::Leaf(index) => {
+                for seg in &self.game_data.bsp.subsectors[index].segs {
+                    self.render_seg(buffer, seg);
+                }
+            },
+            Child::Node(index) => {
+                let node = &self.game_data.bsp.nodes[index];
+
+                // The side the player is on is closer, so it gets drawn first.
+                if side(&node.a, &node.b, &self.player.pos) <= 0.0 {
+                    self.render_child(buffer, node.front);
+                    self.render_child(buffer, node.back);
+                } else {
+                    self.render_child(buffer, node.back);
+                    self.render_child(buffer, node.front);
+                }
+            }
+        }
+    }
+
+    fn to_view(&self, p: &Vec2<f64>, u: f64) -> ViewPoint {
+        let rel_x = p.x - self.player.pos.x;
+        let rel_y = p.y - self.player.pos.y;
+        let dir = &self.player.dir;
+        let cam = &self.player.cam;
+
+        ViewPoint {
+            x: self.inv_det * (dir.y * rel_x - dir.x * rel_y),
+            z: self.inv_det * (-cam.y * rel_x + cam.x * rel_y),
+            u
+        }
+    }
+
+    fn render_seg(&mut self, buffer: &mut FrameBuffer, seg: &Seg) {
+        // Segs are only visible from the front.
+        if side(&seg.a, &seg.b, &self.player.pos) >= 0.0 {
+            return;
+        }
+
+        let mut a = self.to_view(&seg.a, 0.0);
+        let mut b = self.to_view(&seg.b, length(&seg.a, &seg.b));
+
+        if a.z < NEAR_PLANE && b.z < NEAR_PLANE {
+            return;
+        }
+
+        if a.z < NEAR_PLANE || b.z < NEAR_PLANE {
+            let t = (NEAR_PLANE - a.z) / (b.z - a.z);
+            let clipped = ViewPoint {
+                x: a.x + (b.x - a.x) * t,
+                z: NEAR_PLANE,
+                u: a.u + (b.u - a.u) * t
+            };
+
+            if a.z < NEAR_PLANE {
+                a = clipped;
+            } else {
+                b = clipped;
+            }
+        }
+
+        let half_width = self.width as f64 / 2.0;
+        let mut screen_a = half_width * (1.0 + a.x / a.z);
+        let mut screen_b = half_width * (1.0 + b.x / b.z);
+        if screen_a > screen_b {
+            std::mem::swap(&mut a, &mut b);
+            std::mem::swap(&mut screen_a, &mut screen_b);
+        }
+
+        let x_begin = (screen_a.ceil() as i32).max(0);
+        let x_end = (screen_b.ceil() as i32 - 1).min(self.width - 1);
+        if x_begin > x_end {
+            return;
+        }
+
+        let level = self.level;
+        let sidedef = &level.sidedefs[seg.side];
+        let front = &level.sectors[sidedef.sector];
+        let back = seg.back_side.map(|back| &level.sectors[level.sidedefs[back].sector]);
+
+        // Closed doors and the like block the view just like one-sided lines.
+        let solid = back.is_none_or(|back| back.ceiling <= front.floor || back.floor >= front.ceiling);
+        let x_side = (seg.b.x - seg.a.x).abs() < (seg.b.y - seg.a.y).abs();
+
+        // Between two sky sectors the sky comes down to the lower ceiling instead of showing an upper wall.
+        let front_ceiling = match back {
+            Some(back) if front.ceiling_flat == SKY_FLAT && back.ceiling_flat == SKY_FLAT => back.ceiling,
+            _ => front.ceiling
+        };
+
+        for (span_start, span_end) in self.solid.visible(x_begin, x_end) {
+            let ceiling_plane = (front.ceiling > self.eye)
+                .then(|| self.find_plane(front.ceiling, &front.ceiling_flat, front.light, span_start, span_end));
+            let floor_plane = (front.floor < self.eye)
+                .then(|| self.find_plane(front.floor, &front.floor_flat, front.light, span_start, span_end));
+
+            for x in span_start..=span_end {
+                let column = x as usize;
+                let top = self.y_top[column];
+                let bottom = self.y_bottom[column];
+                if top > bottom {
+                    continue;
+                }
+
+                // Perspective correct interpolation of the depth and texture coordinate.
+                let t = ((x as f64 - screen_a) / (screen_b - screen_a)).clamp(0.0, 1.0);
+                let z = 1.0 / ((1.0 / a.z) + ((1.0 / b.z) - (1.0 / a.z)) * t);
+                let u = ((a.u / a.z) + ((b.u / b.z) - (a.u / a.z)) * t) * z;
+
+                let scale = self.height as f64 / z;
+                let project = |world_y: f64| (self.horizon - (world_y - self.eye) * scale) as i32;
+
+                let ceiling_y = project(front_ceiling).max(top).min(bottom + 1);
+                let floor_y = project(front.floor).max(top - 1).min(bottom);
+
+                if let Some(plane) = ceiling_plane {
+                    self.planes[plane].top[column] = top;
+                    self.planes[plane].bottom[column] = ceiling_y - 1;
+                }
+                if let Some(plane) = floor_plane {
+                    self.planes[plane].top[column] = floor_y + 1;
+                    self.planes[plane].bottom[column] = bottom;
+                }
+
+                let column_wall = ColumnWall {
+                    x,
+                    u: seg.offset + u + sidedef.x_offset,
+                    y_offset: sidedef.y_offset,
+                    scale,
+                    light: front.light,
+                    x_side,
+                    z
+                };
+
+                match back {
+                    None => {
+                        self.draw_wall(buffer, &column_wall, &sidedef.middle, front.ceiling, (ceiling_y, floor_y), Color::RED);
+                        self.y_top[column] = self.height;
+                        self.y_bottom[column] = -1;
+                    },
+                    Some(back) => {
+                        let mut new_top = ceiling_y;
+                        let mut new_bottom = floor_y;
+
+                        if back.ceiling < front_ceiling {
+                            let back_ceiling_y = project(back.ceiling).max(top).min(bottom + 1);
+                            self.draw_wall(buffer, &column_wall, &sidedef.upper, front.ceiling, (ceiling_y, back_ceiling_y - 1), Color::GREEN);
+                            new_top = new_top.max(back_ceiling_y);
+                        }
+
+                        if back.floor > front.floor {
+                            let back_floor_y = project(back.floor).max(top - 1).min(bottom);
+                            self.draw_wall(buffer, &column_wall, &sidedef.lower, back.floor, (back_floor_y + 1, floor_y), Color::BLUE);
+                            new_bottom = new_bottom.min(back_floor_y);
+                        }
+
+                        self.y_top[column] = new_top;
+                        self.y_bottom[column] = new_bottom;
+                    }
+                }
+            }
+
+            if solid {
+                self.solid.add(span_start, span_end);
+            }
+        }
+    }
+
+    /// Finds a visplane that still has the columns free, or starts a new one.
+    fn find_plane(&mut self, height: f64, flat: &'a str, light: u8, x0: i32, x1: i32) -> usize {
+        let free = |plane: &Visplane| (x0..=x1).all(|x| plane.top[x as usize] > plane.bottom[x as usize]);
+
+        if let Some(index) = self.planes.iter().position(|plane| {
+            plane.height == height && plane.flat == flat && plane.light == light && free(plane)
+        }) {
+            return index;
+        }
+
+        self.planes.push(Visplane {
+            height,
+            flat,
+            light,
+            top: vec![i32::MAX; self.width as usize],
+            bottom: vec![i32::MIN; self.width as usize]
+        });
+
+        self.planes.len() - 1
+    }
+
+    /// Draws one column of a wall texture, `anchor` is the height the top of the texture is pinned to.
+    fn draw_wall(&self, buffer: &mut FrameBuffer, column: &ColumnWall, texture: &str, anchor: f64, (draw_start, draw_end): (i32, i32), flat_color: Color) {
+        profiler::accumulate!(Stage::Walls);
+        let light = column.light as f64 / 255.0 * if column.x_side {0.5} else {1.0};
+        buffer.set_depth(column.x, draw_start, draw_end, column.z);
+
+        let texture = match self.game_data.level_textures.get(texture) {
+            Some(texture) if self.textured => texture,
+            _ => {
+                buffer.ver_line(column.x, draw_start, draw_end, shade(flat_color, light));
+                return;
+            }
+        };
+
+        let tex_x = (column.u / WAD_SCALE).rem_euclid(texture.width as f64) as usize;
+
+        for y in draw_start..=draw_end {
+            let world_y = self.eye + (self.horizon - y as f64) / column.scale;
+            let tex_y = ((anchor - world_y + column.y_offset) / WAD_SCALE).rem_euclid(texture.height as f64) as usize;
+
+            buffer.set_pixel(column.x, y, shade(texel(texture, tex_x, tex_y), light));
+        }
+    }
+
+    /// Floors and ceilings, every pixel gets projected back onto the plane to find the spot on the flat.
+    fn draw_planes(&self, buffer: &mut FrameBuffer) {
+        profiler::scope!(Stage::Floors);
+        let dir = &self.player.dir;
+        let cam = &self.player.cam;
+
+        for plane in &self.planes {
+            if let Some(sky) = self.game_data.sky.as_ref().filter(|_| plane.flat == SKY_FLAT) {
+                for x in 0..self.width {
+                    draw_sky_column(buffer, sky, self.player, x, plane.top[x as usize], plane.bottom[x as usize]);
+                }
+                continue;
+            }
+
+            let flat = self.game_data.level_flats.get(plane.flat).filter(|_| self.textured);
+            let fallback = if plane.height > self.eye {CEILING_COLOR} else {FLOOR_COLOR};
+            let light = plane.light as f64 / 255.0;
+
+            for x in 0..self.width {
+                let column = x as usize;
+                if plane.top[column] > plane.bottom[column] {
+                    continue;
+                }
+
+                let camera_x = 2.0 * (x as f64) / (self.width as f64) - 1.0;
+                let ray_x = dir.x + cam.x * camera_x;
+                let ray_y = dir.y + cam.y * camera_x;
+
+                for y in plane.top[column]..=plane.bottom[column] {
+                    let color = match flat {
+                        None => fallback,
+                        Some(flat) => {
+                            // Depth of the row, the same projection as for the walls solved for z.
+                            let z = (plane.height - self.eye) * self.height as f64 / (self.horizon - y as f64 - 0.5);
+                            let world_x = self.player.pos.x + ray_x * z;
+                            let world_y = self.player.pos.y + ray_y * z;
+
+                            let tex_x = (world_x / WAD_SCALE).rem_euclid(flat.width as f64) as usize;
+                            let tex_y = (world_y / WAD_SCALE).rem_euclid(flat.height as f64) as usize;
+                            texel(flat, tex_x, tex_y)
+                        }
+                    };
+
+                    buffer.set_pixel(x, y, shade(color, light));
+                }
+            }
+        }
+    }
+}
+
+/// The part of a seg that falls into a single screen column.
+struct ColumnWall {
+    x: i32,
+    u: f64,
+    y_offset: f64,
+    scale: f64,
+    light: u8,
+    x_side: bool,
+    z: f64
+}
+
+fn texel(texture: &LoadedTexture, x: usize, y: usize) -> Color {
+    let (r, g, b, a) = texture.get_pixel(x, y).unwrap_or((255, 255, 255, 255));
+    Color::RGBA(r, g, b, a)
+}
+
+fn shade(color: Color, light: f64) -> Color {
+    Color::RGBA(
+        (color.r as f64 * light) as u8,
+        (color.g as f64 * light) as u8,
+        (color.b as f64 * light) as u8,
+        color.a
+    )
+}
+
+/// The sector the position is in, for collision and eye height.
+pub fn sector_at<'a>(game_data: &'a GameData, pos: &Vec2<f64>) -> &'a LevelSector {
+    &game_data.level.sectors[game_data.bsp.locate(pos).sector]
+}
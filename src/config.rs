@@ -1,16 +1,199 @@
+use sdl2::pixels::Color;
+
+use crate::data::Vec2;
+use crate::level::Thing;
+use crate::map::Segment;
+
 pub const WIDTH: u32 = 800;
 pub const HEIGHT: u32 = 600;
 
+/// Optional settings file in the working directory, the format is described in settings.rs.
+pub const SETTINGS_FILE: &str = "settings.cfg";
+
+/// Save games go to <SAVE_DIR>/<slot>.sav, saves of another version don't get loaded.
+pub const SAVE_DIR: &str = "saves";
+pub const SAVE_VERSION: u32 = 1;
+pub const QUICKSAVE_SLOT: &str = "quick";
+
+/// Optional console commands that run at startup, one per line.
+pub const AUTOEXEC_FILE: &str = "autoexec.cfg";
+
+// Console: lines of output it keeps and the range the fov command allows (degrees).
+pub const CONSOLE_LINES: usize = 100;
+pub const MIN_FOV: f64 = 30.0;
+pub const MAX_FOV: f64 = 150.0;
+/// Degrees, the camera plane of 0.66 the renderer started out with.
+pub const DEFAULT_FOV: f64 = 66.8;
+
+// Menus: the numbered save slots and the window sizes the options go through.
+pub const MENU_SLOTS: u32 = 5;
+pub const WINDOW_SIZES: [(u32, u32); 5] = [(640, 480), (800, 600), (1024, 768), (1280, 720), (1920, 1080)];
+
+/// Frames the profiler overlay keeps the times of.
+pub const PROFILE_FRAMES: usize = 120;
+
+// Benchmarks: frames rendered before measuring (also the least that get measured) and seconds per case.
+pub const BENCH_WARMUP_FRAMES: u32 = 3;
+pub const BENCH_TIME: f64 = 0.5;
+
+// Audio: samples per second and per callback, how many sounds play at once and the default volume (0.0 - 1.0).
+pub const MIX_RATE: i32 = 44100;
+pub const MIX_SAMPLES: u16 = 1024;
+pub const MAX_CHANNELS: usize = 8;
+pub const SOUND_VOLUME: f64 = 0.8;
+// Sounds are at full volume up to SOUND_CLOSE_DIST and can't be heard from SOUND_CLIP_DIST on (grid units),
+// how far they move to one side (0.0 - 1.0) and the volume left of a sound behind a wall.
+pub const SOUND_CLOSE_DIST: f64 = 3.0;
+pub const SOUND_CLIP_DIST: f64 = 19.0;
+pub const SOUND_SEPARATION: f64 = 0.75;
+pub const OCCLUDED_VOLUME: f64 = 0.4;
+pub const MUSIC_VOLUME: f64 = 0.6;
+/// Seconds the old song takes to fade out while the new one fades in.
+pub const MUSIC_FADE_TIME: f32 = 2.0;
+/// Notes the MIDI synth plays at once, the oldest one stops for a new one.
+pub const MAX_VOICES: usize = 32;
+
+// Mouse defaults, radians per pixel, pitch pixels per pixel and the smoothing (0.0 = off).
+pub const MOUSE_SENSITIVITY_X: f64 = 0.003;
+pub const MOUSE_SENSITIVITY_Y: f64 = 1.0;
+pub const MOUSE_SMOOTHING: f64 = 0.0;
+
+// Turning (radians per second) and looking up/down (pixels per second) with the keyboard.
+pub const KEY_TURN_SPEED: f64 = 2.5;
+pub const KEY_LOOK_SPEED: f64 = 300.0;
+
+// Controller defaults, the dead zone, right stick exponent and full speed turning/looking.
+pub const PAD_DEAD_ZONE: f64 = 0.2;
+pub const PAD_RESPONSE_CURVE: f64 = 2.0;
+pub const PAD_TURN_SPEED: f64 = 3.0;
+pub const PAD_LOOK_SPEED: f64 = 400.0;
+/// How far a trigger has to be pulled to count as pressed.
+pub const PAD_TRIGGER_THRESHOLD: f64 = 0.5;
+
+/// Pitch limits, how far the horizon can move when looking up and down as a fraction of half the view height.
+/// Below 1.0 the horizon stays on the screen whatever size the window is.
+pub const MAX_PITCH_UP: f64 = 0.67;
+pub const MAX_PITCH_DOWN: f64 = 0.67;
+
+/// How close the player can get to thin walls and other segments.
+pub const PLAYER_RADIUS: f64 = 0.2;
+
+/// Height of the camera above the floor, walls are 1.0 high.
+pub const EYE_HEIGHT: f64 = 0.5;
+
+/// Highest step the player can walk up and the room they need above their feet.
+pub const MAX_STEP_HEIGHT: f64 = 0.375;
+pub const PLAYER_HEIGHT: f64 = 0.875;
+
+// Jumping and crouching, in units and seconds. A jump goes JUMP_SPEED²/(2*GRAVITY) high.
+pub const GRAVITY: f64 = 10.0;
+pub const JUMP_SPEED: f64 = 2.4;
+pub const CROUCH_EYE_HEIGHT: f64 = 0.25;
+pub const CROUCH_SPEED: f64 = 2.0;
+pub const CROUCH_MOVE_FACTOR: f64 = 0.5;
+/// How far the eye stays below the ceiling.
+pub const HEAD_ROOM: f64 = 0.1;
+
+// Head-bob while walking, the height and radians per second.
+pub const BOB_HEIGHT: f64 = 0.02;
+pub const BOB_SPEED: f64 = 10.0;
+
+/// Doom map units per grid cell.
+pub const WAD_SCALE: f64 = 1.0 / 64.0;
+
+// Sector renderer.
+pub const NEAR_PLANE: f64 = 0.01;
+pub const MAX_SECTOR_VISITS: u32 = 32;
+pub const CEILING_COLOR: Color = Color::RGB(40, 40, 40);
+pub const FLOOR_COLOR: Color = Color::RGB(80, 80, 80);
+
+// BSP renderer, the flats used when the grid gets turned into a level.
+pub const MAX_BSP_DEPTH: u32 = 256;
+pub const GRID_FLOOR_FLAT: &str = "greystone";
+pub const GRID_CEILING_FLAT: &str = "wood";
+
+// Sky, ceilings with this flat show the sky (same name as in Doom), the texture reaches this many screen heights above the horizon.
+pub const SKY_FLAT: &str = "F_SKY1";
+pub const SKY_HEIGHT: f64 = 1.0;
+/// The sky of WORLD_MAP, pics/<name>.png. None keeps the ceiling closed.
+pub const WORLD_MAP_SKY: Option<&str> = Some("sky");
+
+/// Entities updates per second, the same as Doom.
+pub const TICK_RATE: f64 = 35.0;
+
+// Level triggers, distances in grid units.
+/// How far away the player can use a switch.
+pub const USE_RANGE: f64 = 64.0 * WAD_SCALE;
+/// Grid units per second doors, floors and ceilings move, Doom's 2 map units per tick.
+pub const PLANE_SPEED: f64 = 2.0 * WAD_SCALE * TICK_RATE;
+/// An open door stops this far below the lowest ceiling around it.
+pub const DOOR_LIP: f64 = 4.0 * WAD_SCALE;
+/// The maps exits lead through, one per line: .lvl files or maps of the WAD.
+pub const EPISODE_FILE: &str = "levels/episode.txt";
+
+// Monsters, distances in grid units.
+/// Seed of the random numbers, --seed on the command line overrides it.
+pub const DEFAULT_SEED: u64 = 0x2545_f491_4f6c_dd1d;
+pub const PLAYER_HEALTH: i32 = 100;
+pub const MAX_AMMO: u32 = 200;
+/// Ticks between looking for a new path to the player.
+pub const REPATH_TICKS: u32 = 17;
+/// Even monsters looking the other way notice the player this close.
+pub const MONSTER_SENSE_DISTANCE: f64 = 1.5;
+pub const MELEE_RANGE: f64 = 64.0 * WAD_SCALE;
+
+// HUD, it gets scaled up by whole pixels from this height.
+pub const HUD_BASE_HEIGHT: u32 = 200;
+pub const DAMAGE_FLASH_TICKS: u32 = 30;
+pub const PICKUP_FLASH_TICKS: u32 = 6;
+/// How long a message stays at the top of the screen.
+pub const MESSAGE_TICKS: u32 = 4 * TICK_RATE as u32;
+/// How far the weapon swings with the head-bob, in HUD pixels.
+pub const WEAPON_BOB: f64 = 6.0;
+
+/// The things placed in WORLD_MAP, see entity::THING_TYPES for the kinds.
+pub const WORLD_MAP_THINGS: [Thing; 9] = [
+    Thing { pos: Vec2::new(2.5, 7.5), angle: 0.0, kind: 2035, flags: 0 },
+    Thing { pos: Vec2::new(1.5, 8.5), angle: 0.0, kind: 2035, flags: 0 },
+    Thing { pos: Vec2::new(3.5, 2.5), angle: 0.0, kind: 30, flags: 0 },
+    Thing { pos: Vec2::new(7.5, 1.5), angle: 0.0, kind: 2028, flags: 0 },
+    Thing { pos: Vec2::new(7.5, 7.5), angle: 3.93, kind: 3004, flags: 0 },
+    Thing { pos: Vec2::new(5.5, 8.5), angle: 4.71, kind: 3001, flags: 0 },
+    Thing { pos: Vec2::new(1.5, 4.5), angle: 0.0, kind: 2011, flags: 0 },
+    Thing { pos: Vec2::new(4.5, 1.5), angle: 0.0, kind: 2018, flags: 0 },
+    Thing { pos: Vec2::new(4.5, 4.5), angle: 0.0, kind: 2008, flags: 0 }
+];
+
 pub const WORLD_MAP: [[i32; 10]; 10] = [
     [1, 1, 1, 1, 1, 1, 1, 1, 1, 1],
     [1, 0, 0, 0, 0, 0, 0, 0, 0, 1],
     [1, 0, 0, 0, 0, 0, 0, 0, 0, 1],
-    [1, 0, 0, 0, 0, 0, 0, 0, 0, 1],
+    [1, 0, 0, 0, 0, 0, 0, 8, 0, 1],
     [1, 0, 0, 0, 0, 0, 0, 2, 2, 1],
     [1, 0, 4, 4, 0, 0, 0, 0, 0, 1],
     [1, 0, 4, 4, 0, 0, 3, 3, 3, 1],
     [1, 0, 0, 0, 0, 0, 0, 0, 0, 1],
-    [1, 0, 0, 0, 0, 0, 0, 0, 0, 1],
+    [1, 6, 0, 5, 5, 5, 0, 0, 7, 1],
     [1, 1, 1, 1, 1, 1, 1, 1, 1, 1]
 ];
 
+/// Tiles made out of segments instead of a full block, the wall decides the color/texture.
+pub const TILE_SEGMENTS: [(i32, &[Segment]); 4] = [
+    // Thin wall on the edge of the cell.
+    (5, &[Segment::new(Vec2::new(0.0, 0.0), Vec2::new(0.0, 1.0), 1)]),
+    // Diagonals.
+    (6, &[Segment::new(Vec2::new(0.0, 0.0), Vec2::new(1.0, 1.0), 2)]),
+    (7, &[Segment::new(Vec2::new(0.0, 1.0), Vec2::new(1.0, 0.0), 3)]),
+    // Pillar in the middle of the cell.
+    (8, &[
+        Segment::new(Vec2::new(0.35, 0.35), Vec2::new(0.65, 0.35), 4),
+        Segment::new(Vec2::new(0.65, 0.35), Vec2::new(0.65, 0.65), 4),
+        Segment::new(Vec2::new(0.65, 0.65), Vec2::new(0.35, 0.65), 4),
+        Segment::new(Vec2::new(0.35, 0.65), Vec2::new(0.35, 0.35), 4)
+    ])
+];
+
+// Palette mode, distance at which everything has faded into the fog.
+pub const LIGHT_LEVELS: usize = 32;
+pub const FOG_DISTANCE: f64 = 16.0;
+pub const FOG_COLOR: Color = Color::RGB(0, 0, 0);
@@ -0,0 +1,448 @@
+use std::fs;
+use std::path::Path;
+
+use sdl2::keyboard::Scancode;
+use sdl2::pixels::Color;
+
+use crate::audio::Sound;
+use crate::config::{CONSOLE_LINES, HUD_BASE_HEIGHT, MAX_AMMO, MAX_FOV, MESSAGE_TICKS, MIN_FOV, PLAYER_HEALTH};
+use crate::data::{GameData, PlayerData, Vec2};
+use crate::font::{Font, TextStyle};
+use crate::framebuffer::FrameBuffer;
+use crate::input::Action;
+use crate::music::Song;
+use crate::profiler;
+use crate::save;
+use crate::wad::Wad;
+
+const BACKGROUND: Color = Color::RGBA(0, 0, 0, 200);
+const EDGE: Color = Color::RGB(200, 30, 30);
+const TEXT_COLOR: Color = Color::RGB(220, 220, 220);
+
+type Run = fn(args: &[&str], player: &mut PlayerData, game_data: &mut GameData) -> Result<(), String>;
+
+pub struct Command {
+    pub name: &'static str,
+    pub usage: &'static str,
+    pub run: Run
+}
+
+pub const COMMANDS: [Command; 15] = [
+    Command { name: "help", usage: "help - lists the commands and cvars", run: help },
+    Command { name: "map", usage: "map <name> - loads a map out of the WAD, or a .lvl file", run: map },
+    Command { name: "noclip", usage: "noclip - walk through walls", run: noclip },
+    Command { name: "god", usage: "god - no more damage", run: god },
+    Command { name: "fov", usage: "fov [degrees] - shows or sets the field of view", run: fov },
+    Command { name: "screenshot", usage: "screenshot [file] - saves the next frame as a PNG", run: screenshot },
+    Command { name: "teleport", usage: "teleport <x> <y> - moves the player", run: teleport },
+    Command { name: "give", usage: "give [all|health|armor|ammo]", run: give },
+    Command { name: "save", usage: "save <slot> - saves the game, F6 saves to the quick slot", run: save },
+    Command { name: "load", usage: "load <slot> - loads a saved game, F9 loads the quick slot", run: load },
+    Command { name: "exec", usage: "exec <file> - runs the commands in a file", run: exec },
+    Command { name: "clear", usage: "clear - empties the console", run: clear },
+    Command { name: "sound", usage: "sound [name] - plays a sound, without a name lists them", run: sound },
+    Command { name: "music", usage: "music [file|lump] - crossfades to a song, without one the music fades out", run: music },
+    Command { name: "profile_trace", usage: "profile_trace [frames] [file] - writes a Chrome trace of the next frames", run: profile_trace }
+];
+
+/// A value that can be read and changed from the console, it stays in GameData where the game uses it.
+pub struct Cvar {
+    pub name: &'static str,
+    pub get: fn(game_data: &GameData) -> String,
+    pub set: fn(game_data: &mut GameData, value: &str) -> Result<(), String>
+}
+
+pub const CVARS: [Cvar; 13] = [
+    Cvar { name: "r_textured", get: |g| flag(g.textured), set: |g, v| { g.textured = parse_flag(v)?; Ok(()) } },
+    Cvar { name: "r_paletted", get: |g| flag(g.paletted), set: |g, v| {
+        if parse_flag(v)? != g.paletted {
+            g.toggle_palette_mode();
+        }
+        Ok(())
+    } },
+    Cvar { name: "map_view", get: |g| flag(g.map_view), set: |g, v| { g.map_view = parse_flag(v)?; Ok(()) } },
+    Cvar { name: "show_fps", get: |g| flag(g.settings.show_fps), set: |g, v| { g.settings.show_fps = parse_flag(v)?; Ok(()) } },
+    Cvar { name: "show_profiler", get: |g| flag(g.settings.show_profiler), set: |g, v| { g.settings.show_profiler = parse_flag(v)?; Ok(()) } },
+    Cvar { name: "invert_y", get: |g| flag(g.settings.invert_y), set: |g, v| { g.settings.invert_y = parse_flag(v)?; Ok(()) } },
+    Cvar { name: "mouse_sensitivity_x", get: |g| g.settings.mouse_sensitivity_x.to_string(), set: |g, v| { g.settings.mouse_sensitivity_x = parse_number(v)?; Ok(()) } },
+    Cvar { name: "mouse_sensitivity_y", get: |g| g.settings.mouse_sensitivity_y.to_string(), set: |g, v| { g.settings.mouse_sensitivity_y = parse_number(v)?; Ok(()) } },
+    Cvar { name: "mouse_smoothing", get: |g| g.settings.mouse_smoothing.to_string(), set: |g, v| { g.settings.mouse_smoothing = parse_number(v)?.clamp(0.0, 0.99); Ok(()) } },
+    Cvar { name: "pad_dead_zone", get: |g| g.settings.pad_dead_zone.to_string(), set: |g, v| { g.settings.pad_dead_zone = parse_number(v)?.clamp(0.0, 0.95); Ok(()) } },
+    Cvar { name: "sound_volume", get: |g| g.settings.sound_volume.to_string(), set: |g, v| { g.settings.sound_volume = parse_number(v)?.clamp(0.0, 1.0); Ok(()) } },
+    Cvar { name: "sound_occlusion", get: |g| flag(g.settings.sound_occlusion), set: |g, v| { g.settings.sound_occlusion = parse_flag(v)?; Ok(()) } },
+    Cvar { name: "music_volume", get: |g| g.settings.music_volume.to_string(), set: |g, v| { g.settings.music_volume = parse_number(v)?.clamp(0.0, 1.0); Ok(()) } }
+];
+
+fn flag(value: bool) -> String {
+    (value as u8).to_string()
+}
+
+/// 1/0 like Quake, true/false like the settings file.
+fn parse_flag(value: &str) -> Result<bool, String> {
+    match value {
+        "1" | "true" => Ok(true),
+        "0" | "false" => Ok(false),
+        _ => Err(format!("expected 1 or 0, got {}", value))
+    }
+}
+
+fn parse_number(value: &str) -> Result<f64, String> {
+    value.parse().map_err(|_| format!("expected a number, got {}", value))
+}
+
+/// The drop-down console, its text and what has been typed into it.
+#[derive(Default)]
+pub struct Console {
+    pub open: bool,
+    pub input: String,
+    /// Newest line last, at most CONSOLE_LINES.
+    pub output: Vec<String>,
+    pub history: Vec<String>,
+    /// Where Up/Down are in the history, None while typing a new line.
+    pub history_pos: Option<usize>,
+    /// File the main loop saves the next frame to, before the console gets drawn over it.
+    pub screenshot: Option<String>
+}
+
+impl Console {
+    pub fn print(&mut self, text: &str) {
+        self.output.extend(text.lines().map(String::from));
+
+        let excess = self.output.len().saturating_sub(CONSOLE_LINES);
+        self.output.drain(..excess);
+    }
+
+    /// Typed characters, the key that opens the console doesn't end up in the input.
+    pub fn type_text(&mut self, text: &str) {
+        self.input.extend(text.chars().filter(|character| *character != '`' && !character.is_control()));
+    }
+
+    /// Steps through the history, back is towards older lines.
+    fn browse_history(&mut self, back: bool) {
+        let pos = match (self.history_pos, back) {
+            (None, true) => self.history.len().checked_sub(1),
+            (Some(pos), true) => Some(pos.saturating_sub(1)),
+            (Some(pos), false) => Some(pos + 1).filter(|pos| *pos < self.history.len()),
+            (None, false) => None
+        };
+
+        self.history_pos = pos;
+        self.input = pos.map(|pos| self.history[pos].clone()).unwrap_or_default();
+    }
+
+    /// Completes the command or cvar name being typed. With several matches they get listed
+    /// and the input grows to the part they have in common.
+    fn complete(&mut self) {
+        if self.input.is_empty() || self.input.contains(' ') {
+            return;
+        }
+
+        let names = COMMANDS.iter().map(|command| command.name).chain(CVARS.iter().map(|cvar| cvar.name));
+        let matches: Vec<&str> = names.filter(|name| name.starts_with(self.input.as_str())).collect();
+
+        match matches.as_slice() {
+            [] => {},
+            [name] => self.input = format!("{} ", name),
+            [first, ..] => {
+                self.print(&matches.join("  "));
+
+                let common = (self.input.len()..first.len())
+                    .take_while(|&end| matches.iter().all(|name| name.get(..=end) == first.get(..=end)))
+                    .last()
+                    .map_or(self.input.len(), |end| end + 1);
+                self.input = first[..common].to_string();
+            }
+        }
+    }
+
+    /// Pulled down over the top half of the screen, the input line at the bottom and the output above it.
+    pub fn draw(&self, font: &Font, buffer: &mut FrameBuffer) {
+        let scale = (buffer.height / HUD_BASE_HEIGHT).max(1);
+        let width = buffer.width as i32;
+        let height = buffer.height as i32 / 2;
+        let line_height = (font.line_height * scale) as i32;
+        let style = TextStyle::new(TEXT_COLOR, scale);
+
+        buffer.fill_rect(0, 0, width, height, BACKGROUND);
+        buffer.fill_rect(0, height - scale as i32, width, scale as i32, EDGE);
+
+        let margin = 2 * scale as i32;
+        let mut y = height - margin - line_height;
+        font.draw(buffer, &format!("> {}_", self.input), margin, y, &style);
+
+        for line in self.output.iter().rev() {
+            y -= line_height;
+            if y < -line_height {
+                break;
+            }
+
+            font.draw(buffer, line, margin, y, &style);
+        }
+    }
+}
+
+/// Keys while the console is open, the held actions don't see them.
+pub fn key_down(scancode: Scancode, player: &mut PlayerData, game_data: &mut GameData) {
+    let toggle = game_data.settings.bindings.actions(scancode).any(|action| action == Action::ToggleConsole);
+    let console = &mut game_data.console;
+
+    match scancode {
+        Scancode::Return | Scancode::KpEnter => {
+            let line = std::mem::take(&mut console.input);
+            console.history_pos = None;
+            console.print(&format!("> {}", line));
+
+            if !line.trim().is_empty() && console.history.last() != Some(&line) {
+                console.history.push(line.clone());
+            }
+
+            execute(&line, player, game_data);
+        },
+        Scancode::Backspace => {
+            console.input.pop();
+        },
+        Scancode::Up => console.browse_history(true),
+        Scancode::Down => console.browse_history(false),
+        Scancode::Tab => console.complete(),
+        Scancode::Escape => console.open = false,
+        _ if toggle => console.open = false,
+        _ => {}
+    }
+}
+
+/// Runs a line: a command with its arguments, a cvar name to show its value or a cvar and a new value.
+/// Errors end up in the console.
+pub fn execute(line: &str, player: &mut PlayerData, game_data: &mut GameData) {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let Some((&name, args)) = words.split_first() else {
+        return;
+    };
+
+    let result = if let Some(command) = COMMANDS.iter().find(|command| command.name == name) {
+        (command.run)(args, player, game_data)
+    } else if let Some(cvar) = CVARS.iter().find(|cvar| cvar.name == name) {
+        match args {
+            [] => {
+                let value = (cvar.get)(game_data);
+                game_data.console.print(&format!("{} is {}", name, value));
+                Ok(())
+            },
+            [value, ..] => (cvar.set)(game_data, value)
+        }
+    } else {
+        Err(format!("unknown command {}", name))
+    };
+
+    if let Err(error) = result {
+        game_data.console.print(&format!("{}: {}", name, error));
+    }
+}
+
+/// Commands given on the command line, every one starts with a `+`: `+god +fov 100 +map E1M2`.
+pub fn command_line(args: &[String]) -> Vec<String> {
+    let mut commands: Vec<String> = Vec::new();
+    let mut in_command = false;
+
+    for arg in args {
+        if let Some(command) = arg.strip_prefix('+') {
+            commands.push(command.to_string());
+            in_command = true;
+        } else if arg.starts_with("--") {
+            in_command = false;
+        } else if let Some(command) = commands.last_mut().filter(|_| in_command) {
+            command.push(' ');
+            command.push_str(arg);
+        }
+    }
+
+    commands
+}
+
+fn help(_args: &[&str], _player: &mut PlayerData, game_data: &mut GameData) -> Result<(), String> {
+    for command in &COMMANDS {
+        game_data.console.print(command.usage);
+    }
+
+    let cvars: Vec<&str> = CVARS.iter().map(|cvar| cvar.name).collect();
+    game_data.console.print(&format!("cvars: {}", cvars.join(", ")));
+    Ok(())
+}
+
+/// Names ending in .lvl are level files, everything else is a map in the WAD the game was started with.
+fn map(args: &[&str], player: &mut PlayerData, game_data: &mut GameData) -> Result<(), String> {
+    let [name] = args else {
+        return Err("usage: map <name>".to_string());
+    };
+
+    if name.ends_with(".lvl") {
+        game_data.load_level_file(name)?;
+    } else {
+        let wad = game_data.wad_file.clone().ok_or("no WAD loaded, start with --wad <file>")?;
+        game_data.load_wad(&wad, &name.to_uppercase())?;
+    }
+
+    game_data.spawn_entities();
+    if let Some(start) = game_data.level.player_start() {
+        player.place(start.pos, start.angle);
+    }
+
+    Ok(())
+}
+
+fn noclip(_args: &[&str], player: &mut PlayerData, game_data: &mut GameData) -> Result<(), String> {
+    player.noclip = !player.noclip;
+    game_data.console.print(if player.noclip {"noclip on"} else {"noclip off"});
+    Ok(())
+}
+
+fn god(_args: &[&str], player: &mut PlayerData, game_data: &mut GameData) -> Result<(), String> {
+    player.god = !player.god;
+    game_data.console.print(if player.god {"god mode on"} else {"god mode off"});
+    Ok(())
+}
+
+fn fov(args: &[&str], player: &mut PlayerData, game_data: &mut GameData) -> Result<(), String> {
+    match args {
+        [] => game_data.console.print(&format!("fov is {:.0}", player.fov())),
+        [degrees, ..] => {
+            game_data.settings.fov = parse_number(degrees)?.clamp(MIN_FOV, MAX_FOV);
+            player.set_fov(game_data.settings.fov);
+        }
+    }
+
+    Ok(())
+}
+
+/// Without a name the first free screenshotNNN.png is taken.
+fn screenshot(args: &[&str], _player: &mut PlayerData, game_data: &mut GameData) -> Result<(), String> {
+    let filename = match args {
+        [name, ..] => name.to_string(),
+        [] => (0..1000)
+            .map(|number| format!("screenshot{:03}.png", number))
+            .find(|name| !std::path::Path::new(name).exists())
+            .ok_or("no free screenshot name left")?
+    };
+
+    game_data.console.screenshot = Some(filename);
+    Ok(())
+}
+
+fn teleport(args: &[&str], player: &mut PlayerData, _game_data: &mut GameData) -> Result<(), String> {
+    let [x, y] = args else {
+        return Err("usage: teleport <x> <y>".to_string());
+    };
+
+    player.pos = Vec2::new(parse_number(x)?, parse_number(y)?);
+    Ok(())
+}
+
+fn give(args: &[&str], player: &mut PlayerData, _game_data: &mut GameData) -> Result<(), String> {
+    let what = args.first().copied().unwrap_or("all");
+
+    if what == "all" || what == "health" {
+        player.health = PLAYER_HEALTH;
+    }
+    if what == "all" || what == "armor" {
+        player.armor = PLAYER_HEALTH;
+    }
+    if what == "all" || what == "ammo" {
+        player.weapons.ammo = [MAX_AMMO; 3];
+    }
+
+    match what {
+        "all" | "health" | "armor" | "ammo" => Ok(()),
+        _ => Err(format!("can't give {}", what))
+    }
+}
+
+pub(crate) fn save(args: &[&str], player: &mut PlayerData, game_data: &mut GameData) -> Result<(), String> {
+    let [slot] = args else {
+        return Err("usage: save <slot>".to_string());
+    };
+
+    save::save(slot, player, game_data)?;
+    player.message = Some(("Game saved.", MESSAGE_TICKS));
+    game_data.console.print(&format!("saved {}", save::slot_path(slot)?));
+    Ok(())
+}
+
+pub(crate) fn load(args: &[&str], player: &mut PlayerData, game_data: &mut GameData) -> Result<(), String> {
+    let [slot] = args else {
+        return Err("usage: load <slot>".to_string());
+    };
+
+    // A failed quickload shouldn't go unnoticed with the console closed.
+    if let Err(error) = save::load(slot, player, game_data) {
+        player.message = Some(("Loading failed, see the console.", MESSAGE_TICKS));
+        return Err(error);
+    }
+
+    player.message = Some(("Game loaded.", MESSAGE_TICKS));
+    game_data.console.print(&format!("loaded {}", save::slot_path(slot)?));
+    Ok(())
+}
+
+fn sound(args: &[&str], _player: &mut PlayerData, game_data: &mut GameData) -> Result<(), String> {
+    let [name, ..] = args else {
+        let names: Vec<&str> = Sound::ALL.iter().map(Sound::name).collect();
+        game_data.console.print(&names.join(" "));
+        return Ok(());
+    };
+
+    let sound = Sound::from_name(name).ok_or_else(|| format!("unknown sound {}", name))?;
+    game_data.audio.play(sound, None);
+    Ok(())
+}
+
+/// Files first, then lumps of the loaded WAD (D_E1M1, D_RUNNIN, ...).
+fn music(args: &[&str], _player: &mut PlayerData, game_data: &mut GameData) -> Result<(), String> {
+    if !game_data.audio.is_open() {
+        return Err("no sound device".to_string());
+    }
+
+    let song = match args {
+        [] => None,
+        [name] if Path::new(name).exists() => Some(Song::from_file(name)?),
+        [name] => {
+            let wad = game_data.wad_file.as_ref().ok_or_else(|| format!("{} not found", name))?;
+            Some(Song::from_lump(&Wad::from_file(wad)?, &name.to_uppercase())?)
+        },
+        _ => return Err("usage: music [file|lump]".to_string())
+    };
+
+    game_data.audio.music = args.first().map(|name| name.to_string());
+    game_data.audio.play_music(song);
+    Ok(())
+}
+
+/// `#` starts a comment, like in the settings file.
+fn exec(args: &[&str], player: &mut PlayerData, game_data: &mut GameData) -> Result<(), String> {
+    let [filename] = args else {
+        return Err("usage: exec <file>".to_string());
+    };
+
+    let text = fs::read_to_string(filename).map_err(|e| format!("{}: {}", filename, e))?;
+    for line in text.lines() {
+        execute(line.split('#').next().unwrap_or(""), player, game_data);
+    }
+
+    Ok(())
+}
+
+fn clear(_args: &[&str], _player: &mut PlayerData, game_data: &mut GameData) -> Result<(), String> {
+    game_data.console.output.clear();
+    Ok(())
+}
+
+/// 100 frames into trace.json if nothing else is given.
+fn profile_trace(args: &[&str], _player: &mut PlayerData, game_data: &mut GameData) -> Result<(), String> {
+    let frames = match args.first() {
+        Some(frames) => frames.parse().map_err(|_| format!("expected a number of frames, got {}", frames))?,
+        None => 100
+    };
+    let filename = args.get(1).copied().unwrap_or("trace.json");
+
+    profiler::start_trace(frames, filename);
+    game_data.console.print(&format!("tracing {} frames", frames));
+    Ok(())
+}
@@ -1,21 +1,44 @@
+use std::collections::HashMap;
+use std::f64::consts::TAU;
 use std::ops;
 
 use sdl2::rect::Rect;
 use sdl2::surface::Surface;
 use sdl2::image::{LoadSurface, LoadTexture};
 
+use crate::audio::Audio;
+use crate::bsp::{sector_at, Bsp};
+use crate::config::{
+    BOB_HEIGHT, BOB_SPEED, CROUCH_EYE_HEIGHT, CROUCH_SPEED, EYE_HEIGHT, GRAVITY, HEAD_ROOM, JUMP_SPEED, MAX_PITCH_DOWN, MAX_PITCH_UP,
+    PLAYER_HEALTH, WORLD_MAP_THINGS, DEFAULT_SEED, DAMAGE_FLASH_TICKS, PICKUP_FLASH_TICKS, MESSAGE_TICKS, MAX_AMMO,
+    WORLD_MAP, WORLD_MAP_SKY
+};
+use crate::console::Console;
+use crate::entity::{self, spawn_things, Entity, Pickup, THING_TYPES};
+use crate::font::Font;
+use crate::gamepad::Gamepad;
+use crate::level::Level;
+use crate::map::Map;
+use crate::menu::Menu;
+use crate::music::Song;
+use crate::palette::{IndexedTexture, Palette, PaletteData};
+use crate::random::Rng;
+use crate::sector::SectorMap;
+use crate::settings::Settings;
+use crate::sprite::{rotation, Sprites};
+use crate::trigger::{Activation, Mover, TriggerAction};
+use crate::wad::Wad;
+use crate::weapon::{Weapons, SPAWNED_TYPES, WEAPONS};
+
+#[derive(Clone, Copy)]
 pub struct Vec2<T> {
     pub x: T,
     pub y: T
 }
-impl<T> Vec2<T> { pub fn new(x: T, y: T) -> Self {
+impl<T> Vec2<T> {
+    pub const fn new(x: T, y: T) -> Self {
         Vec2 { x, y }
     }
-
-    pub fn set(&mut self, x: T, y: T) {
-        self.x = x;
-        self.y = y;
-    }
 }
 
 impl Vec2<f64> {
@@ -49,61 +72,579 @@ pub struct PlayerData {
     pub pos: Vec2<f64>,
     pub dir: Vec2<f64>,
     pub cam: Vec2<f64>,
-    pub ver: i32
+    /// Pitch as a vertical shear in pixels, the horizon is at height/2 - ver.
+    pub ver: i32,
+    /// Height of the feet and how fast they are moving up, only grounded players can jump.
+    pub z: f64,
+    pub z_speed: f64,
+    pub on_ground: bool,
+    pub crouching: bool,
+    /// Eye above the feet, moves between EYE_HEIGHT and CROUCH_EYE_HEIGHT.
+    pub eye_height: f64,
+    /// Phase of the head-bob, only advances while walking.
+    pub bob: f64,
+    pub health: i32,
+    pub armor: i32,
+    pub weapons: Weapons,
+    /// Ticks the red and the gold screen flash have left.
+    pub damage_flash: u32,
+    pub pickup_flash: u32,
+    /// Text at the top of the screen and the ticks it has left.
+    pub message: Option<(&'static str, u32)>,
+    /// Cheats from the console: walking through walls and not taking damage.
+    pub noclip: bool,
+    pub god: bool,
+    /// Use was pressed, it happens in the next tick like the shots.
+    pub using: bool
 }
 
 impl PlayerData {
     pub fn new(pos: Vec2<f64>, dir: Vec2<f64>, cam: Vec2<f64>) -> PlayerData {
-        PlayerData { pos, dir, cam, ver: 0 }
+        PlayerData {
+            pos,
+            dir,
+            cam,
+            ver: 0,
+            z: 0.0,
+            z_speed: 0.0,
+            on_ground: true,
+            crouching: false,
+            eye_height: EYE_HEIGHT,
+            bob: 0.0,
+            health: PLAYER_HEALTH,
+            armor: 0,
+            weapons: Weapons::default(),
+            damage_flash: 0,
+            pickup_flash: 0,
+            message: None,
+            noclip: false,
+            god: false,
+            using: false
+        }
+    }
+
+    /// Height of the camera in the world, every renderer projects relative to this.
+    pub fn eye(&self) -> f64 {
+        let bob = if self.on_ground {self.bob.sin() * BOB_HEIGHT} else {0.0};
+        self.z + self.eye_height + bob
+    }
+
+    pub fn jump(&mut self) {
+        if self.on_ground && !self.crouching {
+            self.z_speed = JUMP_SPEED;
+            self.on_ground = false;
+        }
+    }
+
+    /// Gravity, landing, crouching and head-bob. `floor` and `ceiling` belong to the spot the player stands on.
+    pub fn update_height(&mut self, floor: f64, ceiling: f64, walking: bool, delta_time: f64) {
+        self.z_speed -= GRAVITY * delta_time;
+        self.z += self.z_speed * delta_time;
+
+        // Landing, also steps up onto higher floors.
+        if self.z <= floor {
+            self.z = floor;
+            self.z_speed = 0.0;
+            self.on_ground = true;
+        } else if self.z_speed < 0.0 {
+            self.on_ground = false;
+        }
+
+        let target = if self.crouching {CROUCH_EYE_HEIGHT} else {EYE_HEIGHT};
+        let crouch_step = CROUCH_SPEED * delta_time;
+        self.eye_height = target.clamp(self.eye_height - crouch_step, self.eye_height + crouch_step);
+
+        // Bumping the head.
+        if self.z + self.eye_height > ceiling - HEAD_ROOM {
+            self.z = (ceiling - HEAD_ROOM - self.eye_height).max(floor);
+            self.z_speed = self.z_speed.min(0.0);
+        }
+
+        if walking && self.on_ground {
+            self.bob = (self.bob + BOB_SPEED * delta_time) % TAU;
+        } else {
+            self.bob = 0.0;
+        }
+    }
+
+    /// Armor takes a third of the damage while it lasts.
+    pub fn damage(&mut self, amount: i32) {
+        if self.god {
+            return;
+        }
+
+        let absorbed = (amount / 3).min(self.armor);
+        self.armor -= absorbed;
+        self.health = (self.health - amount + absorbed).max(0);
+
+        self.damage_flash = (self.damage_flash + amount as u32).min(DAMAGE_FLASH_TICKS);
+    }
+
+    /// Uses up an item, false if the player doesn't need it.
+    pub fn pick_up(&mut self, pickup: Pickup) -> bool {
+        match pickup {
+            Pickup::Health(amount) if self.health < PLAYER_HEALTH => self.health = (self.health + amount).min(PLAYER_HEALTH),
+            Pickup::Armor(amount) if self.armor < amount => self.armor = amount,
+            Pickup::Ammo(ammo, amount) if self.weapons.ammo[ammo as usize] < MAX_AMMO => {
+                self.weapons.ammo[ammo as usize] = (self.weapons.ammo[ammo as usize] + amount).min(MAX_AMMO);
+            },
+            _ => return false
+        }
+
+        self.pickup_flash = PICKUP_FLASH_TICKS;
+        self.message = Some((pickup.message(), MESSAGE_TICKS));
+        true
+    }
+
+    /// The screen flashes and the message fade out, called every tick.
+    pub fn fade_flashes(&mut self) {
+        self.damage_flash = self.damage_flash.saturating_sub(1);
+        self.pickup_flash = self.pickup_flash.saturating_sub(1);
+        self.message = self.message.filter(|(_, ticks)| *ticks > 1).map(|(text, ticks)| (text, ticks - 1));
+    }
+
+    /// Looks up (<0) or down (>0) by some pixels, within the pitch limits of a view `height` pixels high.
+    pub fn look(&mut self, pixels: i32, height: u32) {
+        let half = height as f64 / 2.0;
+        self.ver = (self.ver + pixels).clamp(-(half * MAX_PITCH_UP) as i32, (half * MAX_PITCH_DOWN) as i32);
+    }
+
+    /// Horizontal field of view in degrees, the camera plane is tan(fov / 2) long.
+    pub fn fov(&self) -> f64 {
+        2.0 * self.cam.x.hypot(self.cam.y).atan().to_degrees()
+    }
+
+    pub fn set_fov(&mut self, degrees: f64) {
+        let plane_length = (degrees.to_radians() / 2.0).tan();
+        self.cam = Vec2::new(self.dir.y * plane_length, -self.dir.x * plane_length);
+    }
+
+    /// Moves the player to a spawn point, the angle is in radians with 0 looking along +x.
+    pub fn place(&mut self, pos: Vec2<f64>, angle: f64) {
+        self.pos = pos;
+        self.dir = Vec2::new(angle.cos(), angle.sin());
+
+        // Keep the field of view, the plane stays perpendicular to the right of the direction.
+        let plane_length = (self.cam.x.powi(2) + self.cam.y.powi(2)).sqrt();
+        self.cam = Vec2::new(self.dir.y * plane_length, -self.dir.x * plane_length);
     }
 }
 
+/// Where the current map came from, so a save game can load it again.
+#[derive(Clone, PartialEq)]
+pub enum MapSource {
+    Grid,
+    LevelFile(String),
+    Wad { file: String, map: String }
+}
+
+impl MapSource {
+    /// Just the level of the map, nothing gets loaded into the game.
+    pub fn read_level(&self) -> Result<Level, String> {
+        match self {
+            MapSource::Grid => Ok(Level::from_grid(&Map::from_grid(&WORLD_MAP, WORLD_MAP_SKY))),
+            MapSource::LevelFile(file) => Level::from_file(file),
+            MapSource::Wad { file, map } => Wad::from_file(file)?.read_level(map)
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum RenderMode {
+    Grid,
+    Sectors,
+    Bsp
+}
+
 pub struct GameData {
-    pub mouse_set: bool,
     pub map_view: bool,
     pub textured: bool,
+    pub paletted: bool,
+    pub render_mode: RenderMode,
     pub width: u32,
     pub height: u32,
-    pub last_mouse_pos: Vec2<i32>,
-    pub textures: Vec<LoadedTexture>
+    /// Smoothed relative mouse movement of the last frame.
+    pub mouse_delta: Vec2<f64>,
+    /// Pitch in pixels that doesn't add up to a whole pixel yet, slow stick and key movements would get lost otherwise.
+    pub pitch_remainder: f64,
+    pub settings: Settings,
+    pub gamepad: Gamepad,
+    pub textures: Vec<LoadedTexture>,
+    pub map: Map,
+    pub sectors: SectorMap,
+    pub level: Level,
+    pub bsp: Bsp,
+    /// False while the level is just the grid turned into linedefs.
+    pub custom_level: bool,
+    pub level_textures: HashMap<String, LoadedTexture>,
+    pub level_flats: HashMap<String, LoadedTexture>,
+    pub sky: Option<LoadedTexture>,
+    pub entities: Vec<Entity>,
+    pub sprites: Sprites,
+    /// Time that hasn't been simulated yet, the entities update in fixed ticks.
+    pub tick_time: f64,
+    /// Everything random in the game comes from here, so a seed always plays out the same way.
+    pub rng: Rng,
+    /// Used for all text on screen.
+    pub font: Font,
+    /// Frames per second, smoothed over the last frames.
+    pub fps: f64,
+    pub console: Console,
+    pub menu: Menu,
+    pub audio: Audio,
+    /// The WAD the current map came from, the console loads other maps out of it.
+    pub wad_file: Option<String>,
+    pub map_source: MapSource,
+    pub palette_data: Option<PaletteData>,
+    /// Floors and ceilings that triggers set moving.
+    pub movers: Vec<Mover>,
+    /// The cell and the sector the player was in last tick, walk triggers only go off when stepping into them.
+    pub walked_in: Option<((i32, i32), Option<usize>)>
 }
 
 impl GameData {
-    pub fn new(mouse_set: bool, map_view: bool, width: u32, height: u32) -> Self {
+    pub fn new(map_view: bool, width: u32, height: u32, map: Map) -> Self {
         GameData {
-            mouse_set,
             map_view,
             textured: false,
+            paletted: false,
+            render_mode: RenderMode::Grid,
             width,
             height,
-            last_mouse_pos: Vec2::new(0, 0),
-            textures: Vec::new()
+            mouse_delta: Vec2::new(0.0, 0.0),
+            pitch_remainder: 0.0,
+            settings: Settings::default(),
+            gamepad: Gamepad::default(),
+            textures: Vec::new(),
+            sectors: SectorMap::from_grid(&map),
+            level: Level::from_grid(&map),
+            bsp: Bsp::build(&Level::from_grid(&map)),
+            custom_level: false,
+            map,
+            level_textures: HashMap::new(),
+            level_flats: HashMap::new(),
+            sky: None,
+            entities: Vec::new(),
+            sprites: Sprites::default(),
+            tick_time: 0.0,
+            rng: Rng::new(DEFAULT_SEED),
+            font: Font::builtin(),
+            fps: 0.0,
+            console: Console::default(),
+            menu: Menu::default(),
+            audio: Audio::default(),
+            wad_file: None,
+            map_source: MapSource::Grid,
+            palette_data: None,
+            movers: Vec::new(),
+            walked_in: None
         }
     }
 
+    /// A player at the start of the map, the grid has no start so there it is a fixed spot.
+    pub fn new_player(&self) -> PlayerData {
+        let mut player = PlayerData::new(
+            Vec2::new(1.5, 1.5),
+            Vec2::new(-1.0, 0.0),
+            Vec2::new(0.0, 0.66)
+        );
+
+        if let Some(start) = self.level.player_start().filter(|_| self.custom_level) {
+            player.place(start.pos, start.angle);
+        }
+        player.set_fov(self.settings.fov);
+
+        player
+    }
+
     pub fn update_sizes(&mut self, sizes: (u32, u32)) {
         self.width = sizes.0;
         self.height= sizes.1;
     }
 
     pub fn add_texture_from_file(&mut self, filename: &str) -> Result<(), String> {
-        let sur = Surface::from_file(filename)?.into_canvas()?;
-        let (sur_width, sur_height) = {(sur.surface().width(), sur.surface().height())};
+        self.textures.push(LoadedTexture::from_file(filename)?);
 
-        self.textures.push(
-            LoadedTexture::new(
-                sur_width,
-                sur_height,
-                sur.read_pixels(Rect::new(0, 0, sur_width, sur_height), sdl2::pixels::PixelFormatEnum::RGBA32)?
-            )
-        );
+        Ok(())
+    }
+
+    /// Loads a map together with the textures and flats it needs out of a WAD file.
+    /// Everything gets read before anything changes, a broken WAD leaves the current map as it is.
+    pub fn load_wad(&mut self, filename: &str, map: &str) -> Result<(), String> {
+        let wad = Wad::from_file(filename)?;
+        let palette = wad.read_palette()?;
+        let level = wad.read_level(map)?;
+        let textures = wad.read_textures(&palette)?;
+        let flats = wad.read_flats(&palette)?;
+        let sprites = wad.read_sprites(&palette)?;
+
+        self.set_palette(Palette::new(palette));
+        self.set_level(level);
+        self.level_textures = textures.into_iter().collect();
+        self.level_flats = flats.into_iter().collect();
+        self.sprites = Sprites::default();
+        for (name, texture) in sprites {
+            self.sprites.add(&name, texture);
+        }
+        self.audio.load_wad_sounds(&wad);
+        self.change_music(Some(&wad));
+        self.load_sky();
+        self.wad_file = Some(filename.to_string());
+        self.map_source = MapSource::Wad { file: filename.to_string(), map: map.to_string() };
+
+        Ok(())
+    }
+
+    /// Palette from a PNG strip or a PLAYPAL style file, textures get quantized to it.
+    pub fn load_palette(&mut self, filename: &str) -> Result<(), String> {
+        let palette = if filename.to_lowercase().ends_with(".png") {
+            Palette::from_png(filename)?
+        } else {
+            Palette::from_playpal(filename)?
+        };
+
+        self.set_palette(palette);
+        Ok(())
+    }
+
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette_data = Some(PaletteData::new(palette, &self.textures, self.sky.as_ref()));
+    }
+
+    /// Without a loaded palette one gets made out of the textures.
+    pub fn toggle_palette_mode(&mut self) {
+        if self.palette_data.is_none() {
+            self.set_palette(Palette::from_textures(&self.textures));
+        }
+
+        self.paletted = !self.paletted;
+    }
+
+    /// Loads a level in the in-repo format, textures and flats come out of pics/.
+    pub fn load_level_file(&mut self, filename: &str) -> Result<(), String> {
+        self.set_level(Level::from_file(filename)?);
+        self.load_level_pics();
+        self.load_sky();
+        self.change_music(None);
+        self.map_source = MapSource::LevelFile(filename.to_string());
+
+        Ok(())
+    }
+
+    /// Goes back to a grid map, with the level the other renderers use made out of it.
+    pub fn load_grid(&mut self, map: Map) {
+        self.sectors = SectorMap::from_grid(&map);
+        self.level = Level::from_grid(&map);
+        self.bsp = Bsp::build(&self.level);
+        self.map = map;
+        self.movers.clear();
+        self.walked_in = None;
+        self.custom_level = false;
+        self.render_mode = RenderMode::Grid;
+        self.map_source = MapSource::Grid;
+        self.load_level_pics();
+        self.load_sky();
+        self.change_music(None);
+    }
+
+    /// Crossfades to the music of the level, out of the WAD or a file. It only gets decoded when there
+    /// is a device to play it, a song that can't be read leaves a note in the console and silence.
+    fn change_music(&mut self, wad: Option<&Wad>) {
+        if !self.audio.is_open() || self.audio.music == self.level.music {
+            return;
+        }
+
+        self.audio.music = self.level.music.clone();
+        let song = self.level.music.as_ref().map(|name| match wad {
+            Some(wad) => Song::from_lump(wad, name),
+            None => Song::from_file(name)
+        });
+
+        match song.transpose() {
+            Ok(song) => self.audio.play_music(song),
+            Err(error) => {
+                self.console.print(&format!("no music: {}", error));
+                self.audio.play_music(None);
+            }
+        }
+    }
+
+    /// Loads a map from where it came from with its things, for the current map that undoes everything that happened in it.
+    pub fn load_map(&mut self, source: MapSource) -> Result<(), String> {
+        match source {
+            MapSource::Grid => self.load_grid(Map::from_grid(&WORLD_MAP, WORLD_MAP_SKY)),
+            MapSource::LevelFile(file) => self.load_level_file(&file)?,
+            MapSource::Wad { file, map } => self.load_wad(&file, &map)?
+        }
+        self.spawn_entities();
 
         Ok(())
     }
+
+    /// Replaces the level, from now on the BSP renderer is the only one that shows the right world.
+    pub fn set_level(&mut self, level: Level) {
+        self.bsp = Bsp::build(&level);
+        self.level = level;
+        self.movers.clear();
+        self.walked_in = None;
+        self.custom_level = true;
+        self.render_mode = RenderMode::Bsp;
+    }
+
+    /// Loads every texture and flat the level uses from pics/<name>.png, missing ones are drawn in flat colors.
+    /// The textures switches and triggers change walls to are part of it.
+    pub fn load_level_pics(&mut self) {
+        let level = &self.level;
+        let trigger_textures = level.triggers.iter().filter_map(|trigger| match (&trigger.activation, &trigger.action) {
+            (_, TriggerAction::Tile(_, texture)) | (Activation::Switch { texture: Some(texture), .. }, _) => Some(texture),
+            _ => None
+        });
+        let textures = level.sidedefs.iter().flat_map(|side| [&side.upper, &side.lower, &side.middle]).chain(trigger_textures);
+        let flats = level.sectors.iter().flat_map(|sector| [&sector.floor_flat, &sector.ceiling_flat]);
+
+        for name in textures.filter(|name| !name.is_empty()) {
+            if !self.level_textures.contains_key(name) {
+                if let Ok(texture) = LoadedTexture::from_file(&format!("pics/{}.png", name)) {
+                    self.level_textures.insert(name.clone(), texture);
+                }
+            }
+        }
+
+        for name in flats.filter(|name| !name.is_empty()) {
+            if !self.level_flats.contains_key(name) {
+                if let Ok(texture) = LoadedTexture::from_file(&format!("pics/{}.png", name)) {
+                    self.level_flats.insert(name.clone(), texture);
+                }
+            }
+        }
+    }
+
+    /// The sky of the current level, WAD textures come first and then pics/<name>.png.
+    /// Without one the renderers fall back to their ceiling colors.
+    pub fn load_sky(&mut self) {
+        self.sky = self.level.sky.as_ref().and_then(|name| match self.level_textures.get(name) {
+            Some(texture) => Some(texture.clone()),
+            None => LoadedTexture::from_file(&format!("pics/{}.png", name)).ok()
+        });
+
+        if let Some(palette_data) = &mut self.palette_data {
+            palette_data.sky = self.sky.as_ref().map(|sky| IndexedTexture::new(sky, &palette_data.palette));
+        }
+    }
+
+    /// Fills the world with the things of the level, or WORLD_MAP_THINGS for the grid.
+    pub fn spawn_entities(&mut self) {
+        self.entities = if self.custom_level {
+            spawn_things(&self.level.things)
+        } else {
+            spawn_things(&WORLD_MAP_THINGS)
+        };
+
+        self.load_sprites();
+    }
+
+    /// Sprites the WAD doesn't have come from pics/sprites/<lump name>.png, or else a single picture pics/<pic>.png.
+    /// Missing ones just don't get drawn.
+    pub fn load_sprites(&mut self) {
+        let mut files: Vec<_> = std::fs::read_dir("pics/sprites").into_iter().flatten().flatten().map(|entry| entry.path()).collect();
+        files.sort();
+
+        // Sprite sets out of the WAD don't get mixed with the files.
+        let from_wad: Vec<&str> = THING_TYPES.iter().chain(SPAWNED_TYPES).map(|thing_type| thing_type.sprite)
+            .chain(WEAPONS.iter().map(|weapon| weapon.sprite))
+            .filter(|sprite| self.sprites.contains(sprite))
+            .collect();
+
+        for path in files {
+            let Some(name) = path.file_stem().and_then(|name| name.to_str()).map(str::to_uppercase) else {
+                continue;
+            };
+
+            if from_wad.iter().any(|sprite| name.starts_with(sprite)) {
+                continue;
+            }
+
+            if let Ok(texture) = LoadedTexture::from_file(&path.to_string_lossy()) {
+                self.sprites.add(&name, texture);
+            }
+        }
+
+        let pictures = THING_TYPES.iter().chain(SPAWNED_TYPES).map(|thing_type| (thing_type.sprite, thing_type.pic))
+            .chain(WEAPONS.iter().map(|weapon| (weapon.sprite, weapon.pic)));
+
+        for (sprite, pic) in pictures {
+            if self.sprites.contains(sprite) {
+                continue;
+            }
+
+            if let Ok(texture) = LoadedTexture::from_file(&format!("pics/{}.png", pic)) {
+                self.sprites.add(&format!("{}A0", sprite), texture);
+            }
+        }
+    }
+
+    /// The current animation frame of an entity the way the viewer sees it, and whether it is mirrored.
+    pub fn sprite_frame(&self, entity: &Entity, viewer: &Vec2<f64>) -> Option<(&LoadedTexture, bool)> {
+        self.sprites.get(&entity.sprite, entity.frame_letter(), rotation(viewer, &entity.pos, entity.angle))
+    }
+
+    /// Grid -> Sectors -> BSP, levels that didn't come from the grid can only be shown by the BSP renderer.
+    pub fn next_render_mode(&mut self) {
+        self.render_mode = match self.render_mode {
+            _ if self.custom_level => RenderMode::Bsp,
+            RenderMode::Grid => RenderMode::Sectors,
+            RenderMode::Sectors => RenderMode::Bsp,
+            RenderMode::Bsp => RenderMode::Grid
+        };
+    }
+
+    /// Floor and ceiling height at a position, of whichever world the current renderer shows.
+    pub fn floor_and_ceiling(&self, pos: &Vec2<f64>) -> (f64, f64) {
+        match self.render_mode {
+            RenderMode::Bsp => {
+                let sector = sector_at(self, pos);
+                (sector.floor, sector.ceiling)
+            },
+            RenderMode::Sectors => self.sectors.locate(pos)
+                .map_or((0.0, 1.0), |index| (self.sectors.sectors[index].floor, self.sectors.sectors[index].ceiling)),
+            RenderMode::Grid => (0.0, 1.0)
+        }
+    }
+
+    /// Collision against whichever world the current renderer shows.
+    pub fn can_move(&self, from: &Vec2<f64>, to: &Vec2<f64>) -> bool {
+        let world = match self.render_mode {
+            RenderMode::Bsp => self.level.can_move(from, to),
+            RenderMode::Sectors => self.sectors.can_move(to),
+            RenderMode::Grid => self.map.can_move(from, to)
+        };
+
+        world && entity::can_move(&self.entities, from, to)
+    }
+
+    /// Where a ray along `dir` hits a wall of the world that is shown, as `t` along `dir` and the normal of the wall.
+    pub fn cast_wall(&self, from: &Vec2<f64>, dir: &Vec2<f64>) -> (f64, Vec2<f64>) {
+        match self.render_mode {
+            RenderMode::Bsp => self.level.cast_ray(from, dir).unwrap_or((f64::INFINITY, Vec2::new(0.0, 0.0))),
+            RenderMode::Grid | RenderMode::Sectors => {
+                let hit = self.map.cast_ray(from, dir);
+
+                // The grid only knows which kind of side got hit, the normal points back along the ray.
+                let normal = if hit.x_side {
+                    Vec2::new(-dir.x.signum(), 0.0)
+                } else {
+                    Vec2::new(0.0, -dir.y.signum())
+                };
+
+                (hit.dist, normal)
+            }
+        }
+    }
 }
 
 pub struct EndGame(pub bool);
 
+#[derive(Clone)]
 pub struct LoadedTexture {
     pub width: u32,
     pub height: u32,
@@ -115,12 +656,23 @@ impl LoadedTexture {
         LoadedTexture { width, height, pixels }
     }
 
+    pub fn from_file(filename: &str) -> Result<Self, String> {
+        let sur = Surface::from_file(filename)?.into_canvas()?;
+        let (sur_width, sur_height) = {(sur.surface().width(), sur.surface().height())};
+
+        Ok(LoadedTexture::new(
+            sur_width,
+            sur_height,
+            sur.read_pixels(Rect::new(0, 0, sur_width, sur_height), sdl2::pixels::PixelFormatEnum::RGBA32)?
+        ))
+    }
+
     pub fn get_pixel(&self, x: usize, y: usize) -> Option<(u8, u8, u8, u8)>  {
         if x >= self.width as usize || y >= self.height as usize {
             return Option::None;
         }
 
-        let pos = (x + y*self.height as usize) * 4;
+        let pos = (x + y*self.width as usize) * 4;
 
         if pos+3 >= self.pixels.len() {
             return None;
@@ -0,0 +1,304 @@
+use crate::ai::{think, Cell, Monster, MonsterSounds};
+use crate::audio::Sound;
+use crate::config::{MAX_STEP_HEIGHT, PLAYER_RADIUS, WAD_SCALE};
+use crate::data::{GameData, PlayerData, Vec2};
+use crate::level::Thing;
+use crate::random::Rng;
+use crate::sprite::{Animation, State};
+use crate::weapon::{Ammo, Projectile};
+
+// Entity tags.
+/// Blocks the player and other solid entities.
+pub const SOLID: u16 = 0x0001;
+pub const ENEMY: u16 = 0x0002;
+pub const ITEM: u16 = 0x0004;
+pub const DECORATION: u16 = 0x0008;
+
+// Thing flags, only things for the medium skill in single player get spawned.
+const SKILL_MEDIUM: u16 = 0x0002;
+const SKILL_MASK: u16 = 0x0007;
+const MULTIPLAYER_ONLY: u16 = 0x0010;
+
+/// Called once per tick (TICK_RATE times per second) for every entity that has one, with its index in GameData::entities.
+pub type Update = fn(index: usize, player: &mut PlayerData, game_data: &mut GameData);
+
+/// What a thing type number turns into.
+pub struct ThingType {
+    pub kind: u16,
+    /// Doom's sprite name, the frames come out of the WAD (`BAR1A0`, `POSSA1`, ...).
+    pub sprite: &'static str,
+    /// Used when the WAD has no such sprite, pics/<name>.png.
+    pub pic: &'static str,
+    pub radius: f64,
+    pub height: f64,
+    pub tags: u16,
+    pub update: Option<Update>,
+    pub animations: &'static [Animation],
+    pub monster: Option<Monster>,
+    pub pickup: Option<Pickup>
+}
+
+/// What an item gives the player when they walk over it.
+#[derive(Clone, Copy)]
+pub enum Pickup {
+    Health(i32),
+    Armor(i32),
+    Ammo(Ammo, u32)
+}
+
+impl Pickup {
+    /// Shown at the top of the screen when it gets picked up.
+    pub fn message(&self) -> &'static str {
+        match self {
+            Pickup::Health(_) => "Picked up a stimpack.",
+            Pickup::Armor(_) => "Picked up the armor.",
+            Pickup::Ammo(Ammo::Bullets, _) => "Picked up a clip.",
+            Pickup::Ammo(Ammo::Shells, _) => "Picked up some shotgun shells.",
+            Pickup::Ammo(Ammo::Rockets, _) => "Picked up a rocket."
+        }
+    }
+}
+
+pub const STILL: &[Animation] = &[Animation { state: State::Idle, frames: "A", tics: 0 }];
+
+const BARREL: &[Animation] = &[Animation { state: State::Idle, frames: "AB", tics: 6 }];
+
+const ZOMBIEMAN: &[Animation] = &[
+    Animation { state: State::Idle, frames: "AB", tics: 10 },
+    Animation { state: State::Walk, frames: "ABCD", tics: 4 },
+    Animation { state: State::Attack, frames: "EF", tics: 8 },
+    Animation { state: State::Pain, frames: "G", tics: 3 },
+    Animation { state: State::Death, frames: "HIJKL", tics: 5 }
+];
+
+const IMP: &[Animation] = &[
+    Animation { state: State::Idle, frames: "AB", tics: 10 },
+    Animation { state: State::Walk, frames: "ABCD", tics: 3 },
+    Animation { state: State::Attack, frames: "EFG", tics: 8 },
+    Animation { state: State::Pain, frames: "H", tics: 2 },
+    Animation { state: State::Death, frames: "IJKLM", tics: 8 }
+];
+
+const ZOMBIEMAN_AI: Monster = Monster {
+    health: 20, pain_chance: 0.78, speed: 1.2, melee_damage: 0, ranged_damage: 15, ranged_range: 16.0, ranged_chance: 0.05, accuracy: 0.6,
+    cooldown: 35,
+    sounds: MonsterSounds { sight: Sound::PossessedSight, melee: Sound::Claw, ranged: Sound::Pistol, pain: Sound::MonsterPain, death: Sound::PossessedDeath }
+};
+
+const IMP_AI: Monster = Monster {
+    health: 60, pain_chance: 0.78, speed: 1.2, melee_damage: 24, ranged_damage: 20, ranged_range: 10.0, ranged_chance: 0.03, accuracy: 0.5,
+    cooldown: 40,
+    sounds: MonsterSounds { sight: Sound::ImpSight, melee: Sound::Claw, ranged: Sound::Fireball, pain: Sound::MonsterPain, death: Sound::ImpDeath }
+};
+
+/// Doom's thing numbers, sizes in Doom units get scaled down to grid units.
+pub const THING_TYPES: [ThingType; 10] = [
+    ThingType { kind: 2035, sprite: "BAR1", pic: "barrel", radius: 10.0 * WAD_SCALE, height: 42.0 * WAD_SCALE, tags: SOLID | DECORATION, update: None, animations: BARREL, monster: None, pickup: None },
+    ThingType { kind: 30, sprite: "COL1", pic: "pillar", radius: 16.0 * WAD_SCALE, height: 52.0 * WAD_SCALE, tags: SOLID | DECORATION, update: None, animations: STILL, monster: None, pickup: None },
+    ThingType { kind: 2028, sprite: "COLU", pic: "greenlight", radius: 16.0 * WAD_SCALE, height: 48.0 * WAD_SCALE, tags: SOLID | DECORATION, update: None, animations: STILL, monster: None, pickup: None },
+    ThingType { kind: 2011, sprite: "STIM", pic: "stimpack", radius: 20.0 * WAD_SCALE, height: 16.0 * WAD_SCALE, tags: ITEM, update: None, animations: STILL, monster: None, pickup: Some(Pickup::Health(10)) },
+    ThingType { kind: 2018, sprite: "ARM1", pic: "armor", radius: 20.0 * WAD_SCALE, height: 16.0 * WAD_SCALE, tags: ITEM, update: None, animations: STILL, monster: None, pickup: Some(Pickup::Armor(100)) },
+    ThingType { kind: 2007, sprite: "CLIP", pic: "clip", radius: 20.0 * WAD_SCALE, height: 16.0 * WAD_SCALE, tags: ITEM, update: None, animations: STILL, monster: None, pickup: Some(Pickup::Ammo(Ammo::Bullets, 10)) },
+    ThingType { kind: 2008, sprite: "SHEL", pic: "shells", radius: 20.0 * WAD_SCALE, height: 16.0 * WAD_SCALE, tags: ITEM, update: None, animations: STILL, monster: None, pickup: Some(Pickup::Ammo(Ammo::Shells, 4)) },
+    ThingType { kind: 2010, sprite: "ROCK", pic: "rocketammo", radius: 20.0 * WAD_SCALE, height: 16.0 * WAD_SCALE, tags: ITEM, update: None, animations: STILL, monster: None, pickup: Some(Pickup::Ammo(Ammo::Rockets, 1)) },
+    ThingType { kind: 3004, sprite: "POSS", pic: "zombieman", radius: 20.0 * WAD_SCALE, height: 56.0 * WAD_SCALE, tags: SOLID | ENEMY, update: Some(think), animations: ZOMBIEMAN, monster: Some(ZOMBIEMAN_AI), pickup: None },
+    ThingType { kind: 3001, sprite: "TROO", pic: "imp", radius: 20.0 * WAD_SCALE, height: 56.0 * WAD_SCALE, tags: SOLID | ENEMY, update: Some(think), animations: IMP, monster: Some(IMP_AI), pickup: None }
+];
+
+pub fn thing_type(kind: u16) -> Option<&'static ThingType> {
+    THING_TYPES.iter().find(|thing_type| thing_type.kind == kind)
+}
+
+/// Anything in the world that isn't the level itself: enemies, items and decorations.
+pub struct Entity {
+    pub pos: Vec2<f64>,
+    /// Height above the floor, only projectiles and puffs float.
+    pub z: f64,
+    /// In radians, 0 looks along +x.
+    pub angle: f64,
+    /// The sprite set, see ThingType::sprite.
+    pub sprite: String,
+    pub radius: f64,
+    pub height: f64,
+    pub tags: u16,
+    pub update: Option<Update>,
+    pub animations: &'static [Animation],
+    pub state: State,
+    /// Position in the animation of the state, and the ticks left until the next frame.
+    pub frame: usize,
+    pub tics: u32,
+    /// Ticks since the state started.
+    pub state_ticks: u32,
+    pub monster: Option<Monster>,
+    /// Heard or saw the player, doesn't wait anymore.
+    pub alerted: bool,
+    /// Ticks until the next attack.
+    pub cooldown: u32,
+    /// Cells left to walk to the player, and the ticks until looking for a new path.
+    pub path: Vec<Cell>,
+    pub repath: u32,
+    /// Only things with health can be shot.
+    pub health: i32,
+    pub projectile: Option<Projectile>,
+    pub pickup: Option<Pickup>,
+    /// Gets taken out of GameData::entities at the end of the tick.
+    pub removed: bool
+}
+
+impl Entity {
+    pub fn new(pos: Vec2<f64>, angle: f64, thing_type: &ThingType) -> Self {
+        Entity {
+            pos,
+            z: 0.0,
+            angle,
+            sprite: thing_type.sprite.to_string(),
+            radius: thing_type.radius,
+            height: thing_type.height,
+            tags: thing_type.tags,
+            update: thing_type.update,
+            animations: thing_type.animations,
+            state: State::Idle,
+            frame: 0,
+            tics: thing_type.animations.first().map_or(0, |animation| animation.tics),
+            state_ticks: 0,
+            monster: thing_type.monster,
+            alerted: false,
+            cooldown: 0,
+            path: Vec::new(),
+            repath: 0,
+            health: thing_type.monster.map_or(0, |monster| monster.health),
+            projectile: None,
+            pickup: thing_type.pickup,
+            removed: false
+        }
+    }
+
+    pub fn from_thing(thing: &Thing, thing_type: &ThingType) -> Self {
+        Entity::new(thing.pos, thing.angle, thing_type)
+    }
+
+    pub fn is_alive(&self) -> bool {
+        self.health > 0
+    }
+
+    /// Takes damage, which wakes it up. Monsters may flinch and dead ones don't block anymore.
+    /// Returns the state it went into, if the hit changed it.
+    pub fn hurt(&mut self, amount: i32, rng: &mut Rng) -> Option<State> {
+        if !self.is_alive() {
+            return None;
+        }
+
+        self.health -= amount;
+        self.alerted = true;
+
+        if !self.is_alive() {
+            self.set_state(State::Death);
+            self.tags &= !SOLID;
+            Some(State::Death)
+        } else if self.monster.is_some_and(|monster| rng.chance(monster.pain_chance)) {
+            self.set_state(State::Pain);
+            Some(State::Pain)
+        } else {
+            None
+        }
+    }
+
+    /// Starts the animation of a state, states without one keep showing the idle animation.
+    pub fn set_state(&mut self, state: State) {
+        if self.state == state {
+            return;
+        }
+
+        self.state = state;
+        self.frame = 0;
+        self.tics = self.animation().map_or(0, |animation| animation.tics);
+        self.state_ticks = 0;
+    }
+
+    /// Ticks it takes to play the animation of the state once.
+    pub fn animation_length(&self) -> u32 {
+        self.animation().map_or(0, |animation| animation.frames.len() as u32 * animation.tics)
+    }
+
+    fn animation(&self) -> Option<&'static Animation> {
+        self.animations.iter().find(|animation| animation.state == self.state)
+            .or_else(|| self.animations.iter().find(|animation| animation.state == State::Idle))
+    }
+
+    /// The letter of the frame to show, like the `A` in POSSA1.
+    pub fn frame_letter(&self) -> char {
+        self.animation().and_then(|animation| animation.frames.chars().nth(self.frame)).unwrap_or('A')
+    }
+
+    /// Moves the animation along by one tick, 0 tics means the frame stays forever.
+    pub fn animate(&mut self) {
+        self.state_ticks += 1;
+
+        let Some(animation) = self.animation() else {
+            return;
+        };
+
+        if self.tics == 0 || self.tics > 1 {
+            self.tics = self.tics.saturating_sub(1);
+            return;
+        }
+
+        let last = self.frame + 1 >= animation.frames.len();
+        if last && self.state == State::Death {
+            self.tics = 0;
+            return;
+        }
+
+        self.frame = if last {0} else {self.frame + 1};
+        self.tics = animation.tics;
+    }
+
+    pub fn has_tag(&self, tag: u16) -> bool {
+        self.tags & tag != 0
+    }
+}
+
+/// The things of a level turned into entities, player starts and unknown types are skipped.
+pub fn spawn_things(things: &[Thing]) -> Vec<Entity> {
+    things.iter()
+        .filter(|thing| thing.flags & MULTIPLAYER_ONLY == 0)
+        // Our own level format has no skill flags, those things are always there.
+        .filter(|thing| thing.flags & SKILL_MASK == 0 || thing.flags & SKILL_MEDIUM != 0)
+        .filter_map(|thing| thing_type(thing.kind).map(|thing_type| Entity::from_thing(thing, thing_type)))
+        .collect()
+}
+
+/// Runs the update hooks of all entities once and moves their animations along, the player picks up the items they touch.
+pub fn tick(player: &mut PlayerData, game_data: &mut GameData) {
+    for entity in &mut game_data.entities {
+        let touching = (player.pos.x - entity.pos.x).hypot(player.pos.y - entity.pos.y) < entity.radius + PLAYER_RADIUS;
+
+        if let Some(pickup) = entity.pickup.filter(|_| touching) {
+            // Items the player doesn't need stay where they are.
+            entity.removed = player.pick_up(pickup);
+            if entity.removed {
+                game_data.audio.play(Sound::ItemPickup, None);
+            }
+        }
+    }
+
+    for index in 0..game_data.entities.len() {
+        game_data.entities[index].animate();
+
+        if let Some(update) = game_data.entities[index].update {
+            update(index, player, game_data);
+        }
+    }
+
+    game_data.entities.retain(|entity| !entity.removed);
+}
+
+/// Checks the move against solid entities, just like the walls only moving closer gets blocked.
+/// Things low enough to step on don't block.
+pub fn can_move(entities: &[Entity], from: &Vec2<f64>, to: &Vec2<f64>) -> bool {
+    let dist = |a: &Vec2<f64>, b: &Vec2<f64>| ((a.x - b.x).powi(2) + (a.y - b.y).powi(2)).sqrt();
+
+    !entities.iter()
+        .filter(|entity| entity.has_tag(SOLID) && entity.height > MAX_STEP_HEIGHT)
+        .any(|entity| dist(to, &entity.pos) < entity.radius + PLAYER_RADIUS && dist(to, &entity.pos) < dist(from, &entity.pos))
+}
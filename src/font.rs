@@ -0,0 +1,329 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use sdl2::pixels::Color;
+
+use crate::data::LoadedTexture;
+use crate::framebuffer::FrameBuffer;
+
+/// Where a character is in the atlas and how it sits on the line, in atlas pixels.
+#[derive(Clone, Copy)]
+pub struct Glyph {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    pub x_offset: i32,
+    pub y_offset: i32,
+    pub advance: i32
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Align {
+    Left,
+    Center,
+    Right
+}
+
+/// How a string gets drawn, `x` of Font::draw() is the left edge, the center or the right edge depending on `align`.
+#[derive(Clone, Copy)]
+pub struct TextStyle {
+    pub color: Color,
+    /// Every atlas pixel becomes a scale x scale block.
+    pub scale: u32,
+    pub align: Align,
+    /// Lines longer than this (in screen pixels) get broken between words.
+    pub wrap_width: Option<u32>
+}
+
+impl TextStyle {
+    pub fn new(color: Color, scale: u32) -> Self {
+        TextStyle { color, scale, align: Align::Left, wrap_width: None }
+    }
+
+    pub fn align(mut self, align: Align) -> Self {
+        self.align = align;
+        self
+    }
+
+    pub fn wrap(mut self, width: u32) -> Self {
+        self.wrap_width = Some(width);
+        self
+    }
+}
+
+/// 3x5 pixel glyphs of the built-in font, every row uses the lowest 3 bits. Lower case uses the upper case ones.
+const BUILTIN_GLYPHS: [(char, [u8; 5]); 64] = [
+    (' ', [0b000, 0b000, 0b000, 0b000, 0b000]),
+    ('0', [0b111, 0b101, 0b101, 0b101, 0b111]),
+    ('1', [0b010, 0b110, 0b010, 0b010, 0b111]),
+    ('2', [0b111, 0b001, 0b111, 0b100, 0b111]),
+    ('3', [0b111, 0b001, 0b111, 0b001, 0b111]),
+    ('4', [0b101, 0b101, 0b111, 0b001, 0b001]),
+    ('5', [0b111, 0b100, 0b111, 0b001, 0b111]),
+    ('6', [0b111, 0b100, 0b111, 0b101, 0b111]),
+    ('7', [0b111, 0b001, 0b001, 0b001, 0b001]),
+    ('8', [0b111, 0b101, 0b111, 0b101, 0b111]),
+    ('9', [0b111, 0b101, 0b111, 0b001, 0b111]),
+    ('A', [0b010, 0b101, 0b111, 0b101, 0b101]),
+    ('B', [0b110, 0b101, 0b110, 0b101, 0b110]),
+    ('C', [0b011, 0b100, 0b100, 0b100, 0b011]),
+    ('D', [0b110, 0b101, 0b101, 0b101, 0b110]),
+    ('E', [0b111, 0b100, 0b110, 0b100, 0b111]),
+    ('F', [0b111, 0b100, 0b110, 0b100, 0b100]),
+    ('G', [0b011, 0b100, 0b101, 0b101, 0b011]),
+    ('H', [0b101, 0b101, 0b111, 0b101, 0b101]),
+    ('I', [0b111, 0b010, 0b010, 0b010, 0b111]),
+    ('J', [0b001, 0b001, 0b001, 0b101, 0b010]),
+    ('K', [0b101, 0b101, 0b110, 0b101, 0b101]),
+    ('L', [0b100, 0b100, 0b100, 0b100, 0b111]),
+    ('M', [0b101, 0b111, 0b111, 0b101, 0b101]),
+    ('N', [0b110, 0b101, 0b101, 0b101, 0b101]),
+    ('O', [0b111, 0b101, 0b101, 0b101, 0b111]),
+    ('P', [0b110, 0b101, 0b110, 0b100, 0b100]),
+    ('Q', [0b010, 0b101, 0b101, 0b110, 0b011]),
+    ('R', [0b110, 0b101, 0b110, 0b101, 0b101]),
+    ('S', [0b011, 0b100, 0b010, 0b001, 0b110]),
+    ('T', [0b111, 0b010, 0b010, 0b010, 0b010]),
+    ('U', [0b101, 0b101, 0b101, 0b101, 0b111]),
+    ('V', [0b101, 0b101, 0b101, 0b101, 0b010]),
+    ('W', [0b101, 0b101, 0b111, 0b111, 0b101]),
+    ('X', [0b101, 0b101, 0b010, 0b101, 0b101]),
+    ('Y', [0b101, 0b101, 0b010, 0b010, 0b010]),
+    ('Z', [0b111, 0b001, 0b010, 0b100, 0b111]),
+    ('.', [0b000, 0b000, 0b000, 0b000, 0b010]),
+    (',', [0b000, 0b000, 0b000, 0b010, 0b100]),
+    (':', [0b000, 0b010, 0b000, 0b010, 0b000]),
+    (';', [0b000, 0b010, 0b000, 0b010, 0b100]),
+    ('!', [0b010, 0b010, 0b010, 0b000, 0b010]),
+    ('?', [0b110, 0b001, 0b010, 0b000, 0b010]),
+    ('-', [0b000, 0b000, 0b111, 0b000, 0b000]),
+    ('+', [0b000, 0b010, 0b111, 0b010, 0b000]),
+    ('=', [0b000, 0b111, 0b000, 0b111, 0b000]),
+    ('/', [0b001, 0b001, 0b010, 0b100, 0b100]),
+    ('\\', [0b100, 0b100, 0b010, 0b001, 0b001]),
+    ('%', [0b101, 0b001, 0b010, 0b100, 0b101]),
+    ('\'', [0b010, 0b010, 0b000, 0b000, 0b000]),
+    ('"', [0b101, 0b101, 0b000, 0b000, 0b000]),
+    ('(', [0b001, 0b010, 0b010, 0b010, 0b001]),
+    (')', [0b100, 0b010, 0b010, 0b010, 0b100]),
+    ('[', [0b011, 0b010, 0b010, 0b010, 0b011]),
+    (']', [0b110, 0b010, 0b010, 0b010, 0b110]),
+    ('_', [0b000, 0b000, 0b000, 0b000, 0b111]),
+    ('<', [0b001, 0b010, 0b100, 0b010, 0b001]),
+    ('>', [0b100, 0b010, 0b001, 0b010, 0b100]),
+    ('*', [0b000, 0b101, 0b010, 0b101, 0b000]),
+    ('#', [0b101, 0b111, 0b101, 0b111, 0b101]),
+    ('$', [0b011, 0b110, 0b010, 0b011, 0b110]),
+    ('&', [0b010, 0b101, 0b010, 0b101, 0b011]),
+    ('@', [0b111, 0b101, 0b111, 0b100, 0b111]),
+    ('^', [0b010, 0b101, 0b000, 0b000, 0b000])
+];
+
+/// A bitmap font: the glyphs are cut out of one atlas picture. The brightness and alpha of the
+/// atlas say how much of the text color a pixel gets, so white on black and white on transparent both work.
+pub struct Font {
+    pub atlas: LoadedTexture,
+    pub glyphs: HashMap<char, Glyph>,
+    pub line_height: u32
+}
+
+impl Font {
+    /// The 3x5 font that is always there, no files needed.
+    pub fn builtin() -> Self {
+        let width = BUILTIN_GLYPHS.len() as u32 * 3;
+        let mut pixels = vec![0; (width * 5 * 4) as usize];
+        let mut glyphs = HashMap::new();
+
+        for (i, (character, rows)) in BUILTIN_GLYPHS.iter().enumerate() {
+            let left = i as u32 * 3;
+
+            for (y, bits) in rows.iter().enumerate() {
+                for x in 0..3 {
+                    if bits & (0b100 >> x) != 0 {
+                        let offset = ((y as u32 * width + left + x) * 4) as usize;
+                        pixels[offset..offset + 4].copy_from_slice(&[255, 255, 255, 255]);
+                    }
+                }
+            }
+
+            glyphs.insert(*character, Glyph { x: left, y: 0, width: 3, height: 5, x_offset: 0, y_offset: 0, advance: 4 });
+        }
+
+        Font { atlas: LoadedTexture::new(width, 5, pixels), glyphs, line_height: 6 }
+    }
+
+    /// A picture split into `columns` x `rows` equally sized cells, holding the characters from `first` on, row by row.
+    pub fn from_grid(filename: &str, columns: u32, rows: u32, first: char) -> Result<Self, String> {
+        let atlas = LoadedTexture::from_file(filename)?;
+        let (cell_width, cell_height) = (atlas.width / columns.max(1), atlas.height / rows.max(1));
+        if cell_width == 0 || cell_height == 0 {
+            return Err(format!("{} is too small for {}x{} characters", filename, columns, rows));
+        }
+
+        let glyphs = (0..columns * rows)
+            .filter_map(|i| char::from_u32(first as u32 + i).map(|character| (i, character)))
+            .map(|(i, character)| (character, Glyph {
+                x: i % columns * cell_width,
+                y: i / columns * cell_height,
+                width: cell_width,
+                height: cell_height,
+                x_offset: 0,
+                y_offset: 0,
+                advance: cell_width as i32
+            }))
+            .collect();
+
+        Ok(Font { atlas, glyphs, line_height: cell_height })
+    }
+
+    /// A BMFont descriptor in the text format with a single page, the page picture is next to it.
+    pub fn from_bmfont(filename: &str) -> Result<Self, String> {
+        let text = fs::read_to_string(filename).map_err(|e| format!("{}: {}", filename, e))?;
+        let mut glyphs = HashMap::new();
+        let mut line_height = 0;
+        let mut page = None;
+
+        for line in text.lines() {
+            let mut words = line.split_whitespace();
+            let tag = words.next().unwrap_or_default();
+
+            // key=value pairs, file names are quoted.
+            let values: HashMap<&str, &str> = words
+                .filter_map(|word| word.split_once('='))
+                .map(|(key, value)| (key, value.trim_matches('"')))
+                .collect();
+            let number = |key: &str| -> Result<i32, String> {
+                values.get(key).ok_or(format!("{}: {} without {}", filename, tag, key))?
+                    .parse().map_err(|_| format!("{}: {} is not a number", filename, key))
+            };
+
+            match tag {
+                "common" => line_height = number("lineHeight")? as u32,
+                "page" if number("id")? == 0 => page = values.get("file").map(|file| file.to_string()),
+                "char" => {
+                    let Some(character) = char::from_u32(number("id")? as u32) else {
+                        continue;
+                    };
+
+                    glyphs.insert(character, Glyph {
+                        x: number("x")? as u32,
+                        y: number("y")? as u32,
+                        width: number("width")? as u32,
+                        height: number("height")? as u32,
+                        x_offset: number("xoffset")?,
+                        y_offset: number("yoffset")?,
+                        advance: number("xadvance")?
+                    });
+                },
+                _ => {}
+            }
+        }
+
+        let page = page.ok_or(format!("{}: no page 0", filename))?;
+        let page_path = Path::new(filename).parent().unwrap_or(Path::new("")).join(page);
+
+        Ok(Font { atlas: LoadedTexture::from_file(&page_path.to_string_lossy())?, glyphs, line_height })
+    }
+
+    /// Picks the loader by the file extension, pictures are taken as 16x6 grids starting at the space.
+    pub fn from_file(filename: &str) -> Result<Self, String> {
+        if filename.ends_with(".fnt") {
+            Font::from_bmfont(filename)
+        } else {
+            Font::from_grid(filename, 16, 6, ' ')
+        }
+    }
+
+    /// Fonts without lower case letters show the upper case ones.
+    fn glyph(&self, character: char) -> Option<&Glyph> {
+        self.glyphs.get(&character).or_else(|| self.glyphs.get(&character.to_ascii_uppercase()))
+    }
+
+    /// Width of a single line in screen pixels.
+    pub fn line_width(&self, line: &str, scale: u32) -> u32 {
+        let width: i32 = line.chars().filter_map(|character| self.glyph(character)).map(|glyph| glyph.advance).sum();
+        width.max(0) as u32 * scale
+    }
+
+    /// The lines the text gets drawn as, broken at `\n` and between words once a line gets wider than `wrap_width`.
+    pub fn lines(&self, text: &str, scale: u32, wrap_width: Option<u32>) -> Vec<String> {
+        let Some(wrap_width) = wrap_width else {
+            return text.lines().map(String::from).collect();
+        };
+
+        let mut lines = Vec::new();
+        for paragraph in text.lines() {
+            let mut line = String::new();
+
+            for word in paragraph.split(' ') {
+                let candidate = if line.is_empty() {word.to_string()} else {format!("{} {}", line, word)};
+
+                // A single word that is too long gets a line of its own.
+                if !line.is_empty() && self.line_width(&candidate, scale) > wrap_width {
+                    lines.push(std::mem::replace(&mut line, word.to_string()));
+                } else {
+                    line = candidate;
+                }
+            }
+
+            lines.push(line);
+        }
+
+        lines
+    }
+
+    /// Size of the text in screen pixels, as it would be drawn with the style.
+    pub fn measure(&self, text: &str, style: &TextStyle) -> (u32, u32) {
+        let lines = self.lines(text, style.scale, style.wrap_width);
+        let width = lines.iter().map(|line| self.line_width(line, style.scale)).max().unwrap_or(0);
+
+        (width, lines.len() as u32 * self.line_height * style.scale)
+    }
+
+    /// Draws the text with its top at `y`, characters the font doesn't have are skipped.
+    pub fn draw(&self, buffer: &mut FrameBuffer, text: &str, x: i32, y: i32, style: &TextStyle) {
+        let scale = style.scale as i32;
+
+        for (i, line) in self.lines(text, style.scale, style.wrap_width).iter().enumerate() {
+            let width = self.line_width(line, style.scale) as i32;
+            let mut pen_x = match style.align {
+                Align::Left => x,
+                Align::Center => x - width / 2,
+                Align::Right => x - width
+            };
+            let top = y + i as i32 * (self.line_height as i32 * scale);
+
+            for character in line.chars() {
+                let Some(glyph) = self.glyph(character) else {
+                    continue;
+                };
+
+                self.draw_glyph(buffer, glyph, pen_x + glyph.x_offset * scale, top + glyph.y_offset * scale, style);
+                pen_x += glyph.advance * scale;
+            }
+        }
+    }
+
+    fn draw_glyph(&self, buffer: &mut FrameBuffer, glyph: &Glyph, left: i32, top: i32, style: &TextStyle) {
+        let scale = style.scale as i32;
+
+        for gy in 0..glyph.height {
+            for gx in 0..glyph.width {
+                let Some((r, g, b, a)) = self.atlas.get_pixel((glyph.x + gx) as usize, (glyph.y + gy) as usize) else {
+                    continue;
+                };
+
+                let coverage = a as u32 * r.max(g).max(b) as u32 / 255;
+                if coverage == 0 {
+                    continue;
+                }
+
+                let alpha = (coverage * style.color.a as u32 / 255) as u8;
+                let color = Color::RGBA(style.color.r, style.color.g, style.color.b, alpha);
+                buffer.fill_rect(left + gx as i32 * scale, top + gy as i32 * scale, scale, scale, color);
+            }
+        }
+    }
+}
@@ -0,0 +1,140 @@
+use sdl2::{pixels::{Color, PixelFormatEnum}, render::{BlendMode, Canvas}, video::Window};
+
+use crate::profiler::{self, Stage};
+
+/// Software framebuffer in RGBA32, gets copied onto the canvas once the frame is done.
+pub struct FrameBuffer {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+    /// Distance of the wall at every pixel, so sprites can be hidden behind them.
+    pub depth: Vec<f64>
+}
+
+impl FrameBuffer {
+    pub fn new(width: u32, height: u32) -> Self {
+        FrameBuffer {
+            width,
+            height,
+            pixels: vec![0; (width * height * 4) as usize],
+            depth: vec![f64::INFINITY; (width * height) as usize]
+        }
+    }
+
+    /// Follows the window size, the contents are lost if the size changed.
+    pub fn resize(&mut self, width: u32, height: u32) {
+        if self.width == width && self.height == height {
+            return;
+        }
+
+        self.width = width;
+        self.height = height;
+        self.pixels = vec![0; (width * height * 4) as usize];
+        self.depth = vec![f64::INFINITY; (width * height) as usize];
+    }
+
+    pub fn clear(&mut self, color: Color) {
+        for pixel in self.pixels.chunks_exact_mut(4) {
+            pixel.copy_from_slice(&[color.r, color.g, color.b, color.a]);
+        }
+        self.depth.fill(f64::INFINITY);
+    }
+
+    pub fn set_pixel(&mut self, x: i32, y: i32, color: Color) {
+        if x < 0 || y < 0 || x >= self.width as i32 || y >= self.height as i32 {
+            return;
+        }
+
+        let offset = (y as usize * self.width as usize + x as usize) * 4;
+        self.pixels[offset..offset+4].copy_from_slice(&[color.r, color.g, color.b, color.a]);
+    }
+
+    /// Draws a vertical line, both ends are inclusive.
+    pub fn ver_line(&mut self, x: i32, draw_start: i32, draw_end: i32, color: Color) {
+        for y in draw_start..=draw_end {
+            self.set_pixel(x, y, color);
+        }
+    }
+
+    /// Marks a vertical line as covered by a wall at some distance, both ends are inclusive.
+    pub fn set_depth(&mut self, x: i32, draw_start: i32, draw_end: i32, depth: f64) {
+        if x < 0 || x >= self.width as i32 {
+            return;
+        }
+
+        for y in draw_start.max(0)..=draw_end.min(self.height as i32 - 1) {
+            self.depth[y as usize * self.width as usize + x as usize] = depth;
+        }
+    }
+
+    /// Only draws the pixel if it is closer than the wall there.
+    pub fn set_pixel_in_front(&mut self, x: i32, y: i32, depth: f64, color: Color) {
+        if x < 0 || y < 0 || x >= self.width as i32 || y >= self.height as i32 {
+            return;
+        }
+
+        if depth < self.depth[y as usize * self.width as usize + x as usize] {
+            self.set_pixel(x, y, color);
+        }
+    }
+
+    /// Draws a see-through color over the pixel, the alpha of the color says how much.
+    pub fn blend_pixel(&mut self, x: i32, y: i32, color: Color) {
+        if x < 0 || y < 0 || x >= self.width as i32 || y >= self.height as i32 {
+            return;
+        }
+
+        let offset = (y as usize * self.width as usize + x as usize) * 4;
+        let pixel = &mut self.pixels[offset..offset+4];
+
+        // Straight alpha "over", the buffer itself may be see-through too (the HUD is).
+        let src_a = color.a as f64 / 255.0;
+        let dst_a = pixel[3] as f64 / 255.0;
+        let out_a = src_a + dst_a * (1.0 - src_a);
+        if out_a <= 0.0 {
+            return;
+        }
+
+        for (channel, src) in pixel.iter_mut().zip([color.r, color.g, color.b]) {
+            *channel = ((src as f64 * src_a + *channel as f64 * dst_a * (1.0 - src_a)) / out_a) as u8;
+        }
+        pixel[3] = (out_a * 255.0) as u8;
+    }
+
+    /// Filled rectangle, blended if the color is see-through.
+    pub fn fill_rect(&mut self, x: i32, y: i32, width: i32, height: i32, color: Color) {
+        for py in y.max(0)..(y + height).min(self.height as i32) {
+            for px in x.max(0)..(x + width).min(self.width as i32) {
+                if color.a == 255 {
+                    self.set_pixel(px, py, color);
+                } else {
+                    self.blend_pixel(px, py, color);
+                }
+            }
+        }
+    }
+
+    pub fn present(&self, canvas: &mut Canvas<Window>) -> Result<(), String> {
+        profiler::scope!(Stage::Present);
+        let texture_creator = canvas.texture_creator();
+        let mut texture = texture_creator
+            .create_texture_streaming(PixelFormatEnum::RGBA32, self.width, self.height)
+            .map_err(|e| e.to_string())?;
+
+        texture.update(None, &self.pixels, self.width as usize * 4).map_err(|e| e.to_string())?;
+        canvas.copy(&texture, None, None)
+    }
+
+    /// Draws the buffer over what is already on the canvas, transparent pixels leave it alone.
+    pub fn present_overlay(&self, canvas: &mut Canvas<Window>) -> Result<(), String> {
+        profiler::scope!(Stage::Present);
+        let texture_creator = canvas.texture_creator();
+        let mut texture = texture_creator
+            .create_texture_streaming(PixelFormatEnum::RGBA32, self.width, self.height)
+            .map_err(|e| e.to_string())?;
+
+        texture.set_blend_mode(BlendMode::Blend);
+        texture.update(None, &self.pixels, self.width as usize * 4).map_err(|e| e.to_string())?;
+        canvas.copy(&texture, None, None)
+    }
+}
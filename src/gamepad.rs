@@ -0,0 +1,190 @@
+use std::collections::{HashMap, HashSet};
+
+use sdl2::controller::{Axis, Button, GameController};
+use sdl2::event::Event;
+use sdl2::GameControllerSubsystem;
+
+use crate::config::PAD_TRIGGER_THRESHOLD;
+use crate::data::Vec2;
+
+/// Something on a controller an action can be bound to, the triggers count as buttons once pulled far enough.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PadInput {
+    Button(Button),
+    Trigger(Axis)
+}
+
+impl PadInput {
+    /// SDL's names, like `a`, `leftshoulder`, `dpup` or `righttrigger`.
+    pub fn from_name(name: &str) -> Option<PadInput> {
+        match Axis::from_string(name) {
+            Some(axis @ (Axis::TriggerLeft | Axis::TriggerRight)) => Some(PadInput::Trigger(axis)),
+            Some(_) => None,
+            None => Button::from_string(name).map(PadInput::Button)
+        }
+    }
+
+    pub fn name(&self) -> String {
+        match self {
+            PadInput::Button(button) => button.string(),
+            PadInput::Trigger(axis) => axis.string()
+        }
+    }
+}
+
+/// State of all connected controllers merged together, only ever changed by SDL events.
+/// That way synthetic events can drive it just like real hardware.
+#[derive(Default)]
+pub struct Gamepad {
+    /// SDL closes a controller once it gets dropped.
+    controllers: Vec<GameController>,
+    /// -1.0 - 1.0 for the sticks, 0.0 - 1.0 for the triggers.
+    axes: HashMap<Axis, f64>,
+    buttons: HashSet<Button>
+}
+
+impl Gamepad {
+    /// Opens a controller, called for every ControllerDeviceAdded event (SDL also sends those on startup).
+    pub fn connect(&mut self, subsystem: &GameControllerSubsystem, joystick_index: u32) -> Result<(), String> {
+        let controller = subsystem.open(joystick_index).map_err(|e| e.to_string())?;
+        self.controllers.push(controller);
+
+        Ok(())
+    }
+
+    /// Updates the state, returns the input that just got pressed so toggles only fire once.
+    pub fn handle_event(&mut self, event: &Event) -> Option<PadInput> {
+        match *event {
+            Event::ControllerAxisMotion { axis, value, .. } => {
+                let old = self.axis(axis);
+                let new = (value as f64 / i16::MAX as f64).clamp(-1.0, 1.0);
+                self.axes.insert(axis, new);
+
+                let trigger = matches!(axis, Axis::TriggerLeft | Axis::TriggerRight);
+                (trigger && old < PAD_TRIGGER_THRESHOLD && new >= PAD_TRIGGER_THRESHOLD).then_some(PadInput::Trigger(axis))
+            },
+            Event::ControllerButtonDown { button, .. } => {
+                self.buttons.insert(button).then_some(PadInput::Button(button))
+            },
+            Event::ControllerButtonUp { button, .. } => {
+                self.buttons.remove(&button);
+                None
+            },
+            Event::ControllerDeviceRemoved { which, .. } => {
+                self.controllers.retain(|controller| controller.instance_id() != which);
+
+                // Don't keep walking with a stick that is gone.
+                self.axes.clear();
+                self.buttons.clear();
+                None
+            },
+            _ => None
+        }
+    }
+
+    pub fn axis(&self, axis: Axis) -> f64 {
+        self.axes.get(&axis).copied().unwrap_or(0.0)
+    }
+
+    pub fn is_down(&self, input: PadInput) -> bool {
+        match input {
+            PadInput::Button(button) => self.buttons.contains(&button),
+            PadInput::Trigger(axis) => self.axis(axis) >= PAD_TRIGGER_THRESHOLD
+        }
+    }
+
+    /// A stick with a round dead zone, the rest gets stretched so it still reaches 1.0.
+    /// `curve` > 1.0 makes small movements finer, 1.0 keeps it linear.
+    pub fn stick(&self, x: Axis, y: Axis, dead_zone: f64, curve: f64) -> Vec2<f64> {
+        let (x, y) = (self.axis(x), self.axis(y));
+        let length = (x * x + y * y).sqrt();
+
+        if length <= dead_zone {
+            return Vec2::new(0.0, 0.0);
+        }
+
+        let scaled = ((length - dead_zone) / (1.0 - dead_zone)).min(1.0).powf(curve);
+        Vec2::new(x / length * scaled, y / length * scaled)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn axis_motion(axis: Axis, value: f64) -> Event {
+        Event::ControllerAxisMotion { timestamp: 0, which: 0, axis, value: (value * i16::MAX as f64) as i16 }
+    }
+
+    fn button_down(button: Button) -> Event {
+        Event::ControllerButtonDown { timestamp: 0, which: 0, button }
+    }
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-3
+    }
+
+    #[test]
+    fn dead_zone() {
+        let mut gamepad = Gamepad::default();
+
+        gamepad.handle_event(&axis_motion(Axis::RightX, 0.15));
+        gamepad.handle_event(&axis_motion(Axis::RightY, 0.1));
+        let look = gamepad.stick(Axis::RightX, Axis::RightY, 0.2, 1.0);
+        assert_eq!((look.x, look.y), (0.0, 0.0));
+
+        // Right past the dead zone it starts from 0.0 again instead of jumping.
+        gamepad.handle_event(&axis_motion(Axis::RightY, 0.0));
+        gamepad.handle_event(&axis_motion(Axis::RightX, 0.25));
+        let look = gamepad.stick(Axis::RightX, Axis::RightY, 0.2, 1.0);
+        assert!(close(look.x, 0.0625) && look.y == 0.0);
+
+        gamepad.handle_event(&axis_motion(Axis::RightX, -1.0));
+        assert!(close(gamepad.stick(Axis::RightX, Axis::RightY, 0.2, 1.0).x, -1.0));
+    }
+
+    #[test]
+    fn response_curve() {
+        let mut gamepad = Gamepad::default();
+        gamepad.handle_event(&axis_motion(Axis::LeftY, 0.6));
+
+        assert!(close(gamepad.stick(Axis::LeftX, Axis::LeftY, 0.2, 1.0).y, 0.5));
+        assert!(close(gamepad.stick(Axis::LeftX, Axis::LeftY, 0.2, 2.0).y, 0.25));
+
+        // All the way stays all the way.
+        gamepad.handle_event(&axis_motion(Axis::LeftY, 1.0));
+        assert!(close(gamepad.stick(Axis::LeftX, Axis::LeftY, 0.2, 2.0).y, 1.0));
+    }
+
+    #[test]
+    fn presses_fire_once() {
+        let mut gamepad = Gamepad::default();
+
+        assert!(gamepad.handle_event(&button_down(Button::A)) == Some(PadInput::Button(Button::A)));
+        assert!(gamepad.handle_event(&button_down(Button::A)).is_none());
+        assert!(gamepad.is_down(PadInput::Button(Button::A)));
+
+        gamepad.handle_event(&Event::ControllerButtonUp { timestamp: 0, which: 0, button: Button::A });
+        assert!(!gamepad.is_down(PadInput::Button(Button::A)));
+
+        // The trigger only counts once it gets pulled past the threshold.
+        assert!(gamepad.handle_event(&axis_motion(Axis::TriggerRight, 0.3)).is_none());
+        assert!(gamepad.handle_event(&axis_motion(Axis::TriggerRight, 0.8)) == Some(PadInput::Trigger(Axis::TriggerRight)));
+        assert!(gamepad.handle_event(&axis_motion(Axis::TriggerRight, 1.0)).is_none());
+        assert!(gamepad.is_down(PadInput::Trigger(Axis::TriggerRight)));
+    }
+
+    #[test]
+    fn removing_a_controller_lets_go() {
+        let mut gamepad = Gamepad::default();
+        gamepad.handle_event(&axis_motion(Axis::LeftY, -1.0));
+        gamepad.handle_event(&axis_motion(Axis::TriggerLeft, 1.0));
+        gamepad.handle_event(&button_down(Button::DPadUp));
+
+        gamepad.handle_event(&Event::ControllerDeviceRemoved { timestamp: 0, which: 0 });
+
+        assert_eq!(gamepad.axis(Axis::LeftY), 0.0);
+        assert!(!gamepad.is_down(PadInput::Trigger(Axis::TriggerLeft)));
+        assert!(!gamepad.is_down(PadInput::Button(Button::DPadUp)));
+    }
+}
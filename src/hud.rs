@@ -0,0 +1,111 @@
+use std::f64::consts::PI;
+
+use sdl2::pixels::Color;
+
+use crate::config::{HUD_BASE_HEIGHT, WEAPON_BOB};
+use crate::data::{GameData, PlayerData};
+use crate::font::{Align, TextStyle};
+use crate::framebuffer::FrameBuffer;
+
+const STATUS_BAR_HEIGHT: i32 = 32;
+const STATUS_BAR_COLOR: Color = Color::RGB(60, 60, 60);
+const STATUS_BAR_EDGE: Color = Color::RGB(100, 100, 100);
+const NUMBER_COLOR: Color = Color::RGB(200, 30, 30);
+const LABEL_COLOR: Color = Color::RGB(200, 200, 200);
+const MESSAGE_COLOR: Color = Color::RGB(220, 220, 220);
+const FPS_BACKGROUND: Color = Color::RGBA(0, 0, 0, 128);
+
+/// Draws the HUD into a transparent buffer that goes over the 3D view: weapon, crosshair, status bar, messages, the FPS counter and screen flashes.
+/// The status bar numbers use GameData::font, made for the built-in 3x5 font.
+/// Everything is made for a HUD_BASE_HEIGHT pixel high screen and scaled up by whole pixels.
+pub fn draw_hud(player: &PlayerData, game_data: &GameData, buffer: &mut FrameBuffer) {
+    let width = buffer.width as i32;
+    let height = buffer.height as i32;
+    let scale = (buffer.height / HUD_BASE_HEIGHT).max(1) as i32;
+    let bar_top = height - STATUS_BAR_HEIGHT * scale;
+
+    draw_weapon(player, game_data, buffer, bar_top, scale);
+
+    // Crosshair, the shots go straight through the middle of the screen.
+    let (center_x, center_y) = (width / 2, height / 2);
+    buffer.fill_rect(center_x - 4 * scale, center_y, 3 * scale, scale, Color::WHITE);
+    buffer.fill_rect(center_x + 2 * scale, center_y, 3 * scale, scale, Color::WHITE);
+    buffer.fill_rect(center_x, center_y - 4 * scale, scale, 3 * scale, Color::WHITE);
+    buffer.fill_rect(center_x, center_y + 2 * scale, scale, 3 * scale, Color::WHITE);
+
+    // Status bar.
+    buffer.fill_rect(0, bar_top, width, STATUS_BAR_HEIGHT * scale, STATUS_BAR_COLOR);
+    buffer.fill_rect(0, bar_top, width, scale, STATUS_BAR_EDGE);
+
+    let fields = [
+        ("AMMO", player.weapons.ammo_left().to_string()),
+        ("HEALTH", format!("{}%", player.health)),
+        ("ARMOR", format!("{}%", player.armor))
+    ];
+
+    for (i, (label, value)) in fields.iter().enumerate() {
+        let center = width * (2 * i as i32 + 1) / 6;
+        let font = &game_data.font;
+        font.draw(buffer, value, center, bar_top + 5 * scale, &TextStyle::new(NUMBER_COLOR, 3 * scale as u32).align(Align::Center));
+        font.draw(buffer, label, center, bar_top + 23 * scale, &TextStyle::new(LABEL_COLOR, scale as u32).align(Align::Center));
+    }
+
+    // The latest message in the top left corner, long ones wrap at half the screen.
+    if let Some((message, _)) = player.message {
+        let style = TextStyle::new(MESSAGE_COLOR, scale as u32).wrap(width as u32 / 2);
+        game_data.font.draw(buffer, message, 2 * scale, 2 * scale, &style);
+    }
+
+    if game_data.settings.show_fps {
+        let text = format!("{:.0} FPS", game_data.fps);
+        let style = TextStyle::new(Color::WHITE, scale as u32).align(Align::Right);
+        let (text_width, text_height) = game_data.font.measure(&text, &style);
+        buffer.fill_rect(width - text_width as i32 - 3 * scale, 0, text_width as i32 + 3 * scale, text_height as i32 + 3 * scale, FPS_BACKGROUND);
+        game_data.font.draw(buffer, &text, width - 2 * scale, 2 * scale, &style);
+    }
+
+    // Screen flashes, getting hurt wins over picking something up.
+    if player.damage_flash > 0 {
+        let alpha = (player.damage_flash * 6).min(160) as u8;
+        buffer.fill_rect(0, 0, width, height, Color::RGBA(255, 0, 0, alpha));
+    } else if player.pickup_flash > 0 {
+        let alpha = (player.pickup_flash * 16).min(96) as u8;
+        buffer.fill_rect(0, 0, width, height, Color::RGBA(255, 215, 0, alpha));
+    }
+}
+
+/// The weapon in front of the player, it swings with the head-bob and kicks back while firing.
+fn draw_weapon(player: &PlayerData, game_data: &GameData, buffer: &mut FrameBuffer, bar_top: i32, scale: i32) {
+    let weapons = &player.weapons;
+    let weapon = weapons.weapon();
+
+    let frame = weapons.fire_progress()
+        .and_then(|progress| weapon.fire_frames.chars().nth((progress * weapon.fire_frames.len() as f64) as usize))
+        .unwrap_or('A');
+
+    let Some((texture, _)) = game_data.sprites.get(weapon.sprite, frame, 0) else {
+        return;
+    };
+
+    let swing = WEAPON_BOB * scale as f64;
+    let kick = weapons.fire_progress().map_or(0.0, |progress| (progress * PI).sin() * 8.0 * scale as f64);
+    let left = buffer.width as i32 / 2 - texture.width as i32 * scale / 2 + (player.bob.cos() * swing) as i32;
+    let top = bar_top - texture.height as i32 * scale + (player.bob.sin().abs() * swing + kick) as i32 + swing as i32;
+
+    for tex_y in 0..texture.height as usize {
+        for tex_x in 0..texture.width as usize {
+            let Some((r, g, b, a)) = texture.get_pixel(tex_x, tex_y) else {
+                continue;
+            };
+
+            // Same transparency as the sprites in the world.
+            if a == 0 || (r, g, b) == (0, 0, 0) {
+                continue;
+            }
+
+            let x = left + tex_x as i32 * scale;
+            let y = top + tex_y as i32 * scale;
+            buffer.fill_rect(x, y, scale, scale, Color::RGB(r, g, b));
+        }
+    }
+}
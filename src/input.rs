@@ -0,0 +1,211 @@
+use std::collections::HashMap;
+
+use sdl2::controller::{Axis, Button};
+use sdl2::keyboard::{KeyboardState, Scancode};
+
+use crate::gamepad::{Gamepad, PadInput};
+
+/// Everything a key can be bound to.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Action {
+    MoveForward,
+    MoveBackward,
+    StrafeLeft,
+    StrafeRight,
+    TurnLeft,
+    TurnRight,
+    LookUp,
+    LookDown,
+    Jump,
+    Crouch,
+    Fire,
+    NextWeapon,
+    Use,
+    ToggleMap,
+    ToggleTextures,
+    TogglePalette,
+    NextRenderMode,
+    ToggleConsole,
+    QuickSave,
+    QuickLoad,
+    Menu
+}
+
+impl Action {
+    pub const ALL: [Action; 21] = [
+        Action::MoveForward,
+        Action::MoveBackward,
+        Action::StrafeLeft,
+        Action::StrafeRight,
+        Action::TurnLeft,
+        Action::TurnRight,
+        Action::LookUp,
+        Action::LookDown,
+        Action::Jump,
+        Action::Crouch,
+        Action::Fire,
+        Action::NextWeapon,
+        Action::Use,
+        Action::ToggleMap,
+        Action::ToggleTextures,
+        Action::TogglePalette,
+        Action::NextRenderMode,
+        Action::ToggleConsole,
+        Action::QuickSave,
+        Action::QuickLoad,
+        Action::Menu
+    ];
+
+    /// The name used in the settings file.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Action::MoveForward => "move_forward",
+            Action::MoveBackward => "move_backward",
+            Action::StrafeLeft => "strafe_left",
+            Action::StrafeRight => "strafe_right",
+            Action::TurnLeft => "turn_left",
+            Action::TurnRight => "turn_right",
+            Action::LookUp => "look_up",
+            Action::LookDown => "look_down",
+            Action::Jump => "jump",
+            Action::Crouch => "crouch",
+            Action::Fire => "fire",
+            Action::NextWeapon => "next_weapon",
+            Action::Use => "use",
+            Action::ToggleMap => "toggle_map",
+            Action::ToggleTextures => "toggle_textures",
+            Action::TogglePalette => "toggle_palette",
+            Action::NextRenderMode => "next_render_mode",
+            Action::ToggleConsole => "toggle_console",
+            Action::QuickSave => "quick_save",
+            Action::QuickLoad => "quick_load",
+            Action::Menu => "menu"
+        }
+    }
+
+    /// Settings files from before the menu call it `quit`, it used to quit right away.
+    pub fn from_name(name: &str) -> Option<Action> {
+        match name {
+            "quit" => Some(Action::Menu),
+            _ => Action::ALL.into_iter().find(|action| action.name() == name)
+        }
+    }
+
+    fn default_keys(&self) -> &'static [Scancode] {
+        match self {
+            Action::MoveForward => &[Scancode::W, Scancode::Up],
+            Action::MoveBackward => &[Scancode::S, Scancode::Down],
+            Action::StrafeLeft => &[Scancode::A],
+            Action::StrafeRight => &[Scancode::D],
+            Action::TurnLeft => &[Scancode::Left],
+            Action::TurnRight => &[Scancode::Right],
+            Action::LookUp => &[Scancode::PageUp],
+            Action::LookDown => &[Scancode::PageDown],
+            Action::Jump => &[Scancode::Space],
+            Action::Crouch => &[Scancode::LCtrl, Scancode::C],
+            Action::Fire => &[Scancode::F],
+            Action::NextWeapon => &[Scancode::Q],
+            Action::Use => &[Scancode::E],
+            Action::ToggleMap => &[Scancode::M],
+            Action::ToggleTextures => &[Scancode::T],
+            Action::TogglePalette => &[Scancode::P],
+            Action::NextRenderMode => &[Scancode::R],
+            Action::ToggleConsole => &[Scancode::Grave],
+            Action::QuickSave => &[Scancode::F6],
+            Action::QuickLoad => &[Scancode::F9],
+            Action::Menu => &[Scancode::Escape]
+        }
+    }
+
+    /// The sticks aren't bound, they always move and look.
+    fn default_pad(&self) -> Vec<PadInput> {
+        match self {
+            Action::MoveForward => vec![PadInput::Button(Button::DPadUp)],
+            Action::MoveBackward => vec![PadInput::Button(Button::DPadDown)],
+            Action::StrafeLeft => vec![PadInput::Button(Button::DPadLeft)],
+            Action::StrafeRight => vec![PadInput::Button(Button::DPadRight)],
+            Action::Jump => vec![PadInput::Button(Button::A)],
+            Action::Crouch => vec![PadInput::Button(Button::B), PadInput::Trigger(Axis::TriggerLeft)],
+            Action::Fire => vec![PadInput::Trigger(Axis::TriggerRight)],
+            Action::NextWeapon => vec![PadInput::Button(Button::LeftShoulder)],
+            Action::Use => vec![PadInput::Button(Button::X)],
+            Action::ToggleMap => vec![PadInput::Button(Button::Back)],
+            Action::ToggleTextures => vec![PadInput::Button(Button::Y)],
+            Action::NextRenderMode => vec![PadInput::Button(Button::RightShoulder)],
+            Action::Menu => vec![PadInput::Button(Button::Start)],
+            _ => Vec::new()
+        }
+    }
+}
+
+/// Which keys and controller buttons trigger which action, an action can have any number of them.
+pub struct Bindings {
+    pub keys: HashMap<Action, Vec<Scancode>>,
+    pub pad: HashMap<Action, Vec<PadInput>>
+}
+
+impl Default for Bindings {
+    fn default() -> Self {
+        Bindings {
+            keys: Action::ALL.iter().map(|action| (*action, action.default_keys().to_vec())).collect(),
+            pad: Action::ALL.iter().map(|action| (*action, action.default_pad())).collect()
+        }
+    }
+}
+
+impl Bindings {
+    /// Replaces the keys of an action.
+    pub fn bind(&mut self, action: Action, keys: Vec<Scancode>) {
+        self.keys.insert(action, keys);
+    }
+
+    pub fn bind_pad(&mut self, action: Action, inputs: Vec<PadInput>) {
+        self.pad.insert(action, inputs);
+    }
+
+    /// True while any key or controller button of the action is held down.
+    pub fn is_held(&self, action: Action, keyboard: &KeyboardState, gamepad: &Gamepad) -> bool {
+        self.keys.get(&action).is_some_and(|keys| keys.iter().any(|&key| keyboard.is_scancode_pressed(key)))
+            || self.pad.get(&action).is_some_and(|inputs| inputs.iter().any(|&input| gamepad.is_down(input)))
+    }
+
+    /// The actions a pressed key triggers.
+    pub fn actions(&self, key: Scancode) -> impl Iterator<Item = Action> + '_ {
+        Action::ALL.into_iter().filter(move |action| self.keys.get(action).is_some_and(|keys| keys.contains(&key)))
+    }
+
+    /// The actions a pressed controller button or trigger triggers.
+    pub fn pad_actions(&self, input: PadInput) -> impl Iterator<Item = Action> + '_ {
+        Action::ALL.into_iter().filter(move |action| self.pad.get(action).is_some_and(|inputs| inputs.contains(&input)))
+    }
+
+    /// SDL key names separated by commas, like `W, Up`.
+    pub fn parse_keys(text: &str) -> Result<Vec<Scancode>, String> {
+        text.split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(|name| Scancode::from_name(name).ok_or_else(|| format!("unknown key {}", name)))
+            .collect()
+    }
+
+    /// SDL controller names separated by commas, like `a, righttrigger`.
+    pub fn parse_pad(text: &str) -> Result<Vec<PadInput>, String> {
+        text.split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(|name| PadInput::from_name(name).ok_or_else(|| format!("unknown controller button {}", name)))
+            .collect()
+    }
+
+    pub fn keys_to_string(&self, action: Action) -> String {
+        self.keys.get(&action)
+            .map(|keys| keys.iter().map(|key| key.name()).collect::<Vec<_>>().join(", "))
+            .unwrap_or_default()
+    }
+
+    pub fn pad_to_string(&self, action: Action) -> String {
+        self.pad.get(&action)
+            .map(|inputs| inputs.iter().map(|input| input.name()).collect::<Vec<_>>().join(", "))
+            .unwrap_or_default()
+    }
+}
@@ -0,0 +1,381 @@
+use std::fs;
+
+use crate::config::{GRID_CEILING_FLAT, GRID_FLOOR_FLAT, MAX_STEP_HEIGHT, PLAYER_HEIGHT, PLAYER_RADIUS, SKY_FLAT};
+use crate::data::Vec2;
+use crate::map::{intersect, point_segment_dist, Map};
+use crate::trigger::{Activation, Trigger, TriggerAction};
+
+/// Linedef flag, the line blocks the player even if it is two-sided.
+pub const BLOCKING: u16 = 0x0001;
+
+/// Line between two vertices, the front side is to the right when looking from start to end.
+pub struct Linedef {
+    pub start: usize,
+    pub end: usize,
+    pub flags: u16,
+    pub special: u16,
+    pub tag: u16,
+    pub front: Option<usize>,
+    pub back: Option<usize>
+}
+
+/// One side of a linedef with its textures, empty names mean no texture.
+pub struct Sidedef {
+    pub x_offset: f64,
+    pub y_offset: f64,
+    pub upper: String,
+    pub lower: String,
+    pub middle: String,
+    pub sector: usize
+}
+
+pub struct LevelSector {
+    pub floor: f64,
+    pub ceiling: f64,
+    pub floor_flat: String,
+    pub ceiling_flat: String,
+    pub light: u8,
+    pub special: u16,
+    pub tag: u16
+}
+
+pub struct Thing {
+    pub pos: Vec2<f64>,
+    /// In radians, 0 looks along +x.
+    pub angle: f64,
+    pub kind: u16,
+    pub flags: u16
+}
+
+/// Linedef/sector based level geometry, as used by Doom. All units are in map units (1.0 = one grid cell).
+pub struct Level {
+    pub vertices: Vec<Vec2<f64>>,
+    pub linedefs: Vec<Linedef>,
+    pub sidedefs: Vec<Sidedef>,
+    pub sectors: Vec<LevelSector>,
+    pub things: Vec<Thing>,
+    /// Texture name of the sky shown above SKY_FLAT ceilings.
+    pub sky: Option<String>,
+    /// Lump name for WAD maps, a file otherwise.
+    pub music: Option<String>,
+    /// Switches and walk-over triggers, see trigger.rs.
+    pub triggers: Vec<Trigger>
+}
+
+impl Level {
+    /// Reads the in-repo level description, one definition per line:
+    ///
+    /// vertex <x> <y>
+    /// sector <floor> <ceiling> <floor flat> <ceiling flat> <light> [special] [tag]
+    /// side <sector> <upper> <lower> <middle> [x offset] [y offset]
+    /// line <start vertex> <end vertex> <front side> <back side> [special] [tag]
+    /// thing <x> <y> <angle in degrees> <kind>
+    /// sky <texture>
+    /// music <file>
+    /// switch <line> <pressed texture> <action>
+    /// walk <cell x> <cell y> <action>
+    ///
+    /// Texture names refer to pics/<name>.png, `-` means no texture or no back side.
+    /// The actions are listed at TriggerAction::parse().
+    pub fn from_file(filename: &str) -> Result<Self, String> {
+        let text = fs::read_to_string(filename).map_err(|e| format!("{}: {}", filename, e))?;
+
+        let mut level = Level {
+            vertices: Vec::new(),
+            linedefs: Vec::new(),
+            sidedefs: Vec::new(),
+            sectors: Vec::new(),
+            things: Vec::new(),
+            sky: None,
+            music: None,
+            triggers: Vec::new()
+        };
+
+        for (number, line) in text.lines().enumerate() {
+            let words: Vec<&str> = line.split('#').next().unwrap_or("").split_whitespace().collect();
+            let error = |message: &str| format!("{}:{}: {}", filename, number + 1, message);
+
+            let number_at = |i: usize| -> Result<f64, String> {
+                words.get(i)
+                    .ok_or_else(|| error("missing value"))?
+                    .parse::<f64>()
+                    .map_err(|e| error(&e.to_string()))
+            };
+            let optional_number = |i: usize| if words.len() > i {number_at(i)} else {Ok(0.0)};
+            let name_at = |i: usize| -> Result<String, String> {
+                let name = words.get(i).ok_or_else(|| error("missing name"))?;
+                Ok(if *name == "-" {String::new()} else {name.to_string()})
+            };
+            let index_at = |i: usize| -> Result<Option<usize>, String> {
+                if words.get(i) == Some(&"-") {
+                    return Ok(None);
+                }
+                Ok(Some(number_at(i)? as usize))
+            };
+
+            match words.first() {
+                None => continue,
+                Some(&"vertex") => level.vertices.push(Vec2::new(number_at(1)?, number_at(2)?)),
+                Some(&"sector") => level.sectors.push(LevelSector {
+                    floor: number_at(1)?,
+                    ceiling: number_at(2)?,
+                    floor_flat: name_at(3)?,
+                    ceiling_flat: name_at(4)?,
+                    light: number_at(5)?.clamp(0.0, 255.0) as u8,
+                    special: optional_number(6)? as u16,
+                    tag: optional_number(7)? as u16
+                }),
+                Some(&"side") => level.sidedefs.push(Sidedef {
+                    sector: number_at(1)? as usize,
+                    upper: name_at(2)?,
+                    lower: name_at(3)?,
+                    middle: name_at(4)?,
+                    x_offset: optional_number(5)?,
+                    y_offset: optional_number(6)?
+                }),
+                Some(&"line") => level.linedefs.push(Linedef {
+                    start: number_at(1)? as usize,
+                    end: number_at(2)? as usize,
+                    flags: 0,
+                    special: optional_number(5)? as u16,
+                    tag: optional_number(6)? as u16,
+                    front: index_at(3)?,
+                    back: index_at(4)?
+                }),
+                Some(&"thing") => level.things.push(Thing {
+                    pos: Vec2::new(number_at(1)?, number_at(2)?),
+                    angle: number_at(3)?.to_radians(),
+                    kind: number_at(4)? as u16,
+                    flags: 0
+                }),
+                Some(&"sky") => level.sky = Some(name_at(1)?).filter(|name| !name.is_empty()),
+                Some(&"music") => level.music = Some(name_at(1)?).filter(|name| !name.is_empty()),
+                Some(&"switch") => level.triggers.push(Trigger::new(
+                    Activation::Switch { line: number_at(1)? as usize, texture: Some(name_at(2)?) },
+                    TriggerAction::parse(words.get(3..).unwrap_or_default()).map_err(|e| error(&e))?
+                )),
+                Some(&"walk") => level.triggers.push(Trigger::new(
+                    Activation::Cell { x: number_at(1)?.floor() as i32, y: number_at(2)?.floor() as i32 },
+                    TriggerAction::parse(words.get(3..).unwrap_or_default()).map_err(|e| error(&e))?
+                )),
+                Some(other) => return Err(error(&format!("unknown definition {}", other)))
+            }
+        }
+
+        level.validate(filename)?;
+        Ok(level)
+    }
+
+    /// Turns the walls of the grid into one-sided linedefs around a single sector.
+    /// Segment tiles become lines facing both ways, so thin walls can be seen from either side.
+    /// A grid with a sky gets the sky flat as its ceiling.
+    pub fn from_grid(map: &Map) -> Self {
+        let mut level = Level {
+            vertices: Vec::new(),
+            linedefs: Vec::new(),
+            sidedefs: Vec::new(),
+            sectors: vec![LevelSector {
+                floor: 0.0,
+                ceiling: 1.0,
+                floor_flat: GRID_FLOOR_FLAT.to_string(),
+                ceiling_flat: if map.sky.is_some() {SKY_FLAT} else {GRID_CEILING_FLAT}.to_string(),
+                light: 255,
+                special: 0,
+                tag: 0
+            }],
+            things: Vec::new(),
+            sky: map.sky.clone(),
+            music: None,
+            triggers: Vec::new()
+        };
+
+        let mut add_line = |a: Vec2<f64>, b: Vec2<f64>, wall: i32| {
+            level.sidedefs.push(Sidedef {
+                x_offset: 0.0,
+                y_offset: 0.0,
+                upper: String::new(),
+                lower: String::new(),
+                middle: wall_texture_name(wall).to_string(),
+                sector: 0
+            });
+
+            level.vertices.push(a);
+            level.vertices.push(b);
+            level.linedefs.push(Linedef {
+                start: level.vertices.len() - 2,
+                end: level.vertices.len() - 1,
+                flags: 0,
+                special: 0,
+                tag: 0,
+                front: Some(level.sidedefs.len() - 1),
+                back: None
+            });
+        };
+
+        for x in 0..map.width as i32 {
+            for y in 0..map.height as i32 {
+                let (fx, fy) = (x as f64, y as f64);
+
+                for segment in map.segments(x, y) {
+                    let a = Vec2::new(fx + segment.a.x, fy + segment.a.y);
+                    let b = Vec2::new(fx + segment.b.x, fy + segment.b.y);
+                    add_line(a, b, segment.wall);
+                    add_line(b, a, segment.wall);
+                }
+
+                if map.is_solid(x, y) {
+                    continue;
+                }
+
+                // The front side is on the right, so walk around the open cell clockwise.
+                let edges = [
+                    ((fx + 1.0, fy), (fx, fy), (x, y - 1)),
+                    ((fx + 1.0, fy + 1.0), (fx + 1.0, fy), (x + 1, y)),
+                    ((fx, fy + 1.0), (fx + 1.0, fy + 1.0), (x, y + 1)),
+                    ((fx, fy), (fx, fy + 1.0), (x - 1, y))
+                ];
+
+                for ((ax, ay), (bx, by), (nx, ny)) in edges {
+                    if map.is_solid(nx, ny) {
+                        add_line(Vec2::new(ax, ay), Vec2::new(bx, by), map.tile(nx, ny));
+                    }
+                }
+            }
+        }
+
+        level
+    }
+
+    fn validate(&self, name: &str) -> Result<(), String> {
+        // The renderer and the collision always need a sector to stand in.
+        if self.sectors.is_empty() {
+            return Err(format!("{} has no sectors", name));
+        }
+
+        for linedef in &self.linedefs {
+            if linedef.start >= self.vertices.len() || linedef.end >= self.vertices.len() {
+                return Err(format!("{} has a linedef with a missing vertex", name));
+            }
+            if linedef.front.is_none() {
+                return Err(format!("{} has a linedef without a front side", name));
+            }
+            if linedef.front.iter().chain(linedef.back.iter()).any(|&side| side >= self.sidedefs.len()) {
+                return Err(format!("{} has a linedef with a missing sidedef", name));
+            }
+        }
+
+        if self.sidedefs.iter().any(|side| side.sector >= self.sectors.len()) {
+            return Err(format!("{} has a sidedef with a missing sector", name));
+        }
+
+        let mut switch_lines = self.triggers.iter().filter_map(|trigger| match trigger.activation {
+            Activation::Switch { line, .. } => Some(line),
+            _ => None
+        });
+        if switch_lines.any(|line| line >= self.linedefs.len()) {
+            return Err(format!("{} has a switch on a missing linedef", name));
+        }
+
+        Ok(())
+    }
+
+    /// The first player start (thing type 1).
+    pub fn player_start(&self) -> Option<&Thing> {
+        self.things.iter().find(|thing| thing.kind == 1)
+    }
+
+    /// Checks the move against every linedef, one-sided lines always block, two-sided ones if
+    /// the step up is too high or the opening too small.
+    pub fn can_move(&self, from: &Vec2<f64>, to: &Vec2<f64>) -> bool {
+        let step = Vec2::new(to.x - from.x, to.y - from.y);
+
+        for linedef in &self.linedefs {
+            let a = &self.vertices[linedef.start];
+            let b = &self.vertices[linedef.end];
+
+            if !self.blocks(linedef, a, b, from) {
+                continue;
+            }
+
+            if let Some((t, _)) = intersect(from, &step, a, b) {
+                if (0.0..=1.0).contains(&t) {
+                    return false;
+                }
+            }
+
+            if point_segment_dist(to, a, b) < PLAYER_RADIUS && point_segment_dist(to, a, b) < point_segment_dist(from, a, b) {
+                return false;
+            }
+        }
+
+        true
+    }
+
+    /// The closest line a shot along `dir` hits, as `t` along `dir` and the normal of the line facing the shooter.
+    /// One-sided lines and closed openings (like doors) stop shots, steps don't.
+    pub fn cast_ray(&self, pos: &Vec2<f64>, dir: &Vec2<f64>) -> Option<(f64, Vec2<f64>)> {
+        let mut closest: Option<(f64, Vec2<f64>)> = None;
+
+        for linedef in &self.linedefs {
+            let closed = match (linedef.front, linedef.back) {
+                (Some(front), Some(back)) => {
+                    let front = &self.sectors[self.sidedefs[front].sector];
+                    let back = &self.sectors[self.sidedefs[back].sector];
+                    front.ceiling.min(back.ceiling) <= front.floor.max(back.floor)
+                },
+                _ => true
+            };
+
+            let a = &self.vertices[linedef.start];
+            let b = &self.vertices[linedef.end];
+            let Some((t, u)) = intersect(pos, dir, a, b) else {
+                continue;
+            };
+
+            if !closed || t <= 0.0 || !(0.0..=1.0).contains(&u) || closest.is_some_and(|(dist, _)| dist <= t) {
+                continue;
+            }
+
+            let length = (b.x - a.x).hypot(b.y - a.y);
+            let mut normal = Vec2::new(-(b.y - a.y) / length, (b.x - a.x) / length);
+            if normal.x * dir.x + normal.y * dir.y > 0.0 {
+                normal = Vec2::new(-normal.x, -normal.y);
+            }
+
+            closest = Some((t, normal));
+        }
+
+        closest
+    }
+
+    fn blocks(&self, linedef: &Linedef, a: &Vec2<f64>, b: &Vec2<f64>, from: &Vec2<f64>) -> bool {
+        let (Some(front), Some(back)) = (linedef.front, linedef.back) else {
+            return true;
+        };
+
+        if linedef.flags & BLOCKING != 0 {
+            return true;
+        }
+
+        let front = &self.sectors[self.sidedefs[front].sector];
+        let back = &self.sectors[self.sidedefs[back].sector];
+
+        // The front side is to the right of the line.
+        let from_front = (b.x - a.x) * (from.y - a.y) - (b.y - a.y) * (from.x - a.x) <= 0.0;
+        let (here, there) = if from_front {(front, back)} else {(back, front)};
+
+        there.floor - here.floor > MAX_STEP_HEIGHT
+            || there.ceiling.min(here.ceiling) - there.floor.max(here.floor) < PLAYER_HEIGHT
+    }
+}
+
+/// The pics/ texture name of a grid wall type, in the same order as the textures get loaded.
+pub fn wall_texture_name(wall: i32) -> &'static str {
+    match wall {
+        1 => "wood",
+        2 => "greystone",
+        3 => "mossy",
+        4 => "purplestone",
+        _ => "redbrick"
+    }
+}
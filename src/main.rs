@@ -2,25 +2,84 @@ extern crate sdl2;
 
 use sdl2::{
     event::Event,
-    image::InitFlag,
-    keyboard::Keycode,
+    image::{InitFlag, SaveSurface},
     mouse::MouseUtil,
-    pixels::Color,
+    pixels::{Color, PixelFormatEnum},
     rect::{Point, Rect},
     render::Canvas,
+    surface::Surface,
     video::Window,
-    EventPump
+    controller::Axis,
+    EventPump,
+    GameControllerSubsystem
 };
+use std::f64::consts::PI;
+use std::path::Path;
 use std::time::Duration;
 
 mod data;
 mod rendering;
 mod config;
-use data::{EndGame, GameData, PlayerData, Vec2};
-use rendering::render_view_canvas;
-use config::{WIDTH, HEIGHT, WORLD_MAP};
+mod map;
+mod framebuffer;
+mod sector;
+mod level;
+mod wad;
+mod bsp;
+mod palette;
+mod sky;
+mod settings;
+mod input;
+mod gamepad;
+mod entity;
+mod sprite;
+mod ai;
+mod random;
+mod weapon;
+mod hud;
+mod font;
+mod console;
+mod profiler;
+mod bench;
+mod save;
+mod menu;
+mod audio;
+mod mus;
+mod midi;
+mod music;
+mod trigger;
+use data::{EndGame, GameData, PlayerData, RenderMode, Vec2};
+use framebuffer::FrameBuffer;
+use map::Map;
+use palette::IndexedBuffer;
+use rendering::{render_view, render_view_canvas};
+use sector::render_sectors;
+use bsp::render_bsp;
+use hud::draw_hud;
+use font::Font;
+use audio::Sound;
+use input::Action;
+use menu::Screen;
+use profiler::Stage;
+use settings::Settings;
+use random::Rng;
+use config::{WIDTH, HEIGHT, WORLD_MAP, WORLD_MAP_SKY, CROUCH_MOVE_FACTOR, SETTINGS_FILE, AUTOEXEC_FILE, QUICKSAVE_SLOT, KEY_TURN_SPEED, KEY_LOOK_SPEED, TICK_RATE};
 
 pub fn main() -> Result<(), String> {
+    let args: Vec<String> = std::env::args().collect();
+
+    // Headless, the benchmarks don't need a window.
+    if args.iter().any(|arg| arg == "--bench") {
+        return bench::run(args.iter().skip(1).find(|arg| !arg.starts_with("--")).map(String::as_str));
+    }
+
+    // SDL reads the driver from the environment, `--audio-driver dummy` runs without sound hardware.
+    if let Some(pos) = args.iter().position(|arg| arg == "--audio-driver") {
+        let driver = args.get(pos + 1).ok_or("--audio-driver needs a driver name")?;
+
+        std::env::set_var("SDL_AUDIODRIVER", driver);
+    }
+
     let sdl_context = sdl2::init()?;
     let video_subsystem = sdl_context.video()?;
     let _image_context = sdl2::image::init(InitFlag::PNG | InitFlag::JPG)?;
@@ -34,7 +93,6 @@ pub fn main() -> Result<(), String> {
         .map_err(|e| e.to_string())?;
 
     let _ = window.set_title("Software Renderer");
-    window.set_mouse_grab(true); // Prevent cursor from leaving window.
 
     let mut canvas = window.into_canvas().build().map_err(|e| e.to_string())?;
     // let texture_creator = canvas.texture_creator();
@@ -42,34 +100,96 @@ pub fn main() -> Result<(), String> {
     let mut event_pump = sdl_context.event_pump()?;
     let timer = sdl_context.timer()?;
     let mouse = sdl_context.mouse();
-
-    mouse.show_cursor(false);
+    let controllers = sdl_context.game_controller()?;
 
     // Game Setup
     let mut game_data = GameData::new(
-        false,
         false,
         WIDTH,
-        HEIGHT
+        HEIGHT,
+        Map::from_grid(&WORLD_MAP, WORLD_MAP_SKY)
     );
 
+    // The game works without sound, a missing or busy device only leaves a note in the console.
+    if !args.iter().any(|arg| arg == "--nosound") {
+        if let Err(error) = game_data.audio.open(&sdl_context) {
+            game_data.console.print(&format!("no sound: {}", error));
+        }
+    }
+
     game_data.add_texture_from_file("pics/wood.png")?;
     game_data.add_texture_from_file("pics/greystone.png")?;
     game_data.add_texture_from_file("pics/mossy.png")?;
     game_data.add_texture_from_file("pics/purplestone.png")?;
     game_data.add_texture_from_file("pics/redbrick.png")?;
 
-    let mut player = PlayerData::new(
-        Vec2::new(1.5, 1.5),
-        Vec2::new(-1.0, 0.0),
-        Vec2::new(0.0, 0.66)
-    );
+    if Path::new(SETTINGS_FILE).exists() {
+        game_data.settings = Settings::from_file(SETTINGS_FILE)?;
+    }
+
+    // Writes the current settings with all key bindings, as a starting point for changing them.
+    if args.iter().any(|arg| arg == "--write-settings") {
+        game_data.settings.save(SETTINGS_FILE)?;
+    }
+
+    // The grid turned into a level uses the same pictures.
+    game_data.load_level_pics();
+    game_data.load_sky();
+
+    // Optional: --wad <file> <map> or --level <file>, --palette <file>
+    if let Some(pos) = args.iter().position(|arg| arg == "--palette") {
+        let filename = args.get(pos + 1).ok_or("--palette needs a file name")?;
+
+        game_data.load_palette(filename)?;
+    }
+
+    if let Some(pos) = args.iter().position(|arg| arg == "--wad") {
+        let filename = args.get(pos + 1).ok_or("--wad needs a file name")?;
+        let map = args.get(pos + 2).map_or("E1M1", |map| map.as_str());
+
+        game_data.load_wad(filename, map)?;
+    } else if let Some(pos) = args.iter().position(|arg| arg == "--level") {
+        let filename = args.get(pos + 1).ok_or("--level needs a file name")?;
+
+        game_data.load_level_file(filename)?;
+    }
+
+    if let Some(pos) = args.iter().position(|arg| arg == "--font") {
+        let filename = args.get(pos + 1).ok_or("--font needs a file name")?;
+
+        game_data.font = Font::from_file(filename)?;
+    }
+
+    if let Some(pos) = args.iter().position(|arg| arg == "--seed") {
+        let seed = args.get(pos + 1).and_then(|seed| seed.parse().ok()).ok_or("--seed needs a number")?;
+
+        game_data.rng = Rng::new(seed);
+    }
+
+    game_data.spawn_entities();
+
+    let mut player = game_data.new_player();
+
+    // Console commands: the startup script first, then the ones from the command line (`+god +fov 100`).
+    if Path::new(AUTOEXEC_FILE).exists() {
+        console::execute(&format!("exec {}", AUTOEXEC_FILE), &mut player, &mut game_data);
+    }
+
+    for command in console::command_line(&args) {
+        console::execute(&command, &mut player, &mut game_data);
+    }
+
+    let mut frame_buffer = FrameBuffer::new(WIDTH, HEIGHT);
+    let mut indexed_buffer = IndexedBuffer::new(WIDTH, HEIGHT);
+    let mut hud_buffer = FrameBuffer::new(WIDTH, HEIGHT);
 
     let mut time = 0.0;
     let mut old_time = 0.0;
-
+    // The game starts on the title screen, the mouse is only captured while playing.
+    let mut menu_was_open = false;
 
     'running: loop {
+        profiler::begin_frame();
         game_data.update_sizes(canvas.window().size());
 
         canvas.set_draw_color(Color::BLACK);
@@ -78,11 +198,50 @@ pub fn main() -> Result<(), String> {
         old_time = time;
         time = timer.ticks() as f64;
         let delta_time = (time - old_time) / 1000.0;
+        if delta_time > 0.0 {
+            game_data.fps = game_data.fps * 0.95 + 0.05 / delta_time;
+        }
+
+        if menu_was_open != game_data.menu.is_open() {
+            menu_was_open = game_data.menu.is_open();
+            mouse.set_relative_mouse_mode(!menu_was_open);
+            canvas.window_mut().set_mouse_grab(!menu_was_open); // Prevent cursor from leaving window.
+        }
+
+        {
+            profiler::scope!(Stage::Input);
+            let end_game = if game_data.menu.is_open() {
+                menu_input(&mut player, &mut game_data, &mut event_pump, &controllers)
+            } else {
+                player_input(&mut player, &mut game_data, &mut event_pump, &mouse, &mut canvas, &controllers, delta_time)
+            };
+
+            if let EndGame(true) = end_game {
+                break 'running;
+            }
+        }
 
-        if let EndGame(true) = player_input(&mut player, &mut game_data, &mut event_pump, &mouse, &mut canvas, delta_time) {
-            break 'running;
+        if let Some((width, height)) = game_data.menu.window_size.take() {
+            if let Err(error) = canvas.window_mut().set_size(width, height) {
+                game_data.console.print(&format!("resolution: {}", error));
+            }
         }
 
+        // Entities think at a fixed rate, no matter how fast the frames are. The menus pause the game.
+        if !game_data.menu.is_open() {
+            profiler::scope!(Stage::Simulation);
+            game_data.tick_time += delta_time;
+            while game_data.tick_time >= 1.0 / TICK_RATE {
+                game_data.tick_time -= 1.0 / TICK_RATE;
+                player.fade_flashes();
+                weapon::tick(&mut player, &mut game_data);
+                trigger::tick(&mut player, &mut game_data);
+                entity::tick(&mut player, &mut game_data);
+            }
+        }
+
+        audio::update(&player, &mut game_data);
+
         // let mut buffer_texture = texture_creator
         //     .create_texture_streaming(PixelFormatEnum::RGBA32, game_data.width, game_data.height)
         //     .map_err(|e| e.to_string())?;
@@ -91,122 +250,283 @@ pub fn main() -> Result<(), String> {
         //
         // canvas.copy(&buffer_texture, None, None)?;
         
-        render_view_canvas(&player, &mut canvas, &game_data, game_data.textured);
+        match game_data.render_mode {
+            RenderMode::Grid if game_data.paletted => if let Some(palette_data) = &game_data.palette_data {
+                indexed_buffer.resize(game_data.width, game_data.height);
+                indexed_buffer.clear(palette_data.palette.nearest(Color::BLACK));
+                render_view(&player, &mut indexed_buffer, &game_data, game_data.textured);
+
+                // The only place the indices turn into colors.
+                frame_buffer.resize(game_data.width, game_data.height);
+                indexed_buffer.to_rgba(&palette_data.palette, &mut frame_buffer.pixels);
+                frame_buffer.present(&mut canvas)?;
+            },
+            RenderMode::Grid => render_view_canvas(&player, &mut canvas, &game_data, game_data.textured),
+            RenderMode::Sectors => {
+                frame_buffer.resize(game_data.width, game_data.height);
+                frame_buffer.clear(Color::BLACK);
+                render_sectors(&player, &mut frame_buffer, &game_data, game_data.textured);
+                frame_buffer.present(&mut canvas)?;
+            },
+            RenderMode::Bsp => {
+                frame_buffer.resize(game_data.width, game_data.height);
+                frame_buffer.clear(Color::BLACK);
+                render_bsp(&player, &mut frame_buffer, &game_data, game_data.textured);
+                frame_buffer.present(&mut canvas)?;
+            }
+        }
 
-        if game_data.map_view {
-            canvas.set_draw_color(Color::WHITE);
+        // The HUD goes over whatever renderer drew the view.
+        hud_buffer.resize(game_data.width, game_data.height);
+        hud_buffer.clear(Color::RGBA(0, 0, 0, 0));
+        draw_hud(&player, &game_data, &mut hud_buffer);
+        if game_data.settings.show_profiler {
+            profiler::draw_overlay(&game_data.font, &mut hud_buffer);
+        }
+        hud_buffer.present_overlay(&mut canvas)?;
 
-            for x in WORLD_MAP.iter().enumerate() {
-                for y in x.1.iter().enumerate() {
-                    let set_x = (x.0 as i32) * 20;
-                    let set_y = (9 - y.0 as i32) * 20;
+        if game_data.map_view {
+            draw_map(&player, &mut canvas, &game_data);
+        }
 
-                    if *y.1 == 0 {
-                        let _ = canvas.draw_rect(Rect::new(set_x, set_y, 20, 20));
-                    } else {
-                        let _ = canvas.fill_rect(Rect::new(set_x, set_y, 20, 20));
-                    }
-                }
+        // Taken before the console gets drawn, so it doesn't end up in the picture.
+        if let Some(filename) = game_data.console.screenshot.take() {
+            match save_screenshot(&canvas, &filename) {
+                Ok(()) => game_data.console.print(&format!("saved {}", filename)),
+                Err(error) => game_data.console.print(&format!("screenshot: {}", error))
             }
+        }
 
-            canvas.set_draw_color(Color::BLACK);
-            let player_x = (player.pos.x) * 20.0;
-            let player_y = (10.0-player.pos.y) * 20.0;
+        // The view keeps rendering under the menus, dimmed.
+        if game_data.menu.is_open() {
+            hud_buffer.clear(Color::RGBA(0, 0, 0, 0));
+            menu::draw(&player, &game_data, &mut hud_buffer);
+            hud_buffer.present_overlay(&mut canvas)?;
+        }
 
-            let _ = canvas.fill_rect(Rect::new(player_x as i32, player_y as i32, 5, 5));
+        if game_data.console.open {
+            hud_buffer.clear(Color::RGBA(0, 0, 0, 0));
+            game_data.console.draw(&game_data.font, &mut hud_buffer);
+            hud_buffer.present_overlay(&mut canvas)?;
+        }
 
-            let _ = canvas.draw_line(
-                Point::new(player_x as i32, player_y as i32),
-                Point::new((player_x + player.dir.x * 20.0) as i32, (player_y - player.dir.y * 20.0) as i32)
-            );
+        {
+            profiler::scope!(Stage::Present);
+            canvas.present();
+        }
 
+        if let Some(result) = profiler::end_frame() {
+            game_data.console.print(&result.unwrap_or_else(|error| format!("profile_trace: {}", error)));
         }
 
-        canvas.present();
         ::std::thread::sleep(Duration::new(0, 1_000_000_000u32 / 30));
     }
 
     Ok(())
 }
 
+/// Saves what has been drawn so far as a PNG.
+fn save_screenshot(canvas: &Canvas<Window>, filename: &str) -> Result<(), String> {
+    let (width, height) = canvas.output_size()?;
+    let mut pixels = canvas.read_pixels(None, PixelFormatEnum::RGB24)?;
+    let surface = Surface::from_data(&mut pixels, width, height, width * 3, PixelFormatEnum::RGB24)?;
+
+    surface.save(filename)
+}
+
+/// Top down view of the current world, the grid or the linedefs of a level.
+fn draw_map(player: &PlayerData, canvas: &mut Canvas<Window>, game_data: &GameData) {
+    canvas.set_draw_color(Color::WHITE);
+
+    // World position to screen position, y points up in the world.
+    let (origin, top) = if game_data.render_mode == RenderMode::Bsp {
+        let vertices = &game_data.level.vertices;
+        let min_x = vertices.iter().map(|v| v.x).fold(f64::MAX, f64::min);
+        let max_y = vertices.iter().map(|v| v.y).fold(f64::MIN, f64::max);
+        (min_x, max_y)
+    } else {
+        (0.0, game_data.map.height as f64)
+    };
+    let to_screen = |x: f64, y: f64| Point::new(((x - origin) * 20.0) as i32, ((top - y) * 20.0) as i32);
+
+    if game_data.render_mode == RenderMode::Bsp {
+        let level = &game_data.level;
+
+        for linedef in &level.linedefs {
+            let a = &level.vertices[linedef.start];
+            let b = &level.vertices[linedef.end];
+
+            // Two-sided lines are only steps, so they're drawn darker.
+            canvas.set_draw_color(if linedef.back.is_some() {Color::GRAY} else {Color::WHITE});
+            let _ = canvas.draw_line(to_screen(a.x, a.y), to_screen(b.x, b.y));
+        }
+    } else {
+        let map = &game_data.map;
+
+        for x in 0..map.width as i32 {
+            for y in 0..map.height as i32 {
+                let corner = to_screen(x as f64, y as f64 + 1.0);
+
+                if map.is_solid(x, y) {
+                    let _ = canvas.fill_rect(Rect::new(corner.x, corner.y, 20, 20));
+                } else {
+                    let _ = canvas.draw_rect(Rect::new(corner.x, corner.y, 20, 20));
+                }
+
+                for segment in map.segments(x, y) {
+                    let _ = canvas.draw_line(
+                        to_screen(x as f64 + segment.a.x, y as f64 + segment.a.y),
+                        to_screen(x as f64 + segment.b.x, y as f64 + segment.b.y)
+                    );
+                }
+            }
+        }
+    }
+
+    canvas.set_draw_color(if game_data.render_mode == RenderMode::Bsp {Color::RED} else {Color::BLACK});
+    let player_point = to_screen(player.pos.x, player.pos.y);
+
+    let _ = canvas.fill_rect(Rect::new(player_point.x, player_point.y, 5, 5));
+
+    let _ = canvas.draw_line(
+        player_point,
+        to_screen(player.pos.x + player.dir.x, player.pos.y + player.dir.y)
+    );
+}
+
 fn player_input(
     player: &mut PlayerData,
     game_data: &mut GameData,
     event_pump: &mut EventPump,
     mouse: &MouseUtil,
     canvas: &mut Canvas<Window>,
+    controllers: &GameControllerSubsystem,
     delta_time: f64
 ) -> EndGame {
-    let move_speed = 3.0 * delta_time;
+    let keyboard = event_pump.keyboard_state();
+    // While the console is open the keys are for typing.
+    let typing = game_data.console.open;
+    let held = |action: Action| !typing && game_data.settings.bindings.is_held(action, &keyboard, &game_data.gamepad);
+    let settings = &game_data.settings;
+
+    player.crouching = held(Action::Crouch);
+    player.weapons.firing = held(Action::Fire);
+    if held(Action::Jump) {
+        player.jump();
+    }
+
+    // Turning and looking with the keyboard, for playing without a mouse.
+    if held(Action::TurnLeft) {
+        player.dir.rotate(KEY_TURN_SPEED * delta_time);
+        player.cam.rotate(KEY_TURN_SPEED * delta_time);
+    } else if held(Action::TurnRight) {
+        player.dir.rotate(-KEY_TURN_SPEED * delta_time);
+        player.cam.rotate(-KEY_TURN_SPEED * delta_time);
+    }
+
+    if held(Action::LookUp) {
+        game_data.pitch_remainder -= KEY_LOOK_SPEED * delta_time;
+    } else if held(Action::LookDown) {
+        game_data.pitch_remainder += KEY_LOOK_SPEED * delta_time;
+    }
+
+    // The right stick turns and looks, the curve makes small movements finer.
+    let look = game_data.gamepad.stick(Axis::RightX, Axis::RightY, settings.pad_dead_zone, settings.pad_response_curve);
+    player.dir.rotate(-look.x * settings.pad_turn_speed * delta_time);
+    player.cam.rotate(-look.x * settings.pad_turn_speed * delta_time);
+    let invert = if settings.invert_y {-1.0} else {1.0};
+    game_data.pitch_remainder += look.y * settings.pad_look_speed * delta_time * invert;
+
+    // How much to move forward and to the right (-1.0 - 1.0), keys go all the way, the left stick can go less.
+    let stick = game_data.gamepad.stick(Axis::LeftX, Axis::LeftY, settings.pad_dead_zone, 1.0);
+    let key_axis = |positive: Action, negative: Action| {
+        if held(positive) {1.0} else if held(negative) {-1.0} else {0.0}
+    };
+    let forward = (key_axis(Action::MoveForward, Action::MoveBackward) - stick.y).clamp(-1.0, 1.0);
+    let strafe = (key_axis(Action::StrafeRight, Action::StrafeLeft) + stick.x).clamp(-1.0, 1.0);
+    let walking = forward != 0.0 || strafe != 0.0;
+
+    let move_speed = 3.0 * delta_time * if player.crouching {CROUCH_MOVE_FACTOR} else {1.0};
 
     // Strafe Left/Right
-    if event_pump.keyboard_state().is_scancode_pressed(sdl2::keyboard::Scancode::A) {
+    if strafe != 0.0 {
         // The number 67.5 (45+(45/2)) came through trail and error, 90 and 45 led the player
         // to strafe at an angle, for some reason. Do note that it appears that either 90 or 45
         // at least move in the correct direction but 67.5 requires a sign switch.
-        let dir_x: f64 = player.dir.x * (-67.5f64).cos() - player.dir.y * (-67.5f64).sin();
-        let dir_y: f64 = player.dir.x * (-67.5f64).sin() + player.dir.y * (-67.5f64).cos();
+        let angle = 67.5f64 * strafe.signum();
+        let dir_x: f64 = player.dir.x * angle.cos() - player.dir.y * angle.sin();
+        let dir_y: f64 = player.dir.x * angle.sin() + player.dir.y * angle.cos();
 
-        let new_player_x = player.pos.x + dir_x * move_speed;
-        let new_player_y = player.pos.y + dir_y * move_speed;
+        let new_player_x = player.pos.x + dir_x * move_speed * strafe.abs();
+        let new_player_y = player.pos.y + dir_y * move_speed * strafe.abs();
 
-        if WORLD_MAP[new_player_x as usize][new_player_y as usize] == 0 {
-            player.pos.x = new_player_x;
-            player.pos.y = new_player_y;
-        }
-    } else if event_pump.keyboard_state().is_scancode_pressed(sdl2::keyboard::Scancode::D) {
-        let dir_x: f64 = player.dir.x * (67.5f64).cos() - player.dir.y * (67.5f64).sin();
-        let dir_y: f64 = player.dir.x * (67.5f64).sin() + player.dir.y * (67.5f64).cos();
-
-        let new_player_x = player.pos.x + dir_x * move_speed;
-        let new_player_y = player.pos.y + dir_y * move_speed;
-
-        if WORLD_MAP[new_player_x as usize][new_player_y as usize] == 0 {
+        if player.noclip || game_data.can_move(&player.pos, &Vec2::new(new_player_x, new_player_y)) {
             player.pos.x = new_player_x;
             player.pos.y = new_player_y;
         }
     }
 
     // Move Forward/Backward
-    if event_pump.keyboard_state().is_scancode_pressed(sdl2::keyboard::Scancode::W) {
-        if WORLD_MAP[(player.pos.x + player.dir.x * move_speed) as usize][player.pos.y as usize] == 0 {
-            player.pos.x += player.dir.x * move_speed;
+    if forward != 0.0 {
+        let new_player_x = player.pos.x + player.dir.x * move_speed * forward;
+        if player.noclip || game_data.can_move(&player.pos, &Vec2::new(new_player_x, player.pos.y)) {
+            player.pos.x = new_player_x;
         }
 
-        if WORLD_MAP[player.pos.x as usize][(player.pos.y + player.dir.y * move_speed) as usize] == 0 {
-            player.pos.y += player.dir.y * move_speed;
-        }
-    } else if event_pump.keyboard_state().is_scancode_pressed(sdl2::keyboard::Scancode::S) {
-        if WORLD_MAP[(player.pos.x - player.dir.x * move_speed) as usize][player.pos.y as usize] == 0 {
-            player.pos.x -= player.dir.x * move_speed;
+        let new_player_y = player.pos.y + player.dir.y * move_speed * forward;
+        if player.noclip || game_data.can_move(&player.pos, &Vec2::new(player.pos.x, new_player_y)) {
+            player.pos.y = new_player_y;
         }
+    }
 
-        if WORLD_MAP[player.pos.x as usize][(player.pos.y - player.dir.y * move_speed) as usize] == 0 {
-            player.pos.y -= player.dir.y * move_speed;
-        }
+    let (floor, ceiling) = game_data.floor_and_ceiling(&player.pos);
+    let old_bob = player.bob;
+    player.update_height(floor, ceiling, walking, delta_time);
+
+    // A footstep every time the head-bob dips.
+    if walking && player.on_ground && (player.bob / PI).floor() != (old_bob / PI).floor() {
+        game_data.audio.play(Sound::Footstep, None);
     }
 
+    // Relative mouse movement of this frame, in pixels.
+    let mut mouse_move = Vec2::new(0.0, 0.0);
+
     for event in event_pump.poll_iter() {
         match event {
-            Event::Quit { .. } |
-            Event::KeyDown {
-                keycode: Some(Keycode::Escape),
-                ..
-            } => {
+            Event::Quit { .. } => {
                 return EndGame(true);
             },
             Event::KeyDown {
-                keycode: Some(Keycode::M),
+                scancode: Some(scancode),
                 ..
-            } => {
-                game_data.map_view = !game_data.map_view;
-            },
+            } if game_data.console.open => console::key_down(scancode, player, game_data),
+            Event::TextInput { text, .. } if game_data.console.open => game_data.console.type_text(&text),
             Event::KeyDown {
-                keycode: Some(Keycode::T),
+                scancode: Some(scancode),
+                repeat: false,
                 ..
             } => {
-                game_data.textured = !game_data.textured;
-                player.ver = 0;
+                let actions: Vec<Action> = game_data.settings.bindings.actions(scancode).collect();
+
+                if let EndGame(true) = trigger_actions(player, game_data, &actions) {
+                    return EndGame(true);
+                }
+            },
+            Event::ControllerDeviceAdded { which, .. } => {
+                // A controller that can't be opened just doesn't work, the keyboard still does.
+                let _ = game_data.gamepad.connect(controllers, which);
+            },
+            Event::ControllerAxisMotion { .. } |
+            Event::ControllerButtonDown { .. } |
+            Event::ControllerButtonUp { .. } |
+            Event::ControllerDeviceRemoved { .. } => {
+                if let Some(input) = game_data.gamepad.handle_event(&event) {
+                    let actions: Vec<Action> = game_data.settings.bindings.pad_actions(input).collect();
+
+                    if let EndGame(true) = trigger_actions(player, game_data, &actions) {
+                        return EndGame(true);
+                    }
+                }
             },
             Event::MouseButtonDown {
                 mouse_btn,
@@ -214,7 +534,7 @@ fn player_input(
             } => {
                 match mouse_btn {
                     sdl2::mouse::MouseButton::Right => {
-                        mouse.show_cursor(!mouse.is_cursor_showing());
+                        mouse.set_relative_mouse_mode(!mouse.relative_mouse_mode());
                         let grabbed = canvas.window().mouse_grab();
                         canvas.window_mut().set_mouse_grab(!grabbed);
                     },
@@ -222,41 +542,117 @@ fn player_input(
                 }
             },
             Event::MouseMotion {
-                x, y, ..
-            } => {
-                if mouse.is_cursor_showing() {
-                    continue;
-                }
+                xrel, yrel, ..
+            } if mouse.relative_mouse_mode() => {
+                mouse_move.x += xrel as f64;
+                mouse_move.y += yrel as f64;
+            },
+            _ => {}
+        }
+    }
 
-                // Once the mouse gets set back to the center, the MouseMotion event triggers
-                // and has to be ignored.
-                if game_data.mouse_set {
-                    game_data.mouse_set = false;
-                    continue;
-                }
+    mouse_look(player, game_data, mouse_move, delta_time);
+
+    EndGame(false)
+}
 
-                let delta_x_norm = (game_data.last_mouse_pos.x - x).signum(); // 1 = left; -1 = right;
-                let rotate_by = delta_x_norm as f64 * delta_time;
+/// Input while a menu is open, the game doesn't move and the mouse points at the items.
+fn menu_input(
+    player: &mut PlayerData,
+    game_data: &mut GameData,
+    event_pump: &mut EventPump,
+    controllers: &GameControllerSubsystem
+) -> EndGame {
+    for event in event_pump.poll_iter() {
+        let end_game = match event {
+            Event::Quit { .. } => EndGame(true),
+            Event::KeyDown {
+                scancode: Some(scancode),
+                ..
+            } if game_data.console.open => {
+                console::key_down(scancode, player, game_data);
+                EndGame(false)
+            },
+            Event::TextInput { text, .. } if game_data.console.open => {
+                game_data.console.type_text(&text);
+                EndGame(false)
+            },
+            Event::KeyDown {
+                scancode: Some(scancode),
+                ..
+            } => menu::key_down(scancode, player, game_data),
+            Event::ControllerDeviceAdded { which, .. } => {
+                let _ = game_data.gamepad.connect(controllers, which);
+                EndGame(false)
+            },
+            Event::ControllerAxisMotion { .. } |
+            Event::ControllerButtonDown { .. } |
+            Event::ControllerButtonUp { .. } |
+            Event::ControllerDeviceRemoved { .. } => match game_data.gamepad.handle_event(&event) {
+                Some(input) => menu::pad_input(input, player, game_data),
+                None => EndGame(false)
+            },
+            Event::MouseMotion { y, .. } => {
+                menu::mouse_move(y, player, game_data);
+                EndGame(false)
+            },
+            Event::MouseButtonDown { mouse_btn, y, .. } => {
+                menu::mouse_click(y, mouse_btn == sdl2::mouse::MouseButton::Left, player, game_data)
+            },
+            _ => EndGame(false)
+        };
 
-                player.dir.rotate(rotate_by/2.0);
-                player.cam.rotate(rotate_by/2.0);
+        if let EndGame(true) = end_game {
+            return end_game;
+        }
+    }
 
-                let delta_y_norm = (y - game_data.last_mouse_pos.y).signum();
-                player.ver += (delta_y_norm as f64 * delta_time*100.0) as i32;
-                if player.ver.abs() == 100 {
-                    player.ver = player.ver.signum() * 100;
-                }
+    EndGame(false)
+}
 
-                // Warp mouse back to the center of the window.
-                let half_width = (game_data.width/2) as i32;
-                let half_height = (game_data.height/2) as i32;
-                mouse.warp_mouse_in_window(&canvas.window(), half_width, half_height as i32);
-                game_data.last_mouse_pos.set(half_width as i32, half_height as i32);
-                game_data.mouse_set = true;
-            },
+/// Actions that happen once per key press, the held ones are checked every frame.
+fn trigger_actions(player: &mut PlayerData, game_data: &mut GameData, actions: &[Action]) -> EndGame {
+    for action in actions {
+        match action {
+            Action::Menu => game_data.menu.open(Screen::Paused),
+            Action::ToggleMap => game_data.map_view = !game_data.map_view,
+            Action::ToggleTextures => game_data.textured = !game_data.textured,
+            Action::TogglePalette => game_data.toggle_palette_mode(),
+            Action::NextRenderMode => game_data.next_render_mode(),
+            Action::NextWeapon => player.weapons.next_weapon(),
+            Action::Use => player.using = true,
+            Action::ToggleConsole => game_data.console.open = !game_data.console.open,
+            Action::QuickSave => console::execute(&format!("save {}", QUICKSAVE_SLOT), player, game_data),
+            Action::QuickLoad => console::execute(&format!("load {}", QUICKSAVE_SLOT), player, game_data),
             _ => {}
         }
     }
 
     EndGame(false)
 }
+
+/// Turns and tilts the view by the mouse movement. The movement is a distance and not a speed,
+/// so it doesn't get scaled by delta_time, only the smoothing has to adapt to the frame rate.
+fn mouse_look(player: &mut PlayerData, game_data: &mut GameData, mouse_move: Vec2<f64>, delta_time: f64) {
+    let settings = &game_data.settings;
+
+    // The part of the old movement that is kept, as if the game ran at 60 fps.
+    let keep = settings.mouse_smoothing.powf(delta_time * 60.0);
+    let delta = Vec2::new(
+        mouse_move.x * (1.0 - keep) + game_data.mouse_delta.x * keep,
+        mouse_move.y * (1.0 - keep) + game_data.mouse_delta.y * keep
+    );
+    game_data.mouse_delta = delta;
+
+    // Moving the mouse right turns right, which is a negative rotation.
+    let rotate_by = -delta.x * settings.mouse_sensitivity_x;
+    player.dir.rotate(rotate_by);
+    player.cam.rotate(rotate_by);
+
+    // The keys and the stick already added their part, only whole pixels move the view.
+    let invert = if settings.invert_y {-1.0} else {1.0};
+    game_data.pitch_remainder += delta.y * settings.mouse_sensitivity_y * invert;
+    let pixels = game_data.pitch_remainder.trunc();
+    game_data.pitch_remainder -= pixels;
+    player.look(pixels as i32, game_data.height);
+}
@@ -0,0 +1,235 @@
+use crate::config::{PLAYER_RADIUS, TILE_SEGMENTS};
+use crate::data::Vec2;
+
+/// A wall line inside of a single cell, in cell-local coordinates (0.0 - 1.0).
+pub struct Segment {
+    pub a: Vec2<f64>,
+    pub b: Vec2<f64>,
+    pub wall: i32
+}
+
+impl Segment {
+    pub const fn new(a: Vec2<f64>, b: Vec2<f64>, wall: i32) -> Self {
+        Segment { a, b, wall }
+    }
+}
+
+/// Everything the renderer needs to know about where a ray stopped.
+pub struct RayHit {
+    pub dist: f64,
+    pub wall: i32,
+    pub x_side: bool,
+    /// The coordinate on the wall which corresponds to the x-coordinate on the texture.
+    pub wall_x: f64
+}
+
+pub struct Map {
+    pub width: usize,
+    pub height: usize,
+    pub tiles: Vec<i32>,
+    /// Texture name of the sky, without one the grid has a ceiling.
+    pub sky: Option<String>
+}
+
+impl Map {
+    pub fn from_grid<const W: usize, const H: usize>(grid: &[[i32; H]; W], sky: Option<&str>) -> Self {
+        Map {
+            width: W,
+            height: H,
+            tiles: grid.iter().flatten().copied().collect(),
+            sky: sky.map(String::from)
+        }
+    }
+
+    /// Returns the tile at the given cell, anything outside of the map counts as a wall.
+    pub fn tile(&self, x: i32, y: i32) -> i32 {
+        if x < 0 || y < 0 || x as usize >= self.width || y as usize >= self.height {
+            return 1;
+        }
+
+        self.tiles[x as usize * self.height + y as usize]
+    }
+
+    /// The segments of the tile at the given cell, empty for open and full block tiles.
+    pub fn segments(&self, x: i32, y: i32) -> &'static [Segment] {
+        tile_segments(self.tile(x, y))
+    }
+
+    /// True if the whole cell is a solid block.
+    pub fn is_solid(&self, x: i32, y: i32) -> bool {
+        self.tile(x, y) != 0 && self.segments(x, y).is_empty()
+    }
+
+    /// Cells monsters can walk through, anything that isn't empty (walls, thin walls, closed doors) blocks.
+    pub fn is_open(&self, x: i32, y: i32) -> bool {
+        self.tile(x, y) == 0
+    }
+
+    /// Casts a ray using DDA, once a cell with segments is entered the ray is tested against them.
+    /// `ray_dir` is expected to be built from the direction and camera plane, that way the
+    /// returned distance is already the perpendicular one.
+    pub fn cast_ray(&self, pos: &Vec2<f64>, ray_dir: &Vec2<f64>) -> RayHit {
+        let mut map_x: i32 = pos.x as i32;
+        let mut map_y: i32 = pos.y as i32;
+
+        let delta_dist_x: f64 = if ray_dir.x == 0.0 {1e30} else {(1.0/ray_dir.x).abs()};
+        let delta_dist_y: f64 = if ray_dir.y == 0.0 {1e30} else {(1.0/ray_dir.y).abs()};
+
+        let (step_x, mut side_dist_x) = if ray_dir.x < 0.0 {
+            (-1, (pos.x - (map_x as f64)) * delta_dist_x)
+        } else {
+            (1, ((map_x as f64) + 1.0 - pos.x) * delta_dist_x)
+        };
+
+        let (step_y, mut side_dist_y) = if ray_dir.y < 0.0 {
+            (-1, (pos.y - (map_y as f64)) * delta_dist_y)
+        } else {
+            (1, ((map_y as f64) + 1.0 - pos.y) * delta_dist_y)
+        };
+
+        // The player may be standing inside of a cell with segments.
+        if let Some(hit) = self.hit_segments(map_x, map_y, pos, ray_dir) {
+            return hit;
+        }
+
+        loop {
+            let x_side = side_dist_x < side_dist_y;
+            if x_side {
+                side_dist_x += delta_dist_x;
+                map_x += step_x;
+            } else {
+                side_dist_y += delta_dist_y;
+                map_y += step_y;
+            }
+
+            if let Some(hit) = self.hit_segments(map_x, map_y, pos, ray_dir) {
+                return hit;
+            }
+
+            if !self.is_solid(map_x, map_y) {
+                continue;
+            }
+
+            let dist = if x_side {side_dist_x - delta_dist_x} else {side_dist_y - delta_dist_y};
+
+            let mut wall_x = if x_side {
+                pos.y + dist * ray_dir.y
+            } else {
+                pos.x + dist * ray_dir.x
+            };
+            wall_x -= wall_x.floor();
+
+            return RayHit { dist, wall: self.tile(map_x, map_y), x_side, wall_x };
+        }
+    }
+
+    /// Finds the closest segment of a cell the ray passes through.
+    fn hit_segments(&self, map_x: i32, map_y: i32, pos: &Vec2<f64>, ray_dir: &Vec2<f64>) -> Option<RayHit> {
+        let mut closest: Option<RayHit> = None;
+
+        for segment in self.segments(map_x, map_y) {
+            let a = Vec2::new(map_x as f64 + segment.a.x, map_y as f64 + segment.a.y);
+            let b = Vec2::new(map_x as f64 + segment.b.x, map_y as f64 + segment.b.y);
+
+            let Some((t, u)) = intersect(pos, ray_dir, &a, &b) else {
+                continue;
+            };
+
+            if t <= 1e-6 || closest.as_ref().is_some_and(|hit| hit.dist <= t) {
+                continue;
+            }
+
+            // Use the length along the segment, so shorter segments don't stretch the texture.
+            let length = ((b.x - a.x).powi(2) + (b.y - a.y).powi(2)).sqrt();
+
+            closest = Some(RayHit {
+                dist: t,
+                wall: segment.wall,
+                // Shade segments the same way as the grid, lines running along y count as x sides.
+                x_side: (b.x - a.x).abs() < (b.y - a.y).abs(),
+                wall_x: (u * length).fract()
+            });
+        }
+
+        closest
+    }
+
+    /// Checks if something of the size of the player can move from one position to another.
+    pub fn can_move(&self, from: &Vec2<f64>, to: &Vec2<f64>) -> bool {
+        let cell_x = to.x.floor() as i32;
+        let cell_y = to.y.floor() as i32;
+
+        if self.is_solid(cell_x, cell_y) {
+            return false;
+        }
+
+        let step = Vec2::new(to.x - from.x, to.y - from.y);
+
+        // The segments can sit on the edge of a cell, so the neighbours have to be checked too.
+        for x in (cell_x - 1)..=(cell_x + 1) {
+            for y in (cell_y - 1)..=(cell_y + 1) {
+                for segment in self.segments(x, y) {
+                    let a = Vec2::new(x as f64 + segment.a.x, y as f64 + segment.a.y);
+                    let b = Vec2::new(x as f64 + segment.b.x, y as f64 + segment.b.y);
+
+                    if let Some((t, u)) = intersect(from, &step, &a, &b) {
+                        if (0.0..=1.0).contains(&t) && (0.0..=1.0).contains(&u) {
+                            return false;
+                        }
+                    }
+
+                    // Only block movement towards the segment, so the player can't get stuck.
+                    if point_segment_dist(to, &a, &b) < PLAYER_RADIUS
+                        && point_segment_dist(to, &a, &b) < point_segment_dist(from, &a, &b) {
+                        return false;
+                    }
+                }
+            }
+        }
+
+        true
+    }
+}
+
+/// The segments belonging to a tile, empty for open and full block tiles.
+pub fn tile_segments(tile: i32) -> &'static [Segment] {
+    TILE_SEGMENTS.iter()
+        .find(|(id, _)| *id == tile)
+        .map(|(_, segments)| *segments)
+        .unwrap_or(&[])
+}
+
+/// Intersects the line `pos + t*dir` with the segment a-b.
+/// Returns t and the position along the segment (0.0 - 1.0), None if they don't meet.
+pub fn intersect(pos: &Vec2<f64>, dir: &Vec2<f64>, a: &Vec2<f64>, b: &Vec2<f64>) -> Option<(f64, f64)> {
+    let edge = Vec2::new(b.x - a.x, b.y - a.y);
+    let denom = dir.x * edge.y - dir.y * edge.x;
+
+    // Parallel, the ray can never hit the front of the segment.
+    if denom.abs() < 1e-12 {
+        return None;
+    }
+
+    let w = Vec2::new(a.x - pos.x, a.y - pos.y);
+    let t = (w.x * edge.y - w.y * edge.x) / denom;
+    let u = (w.x * dir.y - w.y * dir.x) / denom;
+
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+
+    Some((t, u))
+}
+
+pub fn point_segment_dist(p: &Vec2<f64>, a: &Vec2<f64>, b: &Vec2<f64>) -> f64 {
+    let edge = Vec2::new(b.x - a.x, b.y - a.y);
+    let length_sq = edge.x.powi(2) + edge.y.powi(2);
+
+    let t = if length_sq == 0.0 {
+        0.0
+    } else {
+        (((p.x - a.x) * edge.x + (p.y - a.y) * edge.y) / length_sq).clamp(0.0, 1.0)
+    };
+
+    ((a.x + edge.x * t - p.x).powi(2) + (a.y + edge.y * t - p.y).powi(2)).sqrt()
+}
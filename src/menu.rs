@@ -0,0 +1,391 @@
+use sdl2::controller::Button;
+use sdl2::keyboard::Scancode;
+use sdl2::pixels::Color;
+
+use crate::config::{HUD_BASE_HEIGHT, MAX_FOV, MENU_SLOTS, MIN_FOV, QUICKSAVE_SLOT, SETTINGS_FILE, WINDOW_SIZES};
+use crate::data::{EndGame, GameData, PlayerData};
+use crate::font::{Align, TextStyle};
+use crate::framebuffer::FrameBuffer;
+use crate::gamepad::PadInput;
+use crate::input::Action;
+use crate::console;
+use crate::save;
+
+const DIM: Color = Color::RGBA(0, 0, 0, 160);
+const TITLE_COLOR: Color = Color::RGB(200, 30, 30);
+const ITEM_COLOR: Color = Color::RGB(200, 200, 200);
+const SELECTED_COLOR: Color = Color::RGB(255, 215, 0);
+
+/// Every screen of the menu, playing is when none is open.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Screen {
+    Title,
+    Main,
+    Paused,
+    Options,
+    Keys,
+    Save,
+    Load,
+    QuitConfirm
+}
+
+/// What an item does when it is chosen, or changed with left/right.
+#[derive(Clone)]
+enum Choice {
+    NewGame,
+    Resume,
+    Open(Screen),
+    MainMenu,
+    Quit,
+    Back,
+    Resolution,
+    Fov,
+    MouseSensitivity,
+    LookSensitivity,
+    InvertY,
+    SoundVolume,
+    MusicVolume,
+    Bind(Action),
+    SaveSlot(String),
+    LoadSlot(String)
+}
+
+/// The menu state machine. The screens are a stack, Esc goes back to the one below
+/// and once the stack is empty the game is running.
+pub struct Menu {
+    pub screens: Vec<Screen>,
+    /// Item under the cursor on the current screen.
+    pub selected: usize,
+    /// The action that gets the next pressed key, on the key binding screen.
+    pub binding: Option<Action>,
+    /// Window size chosen in the options, the main loop resizes the window.
+    pub window_size: Option<(u32, u32)>
+}
+
+impl Default for Menu {
+    fn default() -> Self {
+        Menu { screens: vec![Screen::Title], selected: 0, binding: None, window_size: None }
+    }
+}
+
+impl Menu {
+    pub fn is_open(&self) -> bool {
+        !self.screens.is_empty()
+    }
+
+    pub fn screen(&self) -> Option<Screen> {
+        self.screens.last().copied()
+    }
+
+    pub fn open(&mut self, screen: Screen) {
+        self.screens.push(screen);
+        self.selected = 0;
+    }
+
+    fn back(&mut self) {
+        self.screens.pop();
+        self.selected = 0;
+    }
+}
+
+fn items(screen: Screen, player: &PlayerData, game_data: &GameData) -> Vec<(String, Choice)> {
+    let settings = &game_data.settings;
+    let slots = || (1..=MENU_SLOTS).map(|slot| slot.to_string());
+    let slot_label = |slot: &str| match save::slot_path(slot) {
+        Ok(path) if std::path::Path::new(&path).exists() => format!("Slot {}", slot),
+        _ => format!("Slot {} - empty", slot)
+    };
+
+    match screen {
+        Screen::Title => Vec::new(),
+        Screen::Main => vec![
+            ("New game".to_string(), Choice::NewGame),
+            ("Load game".to_string(), Choice::Open(Screen::Load)),
+            ("Options".to_string(), Choice::Open(Screen::Options)),
+            ("Quit".to_string(), Choice::Open(Screen::QuitConfirm))
+        ],
+        Screen::Paused => vec![
+            ("Resume".to_string(), Choice::Resume),
+            ("Save game".to_string(), Choice::Open(Screen::Save)),
+            ("Load game".to_string(), Choice::Open(Screen::Load)),
+            ("Options".to_string(), Choice::Open(Screen::Options)),
+            ("Main menu".to_string(), Choice::MainMenu),
+            ("Quit".to_string(), Choice::Open(Screen::QuitConfirm))
+        ],
+        Screen::Options => vec![
+            (format!("Resolution: {}x{}", game_data.width, game_data.height), Choice::Resolution),
+            (format!("Field of view: {:.0}", player.fov()), Choice::Fov),
+            (format!("Mouse sensitivity: {:.1}", settings.mouse_sensitivity_x * 1000.0), Choice::MouseSensitivity),
+            (format!("Look sensitivity: {:.1}", settings.mouse_sensitivity_y), Choice::LookSensitivity),
+            (format!("Invert mouse: {}", if settings.invert_y {"on"} else {"off"}), Choice::InvertY),
+            (format!("Sound volume: {:.0}%", settings.sound_volume * 100.0), Choice::SoundVolume),
+            (format!("Music volume: {:.0}%", settings.music_volume * 100.0), Choice::MusicVolume),
+            ("Keys".to_string(), Choice::Open(Screen::Keys)),
+            ("Back".to_string(), Choice::Back)
+        ],
+        Screen::Keys => Action::ALL.iter()
+            .map(|&action| {
+                let keys = if game_data.menu.binding == Some(action) {"press a key".to_string()} else {settings.bindings.keys_to_string(action)};
+                (format!("{}: {}", action.name().replace('_', " "), keys), Choice::Bind(action))
+            })
+            .chain([("Back".to_string(), Choice::Back)])
+            .collect(),
+        Screen::Save => slots().map(|slot| (slot_label(&slot), Choice::SaveSlot(slot))).chain([("Back".to_string(), Choice::Back)]).collect(),
+        Screen::Load => [QUICKSAVE_SLOT.to_string()].into_iter().chain(slots())
+            .map(|slot| (if slot == QUICKSAVE_SLOT {"Quicksave".to_string()} else {slot_label(&slot)}, Choice::LoadSlot(slot)))
+            .chain([("Back".to_string(), Choice::Back)])
+            .collect(),
+        Screen::QuitConfirm => vec![
+            ("Yes".to_string(), Choice::Quit),
+            ("No".to_string(), Choice::Back)
+        ]
+    }
+}
+
+/// Chooses the item, `step` is 0 for Enter or a click and -1/1 for left/right, which only change values.
+fn choose(choice: Choice, step: i32, player: &mut PlayerData, game_data: &mut GameData) -> EndGame {
+    let menu = &mut game_data.menu;
+    let settings = &mut game_data.settings;
+    let step_or_next = if step == 0 {1} else {step};
+
+    match choice {
+        _ if step != 0 && !matches!(choice, Choice::Resolution | Choice::Fov | Choice::MouseSensitivity | Choice::LookSensitivity | Choice::InvertY | Choice::SoundVolume | Choice::MusicVolume) => {},
+        Choice::NewGame => {
+            // Starts the map over, doors and switches included.
+            if let Err(error) = game_data.load_map(game_data.map_source.clone()) {
+                game_data.console.print(&error);
+            }
+            *player = game_data.new_player();
+            game_data.menu.screens.clear();
+        },
+        Choice::Resume => menu.screens.clear(),
+        Choice::Open(screen) => menu.open(screen),
+        Choice::MainMenu => {
+            menu.screens = vec![Screen::Main];
+            menu.selected = 0;
+        },
+        Choice::Quit => return EndGame(true),
+        Choice::Back => leave(game_data),
+        Choice::Resolution => {
+            let current = WINDOW_SIZES.iter().position(|&size| size == (game_data.width, game_data.height));
+            let next = match current {
+                Some(index) => (index as i32 + step_or_next).rem_euclid(WINDOW_SIZES.len() as i32) as usize,
+                None => 0
+            };
+            menu.window_size = Some(WINDOW_SIZES[next]);
+        },
+        Choice::Fov => {
+            // Steps of 5 degrees, the default isn't on one.
+            settings.fov = (((settings.fov / 5.0).round() + step_or_next as f64) * 5.0).clamp(MIN_FOV, MAX_FOV);
+            player.set_fov(settings.fov);
+        },
+        Choice::MouseSensitivity => settings.mouse_sensitivity_x = (settings.mouse_sensitivity_x + 0.0005 * step_or_next as f64).clamp(0.0005, 0.02),
+        Choice::LookSensitivity => settings.mouse_sensitivity_y = (settings.mouse_sensitivity_y + 0.1 * step_or_next as f64).clamp(0.1, 5.0),
+        Choice::InvertY => settings.invert_y = !settings.invert_y,
+        Choice::SoundVolume => settings.sound_volume = (settings.sound_volume + 0.1 * step_or_next as f64).clamp(0.0, 1.0),
+        Choice::MusicVolume => settings.music_volume = (settings.music_volume + 0.1 * step_or_next as f64).clamp(0.0, 1.0),
+        Choice::Bind(action) => menu.binding = Some(action),
+        Choice::SaveSlot(slot) => {
+            if let Err(error) = console::save(&[&slot], player, game_data) {
+                game_data.console.print(&format!("save: {}", error));
+            }
+        },
+        // Only a load that worked goes back to playing, the HUD under the menu says when it didn't.
+        Choice::LoadSlot(slot) => match console::load(&[&slot], player, game_data) {
+            Ok(()) => game_data.menu.screens.clear(),
+            Err(error) => game_data.console.print(&format!("load: {}", error))
+        }
+    }
+
+    EndGame(false)
+}
+
+/// Goes back a screen, the options get written to the settings file on the way out.
+fn leave(game_data: &mut GameData) {
+    if game_data.menu.screen() == Some(Screen::Options) {
+        if let Err(error) = game_data.settings.save(SETTINGS_FILE) {
+            game_data.console.print(&error);
+        }
+    }
+
+    // There is nothing below the main menu, Esc there asks before quitting.
+    if game_data.menu.screens.len() == 1 && game_data.menu.screen() == Some(Screen::Main) {
+        game_data.menu.open(Screen::QuitConfirm);
+        return;
+    }
+
+    game_data.menu.back();
+}
+
+/// What the keyboard, mouse and controller do in the menus.
+enum Input {
+    Up,
+    Down,
+    Left,
+    Right,
+    Choose,
+    Back
+}
+
+fn handle(input: Input, player: &mut PlayerData, game_data: &mut GameData) -> EndGame {
+    let Some(screen) = game_data.menu.screen() else {
+        return EndGame(false);
+    };
+
+    // Any key gets past the title.
+    if screen == Screen::Title {
+        game_data.menu.screens = vec![Screen::Main];
+        return EndGame(false);
+    }
+
+    let mut items = items(screen, player, game_data);
+    let count = items.len();
+    let menu = &mut game_data.menu;
+
+    match input {
+        Input::Up => menu.selected = (menu.selected + count - 1) % count,
+        Input::Down => menu.selected = (menu.selected + 1) % count,
+        Input::Back => leave(game_data),
+        Input::Left | Input::Right | Input::Choose if menu.selected < count => {
+            let step = match input {
+                Input::Left => -1,
+                Input::Right => 1,
+                _ => 0
+            };
+            return choose(items.swap_remove(menu.selected).1, step, player, game_data);
+        },
+        _ => {}
+    }
+
+    EndGame(false)
+}
+
+pub fn key_down(scancode: Scancode, player: &mut PlayerData, game_data: &mut GameData) -> EndGame {
+    // Waiting for the key of an action, Esc keeps the old ones.
+    if let Some(action) = game_data.menu.binding.take() {
+        if scancode != Scancode::Escape {
+            game_data.settings.bindings.bind(action, vec![scancode]);
+        }
+        return EndGame(false);
+    }
+
+    if game_data.settings.bindings.actions(scancode).any(|action| action == Action::ToggleConsole) {
+        game_data.console.open = true;
+        return EndGame(false);
+    }
+
+    let input = match scancode {
+        Scancode::Up | Scancode::W => Input::Up,
+        Scancode::Down | Scancode::S => Input::Down,
+        Scancode::Left | Scancode::A => Input::Left,
+        Scancode::Right | Scancode::D => Input::Right,
+        Scancode::Return | Scancode::KpEnter | Scancode::Space => Input::Choose,
+        Scancode::Escape | Scancode::Backspace => Input::Back,
+        _ if game_data.menu.screen() == Some(Screen::Title) => Input::Choose,
+        _ => return EndGame(false)
+    };
+
+    handle(input, player, game_data)
+}
+
+pub fn pad_input(input: PadInput, player: &mut PlayerData, game_data: &mut GameData) -> EndGame {
+    // Controllers can't type a key, binding keeps waiting for the keyboard.
+    let input = match input {
+        PadInput::Button(Button::DPadUp) => Input::Up,
+        PadInput::Button(Button::DPadDown) => Input::Down,
+        PadInput::Button(Button::DPadLeft) => Input::Left,
+        PadInput::Button(Button::DPadRight) => Input::Right,
+        PadInput::Button(Button::A | Button::Start) => Input::Choose,
+        PadInput::Button(Button::B | Button::Back) => Input::Back,
+        _ => return EndGame(false)
+    };
+
+    handle(input, player, game_data)
+}
+
+/// The items are rows across the whole screen, pointing at one selects it.
+pub fn mouse_move(y: i32, player: &PlayerData, game_data: &mut GameData) {
+    let Some(screen) = game_data.menu.screen() else {
+        return;
+    };
+
+    if let Some(index) = item_at(y, items(screen, player, game_data).len(), game_data) {
+        game_data.menu.selected = index;
+    }
+}
+
+/// Left click chooses, right click goes back.
+pub fn mouse_click(y: i32, left: bool, player: &mut PlayerData, game_data: &mut GameData) -> EndGame {
+    if !left {
+        return handle(Input::Back, player, game_data);
+    }
+
+    let count = game_data.menu.screen().map_or(0, |screen| items(screen, player, game_data).len());
+    match item_at(y, count, game_data) {
+        Some(index) => {
+            game_data.menu.selected = index;
+            handle(Input::Choose, player, game_data)
+        },
+        None if game_data.menu.screen() == Some(Screen::Title) => handle(Input::Choose, player, game_data),
+        None => EndGame(false)
+    }
+}
+
+/// Text scale, the top of the first item and the height of an item, for drawing and the mouse alike.
+/// Long screens like the keys get smaller text, so the items and the title still fit.
+fn layout(height: u32, item_count: usize, line_height: u32) -> (u32, i32, i32) {
+    let fitting = height / ((item_count as u32 + 6) * (line_height + 2));
+    let scale = (height / HUD_BASE_HEIGHT).min(fitting).max(1);
+    let item_height = ((line_height + 2) * scale) as i32;
+    let top = (height as i32 - item_count as i32 * item_height) / 2 + item_height;
+
+    (scale, top, item_height)
+}
+
+fn item_at(y: i32, count: usize, game_data: &GameData) -> Option<usize> {
+    let (_, top, item_height) = layout(game_data.height, count, game_data.font.line_height);
+    let index = (y - top).div_euclid(item_height);
+
+    (0..count as i32).contains(&index).then_some(index as usize)
+}
+
+/// Dims everything drawn so far and puts the screen's title and items over it.
+pub fn draw(player: &PlayerData, game_data: &GameData, buffer: &mut FrameBuffer) {
+    let Some(screen) = game_data.menu.screen() else {
+        return;
+    };
+
+    let width = buffer.width as i32;
+    let font = &game_data.font;
+    buffer.fill_rect(0, 0, width, buffer.height as i32, DIM);
+
+    let items = items(screen, player, game_data);
+    let (scale, top, item_height) = layout(buffer.height, items.len(), font.line_height);
+
+    let title = match screen {
+        Screen::Title => "DOOM RENDERER",
+        Screen::Main => "MAIN MENU",
+        Screen::Paused => "PAUSED",
+        Screen::Options => "OPTIONS",
+        Screen::Keys => "KEYS",
+        Screen::Save => "SAVE GAME",
+        Screen::Load => "LOAD GAME",
+        Screen::QuitConfirm => "QUIT THE GAME?"
+    };
+    let title_style = TextStyle::new(TITLE_COLOR, 3 * scale).align(Align::Center);
+    let title_height = (font.line_height * 3 * scale) as i32;
+    font.draw(buffer, title, width / 2, top - item_height - title_height, &title_style);
+
+    if screen == Screen::Title {
+        let style = TextStyle::new(ITEM_COLOR, scale).align(Align::Center);
+        font.draw(buffer, "Press any key", width / 2, buffer.height as i32 * 2 / 3, &style);
+        return;
+    }
+
+    for (index, (label, _)) in items.iter().enumerate() {
+        let selected = index == game_data.menu.selected;
+        let style = TextStyle::new(if selected {SELECTED_COLOR} else {ITEM_COLOR}, scale).align(Align::Center);
+        let text = if selected {format!("> {} <", label)} else {label.clone()};
+        font.draw(buffer, &text, width / 2, top + index as i32 * item_height, &style);
+    }
+}
@@ -0,0 +1,441 @@
+use std::f64::consts::TAU;
+use std::sync::Arc;
+
+use crate::config::{MAX_VOICES, MIX_RATE};
+use crate::random::Rng;
+
+/// A channel message of a MIDI file at its time in seconds, the tempo changes are already worked in.
+pub struct MidiEvent {
+    pub time: f64,
+    pub message: [u8; 3]
+}
+
+/// Reads MIDI's variable length numbers.
+fn variable(data: &[u8], pos: &mut usize) -> Result<u32, String> {
+    let mut value = 0;
+
+    loop {
+        let byte = *data.get(*pos).ok_or("MIDI track ends too early")?;
+        *pos += 1;
+        value = (value << 7) | (byte & 0x7F) as u32;
+
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+}
+
+fn read_u32(data: &[u8], pos: usize) -> Result<u32, String> {
+    data.get(pos..pos + 4).map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]])).ok_or_else(|| "MIDI file is too short".to_string())
+}
+
+/// What a track holds at a tick, the tempo in µs per quarter note or a channel message.
+enum TrackEvent {
+    Tempo(u32),
+    Message([u8; 3])
+}
+
+fn read_track(data: &[u8]) -> Result<Vec<(u64, TrackEvent)>, String> {
+    let mut events = Vec::new();
+    let (mut pos, mut tick, mut running_status) = (0, 0u64, 0u8);
+    let byte = |pos: &mut usize| -> Result<u8, String> {
+        let value = *data.get(*pos).ok_or("MIDI track ends too early")?;
+        *pos += 1;
+        Ok(value)
+    };
+
+    while pos < data.len() {
+        tick += variable(data, &mut pos)? as u64;
+
+        let status = if data.get(pos).is_some_and(|&b| b & 0x80 != 0) {byte(&mut pos)?} else {running_status};
+        match status {
+            0xFF => {
+                let kind = byte(&mut pos)?;
+                let length = variable(data, &mut pos)? as usize;
+                let meta = data.get(pos..pos + length).ok_or("MIDI track ends too early")?;
+                pos += length;
+
+                match kind {
+                    0x51 if length == 3 => events.push((tick, TrackEvent::Tempo(u32::from_be_bytes([0, meta[0], meta[1], meta[2]])))),
+                    0x2F => break,
+                    _ => {}
+                }
+            },
+            // System exclusive, nothing a synth like this one cares about.
+            0xF0 | 0xF7 => pos += variable(data, &mut pos)? as usize,
+            0x80..=0xEF => {
+                running_status = status;
+                let first = byte(&mut pos)?;
+                // Program change and channel pressure have one data byte, the rest two.
+                let second = if matches!(status & 0xF0, 0xC0 | 0xD0) {0} else {byte(&mut pos)?};
+                events.push((tick, TrackEvent::Message([status, first, second])));
+            },
+            _ => return Err(format!("unexpected MIDI status {:#04x}", status))
+        }
+    }
+
+    Ok(events)
+}
+
+/// Reads a standard MIDI file (format 0 or 1), the tracks get merged into one list in time order.
+pub fn parse(data: &[u8]) -> Result<Vec<MidiEvent>, String> {
+    if data.get(0..4) != Some(b"MThd") {
+        return Err("not a MIDI file".to_string());
+    }
+
+    let header_length = read_u32(data, 4)? as usize;
+    let division = data.get(12..14).map(|b| u16::from_be_bytes([b[0], b[1]])).ok_or("MIDI header is too short")?;
+    if division & 0x8000 != 0 || division == 0 {
+        return Err("MIDI files with SMPTE time aren't supported".to_string());
+    }
+
+    let mut events = Vec::new();
+    let mut pos = 8 + header_length;
+    while pos + 8 <= data.len() {
+        let length = read_u32(data, pos + 4)? as usize;
+        let chunk = data.get(pos + 8..pos + 8 + length).ok_or("MIDI chunk goes past the end of the file")?;
+
+        // Unknown chunks are allowed and skipped.
+        if &data[pos..pos + 4] == b"MTrk" {
+            events.extend(read_track(chunk)?);
+        }
+        pos += 8 + length;
+    }
+
+    // Stable, so events at the same tick keep the order of their tracks.
+    events.sort_by_key(|(tick, _)| *tick);
+
+    let mut tempo = 500_000.0;
+    let (mut time, mut last_tick) = (0.0, 0);
+    let mut messages = Vec::new();
+
+    for (tick, event) in events {
+        time += (tick - last_tick) as f64 * tempo / 1_000_000.0 / division as f64;
+        last_tick = tick;
+
+        match event {
+            TrackEvent::Tempo(microseconds) => tempo = microseconds as f64,
+            TrackEvent::Message(message) => messages.push(MidiEvent { time, message })
+        }
+    }
+
+    Ok(messages)
+}
+
+#[derive(Clone, Copy)]
+enum Wave {
+    Sine,
+    Triangle,
+    Square,
+    Saw,
+    Noise
+}
+
+/// How a voice sounds, times in seconds. Without sustain the note dies away on its own.
+#[derive(Clone, Copy)]
+struct Instrument {
+    wave: Wave,
+    attack: f64,
+    decay: f64,
+    sustain: f64
+}
+
+impl Instrument {
+    const fn new(wave: Wave, attack: f64, decay: f64, sustain: f64) -> Self {
+        Instrument { wave, attack, decay, sustain }
+    }
+}
+
+/// One instrument per General MIDI family of 8 programs (pianos, chromatic percussion, organs, ...).
+const FAMILIES: [Instrument; 16] = [
+    Instrument::new(Wave::Triangle, 0.005, 1.5, 0.0),
+    Instrument::new(Wave::Sine, 0.005, 1.0, 0.0),
+    Instrument::new(Wave::Square, 0.01, 0.5, 0.8),
+    Instrument::new(Wave::Saw, 0.005, 1.2, 0.1),
+    Instrument::new(Wave::Triangle, 0.005, 1.0, 0.5),
+    Instrument::new(Wave::Saw, 0.08, 0.5, 0.8),
+    Instrument::new(Wave::Saw, 0.1, 0.5, 0.8),
+    Instrument::new(Wave::Saw, 0.03, 0.5, 0.7),
+    Instrument::new(Wave::Square, 0.03, 0.5, 0.7),
+    Instrument::new(Wave::Sine, 0.05, 0.5, 0.8),
+    Instrument::new(Wave::Square, 0.005, 0.5, 0.8),
+    Instrument::new(Wave::Triangle, 0.2, 0.5, 0.8),
+    Instrument::new(Wave::Triangle, 0.1, 0.5, 0.6),
+    Instrument::new(Wave::Saw, 0.005, 0.8, 0.2),
+    Instrument::new(Wave::Sine, 0.005, 0.3, 0.0),
+    Instrument::new(Wave::Noise, 0.005, 0.5, 0.0)
+];
+
+/// Channel 10 (9 counting from 0) plays drums, the note picks the drum: the sound, its pitch and how long it rings.
+fn drum(note: u8) -> (Instrument, f64) {
+    match note {
+        35 | 36 => (Instrument::new(Wave::Sine, 0.001, 0.25, 0.0), 60.0),
+        38 | 40 => (Instrument::new(Wave::Noise, 0.001, 0.2, 0.0), 0.0),
+        42 | 44 => (Instrument::new(Wave::Noise, 0.001, 0.05, 0.0), 0.0),
+        46 => (Instrument::new(Wave::Noise, 0.001, 0.3, 0.0), 0.0),
+        49 | 57 => (Instrument::new(Wave::Noise, 0.001, 1.0, 0.0), 0.0),
+        51 | 59 => (Instrument::new(Wave::Noise, 0.001, 0.6, 0.0), 0.0),
+        // Toms.
+        41 | 43 | 45 | 47 | 48 | 50 => (Instrument::new(Wave::Sine, 0.001, 0.3, 0.0), 80.0 + (note - 41) as f64 * 15.0),
+        _ => (Instrument::new(Wave::Noise, 0.001, 0.15, 0.0), 0.0)
+    }
+}
+
+const RELEASE: f64 = 0.15;
+const PERCUSSION: u8 = 9;
+
+#[derive(Clone, Copy)]
+struct ChannelState {
+    program: u8,
+    volume: f64,
+    expression: f64,
+    /// 0.0 left - 1.0 right.
+    pan: f64,
+    /// Semitones.
+    bend: f64
+}
+
+impl Default for ChannelState {
+    fn default() -> Self {
+        ChannelState { program: 0, volume: 100.0 / 127.0, expression: 1.0, pan: 0.5, bend: 0.0 }
+    }
+}
+
+struct Voice {
+    channel: u8,
+    note: u8,
+    velocity: f64,
+    instrument: Instrument,
+    /// Hz, fixed for drums.
+    pitch: Option<f64>,
+    phase: f64,
+    step: f64,
+    /// Envelope, 0.0 - 1.0.
+    level: f64,
+    attacking: bool,
+    released: bool
+}
+
+impl Voice {
+    fn update_step(&mut self, bend: f64) {
+        let frequency = self.pitch.unwrap_or_else(|| 440.0 * 2f64.powf((self.note as f64 - 69.0 + bend) / 12.0));
+        self.step = frequency / MIX_RATE as f64;
+    }
+
+    fn sample(&mut self, noise: &mut Rng) -> f64 {
+        let rate = MIX_RATE as f64;
+        let instrument = self.instrument;
+
+        if self.attacking {
+            self.level += 1.0 / (instrument.attack * rate);
+            if self.level >= 1.0 {
+                self.level = 1.0;
+                self.attacking = false;
+            }
+        } else if self.released {
+            self.level *= (-1.0 / (RELEASE * rate)).exp();
+        } else {
+            self.level = instrument.sustain + (self.level - instrument.sustain) * (-1.0 / (instrument.decay * rate)).exp();
+        }
+
+        let value = match instrument.wave {
+            Wave::Sine => (self.phase * TAU).sin(),
+            Wave::Triangle => 1.0 - 4.0 * (self.phase - 0.5).abs(),
+            Wave::Square => if self.phase < 0.5 {1.0} else {-1.0},
+            Wave::Saw => 2.0 * self.phase - 1.0,
+            Wave::Noise => noise.unit() * 2.0 - 1.0
+        };
+        self.phase = (self.phase + self.step).fract();
+
+        value * self.level * self.velocity
+    }
+
+    fn finished(&self) -> bool {
+        !self.attacking && self.level < 0.001 && (self.released || self.instrument.sustain == 0.0)
+    }
+}
+
+/// Plays MIDI events with simple waveforms, one made up instrument per family of General MIDI programs.
+/// Runs in the audio thread, the song starts over when it ends.
+pub struct Synth {
+    events: Arc<Vec<MidiEvent>>,
+    next: usize,
+    /// Seconds since the start of the song.
+    time: f64,
+    channels: [ChannelState; 16],
+    voices: Vec<Voice>,
+    noise: Rng
+}
+
+impl Synth {
+    pub fn new(events: Vec<MidiEvent>) -> Self {
+        Synth { events: Arc::new(events), next: 0, time: 0.0, channels: [ChannelState::default(); 16], voices: Vec::new(), noise: Rng::new(1) }
+    }
+
+    fn note_on(&mut self, channel: u8, note: u8, velocity: u8) {
+        let state = self.channels[channel as usize];
+        let (instrument, pitch) = if channel == PERCUSSION {
+            let (instrument, pitch) = drum(note);
+            (instrument, (pitch > 0.0).then_some(pitch))
+        } else {
+            (FAMILIES[state.program as usize / 8], None)
+        };
+
+        // Out of voices, the oldest one makes room.
+        if self.voices.len() >= MAX_VOICES {
+            self.voices.remove(0);
+        }
+
+        let mut voice = Voice {
+            channel, note, velocity: velocity as f64 / 127.0, instrument, pitch, phase: 0.0, step: 0.0, level: 0.0, attacking: true, released: false
+        };
+        voice.update_step(state.bend);
+        self.voices.push(voice);
+    }
+
+    fn note_off(&mut self, channel: u8, note: u8) {
+        // Drums ring out on their own.
+        if channel == PERCUSSION {
+            return;
+        }
+
+        for voice in self.voices.iter_mut().filter(|voice| voice.channel == channel && voice.note == note) {
+            voice.released = true;
+        }
+    }
+
+    fn handle(&mut self, [status, first, second]: [u8; 3]) {
+        let channel = status & 0x0F;
+        let state = &mut self.channels[channel as usize];
+
+        match status & 0xF0 {
+            0x90 if second > 0 => self.note_on(channel, first, second),
+            0x80 | 0x90 => self.note_off(channel, first),
+            0xB0 => match first {
+                7 => state.volume = second as f64 / 127.0,
+                10 => state.pan = second as f64 / 127.0,
+                11 => state.expression = second as f64 / 127.0,
+                120 | 123 => self.voices.iter_mut().filter(|voice| voice.channel == channel).for_each(|voice| voice.released = true),
+                121 => *state = ChannelState { program: state.program, ..ChannelState::default() },
+                _ => {}
+            },
+            0xC0 => state.program = first & 0x7F,
+            0xE0 => {
+                // 14 bits with 8192 in the middle, two semitones each way.
+                state.bend = ((((second as u16) << 7) | first as u16) as f64 - 8192.0) / 8192.0 * 2.0;
+                let bend = state.bend;
+                self.voices.iter_mut().filter(|voice| voice.channel == channel).for_each(|voice| voice.update_step(bend));
+            },
+            _ => {}
+        }
+    }
+
+    /// Adds the next frames to interleaved stereo `out`, scaled by `volume`.
+    pub fn render(&mut self, out: &mut [f32], volume: f32) {
+        for frame in out.chunks_exact_mut(2) {
+            while let Some(event) = self.events.get(self.next).filter(|event| event.time <= self.time) {
+                let message = event.message;
+                self.next += 1;
+                self.handle(message);
+            }
+
+            let (mut left, mut right) = (0.0, 0.0);
+            for voice in &mut self.voices {
+                let state = &self.channels[voice.channel as usize];
+                let sample = voice.sample(&mut self.noise) * state.volume * state.expression;
+                left += sample * (2.0 * (1.0 - state.pan)).min(1.0);
+                right += sample * (2.0 * state.pan).min(1.0);
+            }
+
+            // Loud enough for a few voices at once without clipping right away.
+            frame[0] += (left * 0.15) as f32 * volume;
+            frame[1] += (right * 0.15) as f32 * volume;
+            self.time += 1.0 / MIX_RATE as f64;
+        }
+
+        self.voices.retain(|voice| !voice.finished());
+
+        // Once every note has died away the song starts over.
+        if self.next >= self.events.len() && self.voices.is_empty() {
+            self.next = 0;
+            self.time = 0.0;
+            self.channels = [ChannelState::default(); 16];
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A MIDI file with 96 ticks per quarter note, half a second at the default tempo.
+    fn midi(chunks: &[(&[u8; 4], &[u8])]) -> Vec<u8> {
+        let mut data = [b"MThd".as_slice(), &6u32.to_be_bytes(), &[0, 1], &(chunks.len() as u16).to_be_bytes(), &96u16.to_be_bytes()].concat();
+        for (kind, chunk) in chunks {
+            data.extend([kind.as_slice(), &(chunk.len() as u32).to_be_bytes(), chunk].concat());
+        }
+        data
+    }
+
+    fn events(data: &[u8]) -> Vec<(f64, [u8; 3])> {
+        parse(data).unwrap().into_iter().map(|event| (event.time, event.message)).collect()
+    }
+
+    #[test]
+    fn running_status() {
+        let track = [0, 0x90, 60, 100, 96, 62, 90, 0, 0xC1, 5, 0, 7, 0, 0x80, 60, 0];
+
+        assert_eq!(events(&midi(&[(b"MTrk", &track)])), [
+            (0.0, [0x90, 60, 100]), (0.5, [0x90, 62, 90]), (0.5, [0xC1, 5, 0]), (0.5, [0xC1, 7, 0]), (0.5, [0x80, 60, 0])
+        ]);
+    }
+
+    #[test]
+    fn skips_meta_events_and_sysex() {
+        let track = [
+            0, 0xFF, 0x03, 3, b'a', b'b', b'c', 0, 0x90, 60, 100, 0, 0xF0, 2, 1, 0xF7,
+            // A tempo with the wrong length is skipped like any other meta event.
+            0, 0xFF, 0x51, 2, 1, 2, 96, 0x90, 61, 100,
+            0, 0xFF, 0x2F, 0, 0, 0x90, 99, 99
+        ];
+
+        assert_eq!(events(&midi(&[(b"MTrk", &track)])), [(0.0, [0x90, 60, 100]), (0.5, [0x90, 61, 100])]);
+    }
+
+    #[test]
+    fn tempo_changes_mid_track() {
+        // A second per quarter note from the second event on.
+        let track = [0, 0x90, 60, 100, 96, 0xFF, 0x51, 3, 0x0F, 0x42, 0x40, 96, 0x80, 60, 0];
+
+        assert_eq!(events(&midi(&[(b"MTrk", &track)])), [(0.0, [0x90, 60, 100]), (1.5, [0x80, 60, 0])]);
+    }
+
+    #[test]
+    fn merges_the_tracks() {
+        let first = [0, 0x90, 60, 100, 96, 0x80, 60, 0];
+        // Its tempo of a quarter second per quarter note counts for the first track too.
+        let second = [0, 0xFF, 0x51, 3, 0x03, 0xD0, 0x90, 48, 0x91, 64, 100];
+
+        assert_eq!(events(&midi(&[(b"MTrk", &first), (b"XTRA", &[1, 2, 3]), (b"MTrk", &second)])), [
+            (0.0, [0x90, 60, 100]), (0.125, [0x91, 64, 100]), (0.25, [0x80, 60, 0])
+        ]);
+    }
+
+    #[test]
+    fn broken_files() {
+        let mut smpte = midi(&[(b"MTrk", &[0, 0x90, 60, 100])]);
+        smpte[12..14].copy_from_slice(&0xE728u16.to_be_bytes());
+        assert_eq!(parse(&smpte).err().unwrap(), "MIDI files with SMPTE time aren't supported");
+        smpte[12..14].copy_from_slice(&[0, 0]);
+        assert!(parse(&smpte).is_err());
+
+        assert_eq!(parse(b"RIFF").err().unwrap(), "not a MIDI file");
+        assert!(parse(&midi(&[(b"MTrk", &[0, 0x90, 60])])).is_err());
+        assert!(parse(&midi(&[(b"MTrk", &[0, 0xFF, 0x51, 3, 1])])).is_err());
+        assert!(parse(&midi(&[(b"MTrk", &[0, 0xF1])])).is_err());
+
+        let mut cut = midi(&[(b"MTrk", &[0, 0x90, 60, 100])]);
+        cut.pop();
+        assert_eq!(parse(&cut).err().unwrap(), "MIDI chunk goes past the end of the file");
+    }
+}
@@ -0,0 +1,269 @@
+/// MUS ticks are 1/140 s, with 70 ticks per quarter note at 500000 µs per quarter the MIDI file runs at the same speed.
+const TICKS_PER_QUARTER: u16 = 70;
+const TEMPO: u32 = 500_000;
+
+/// MIDI channel the percussion of MUS channel 15 goes to.
+const PERCUSSION: u8 = 9;
+
+/// MUS controller numbers 0-9 as MIDI controllers, 0 is the instrument and becomes a program change.
+const CONTROLLERS: [u8; 10] = [0, 0, 1, 7, 10, 11, 91, 93, 64, 67];
+
+/// MUS system events 10-14 as MIDI controllers: all sounds off, all notes off, mono, poly and reset all controllers.
+const SYSTEM_EVENTS: [u8; 5] = [120, 123, 126, 127, 121];
+
+fn byte(data: &[u8], pos: &mut usize) -> Result<u8, String> {
+    let value = *data.get(*pos).ok_or("MUS score ends too early")?;
+    *pos += 1;
+    Ok(value)
+}
+
+fn read_u16(data: &[u8], pos: usize) -> Result<u16, String> {
+    data.get(pos..pos + 2).map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]])).ok_or_else(|| "MUS header is too short".to_string())
+}
+
+/// MIDI's variable length numbers, 7 bits per byte with the high bit set on all but the last.
+fn write_variable(track: &mut Vec<u8>, value: u32) {
+    let mut bytes = vec![(value & 0x7F) as u8];
+    let mut value = value >> 7;
+
+    while value > 0 {
+        bytes.push((value & 0x7F) as u8 | 0x80);
+        value >>= 7;
+    }
+
+    track.extend(bytes.iter().rev());
+}
+
+/// Converts a Doom MUS lump to a standard MIDI file (format 0, one track), the same way Doom's
+/// MUS2MID tools do. The same MUS data always gives the same bytes.
+pub fn mus_to_midi(data: &[u8]) -> Result<Vec<u8>, String> {
+    if data.get(0..4) != Some(b"MUS\x1A") {
+        return Err("not a MUS lump".to_string());
+    }
+
+    let score_length = read_u16(data, 4)? as usize;
+    let mut pos = read_u16(data, 6)? as usize;
+    let score_end = (pos + score_length).min(data.len());
+
+    let mut track = Vec::new();
+    // Tempo first, at time 0.
+    track.extend([0x00, 0xFF, 0x51, 0x03]);
+    track.extend(&TEMPO.to_be_bytes()[1..]);
+
+    // MUS channels get MIDI channels in the order they are first used, 9 is only for percussion.
+    let mut channels: [Option<u8>; 16] = [None; 16];
+    let mut next_channel = 0;
+    // Notes without a volume use the last one of their channel.
+    let mut volumes = [127u8; 16];
+    let mut delay = 0;
+
+    while pos < score_end {
+        let event = byte(data, &mut pos)?;
+        let last = event & 0x80 != 0;
+        let kind = (event >> 4) & 0x07;
+        let mus_channel = (event & 0x0F) as usize;
+
+        let channel = match channels[mus_channel] {
+            Some(channel) => channel,
+            None => {
+                let channel = if mus_channel == 15 {
+                    PERCUSSION
+                } else {
+                    if next_channel == PERCUSSION {
+                        next_channel += 1;
+                    }
+                    next_channel += 1;
+                    next_channel - 1
+                };
+                channels[mus_channel] = Some(channel);
+
+                // A channel starts silent, like in MUS2MID.
+                write_variable(&mut track, delay);
+                track.extend([0xB0 | channel, 123, 0]);
+                delay = 0;
+                channel
+            }
+        };
+
+        let message: Option<Vec<u8>> = match kind {
+            // Release note.
+            0 => Some(vec![0x80 | channel, byte(data, &mut pos)? & 0x7F, 0]),
+            // Play note, the high bit says a volume follows.
+            1 => {
+                let note = byte(data, &mut pos)?;
+                if note & 0x80 != 0 {
+                    volumes[mus_channel] = byte(data, &mut pos)? & 0x7F;
+                }
+                Some(vec![0x90 | channel, note & 0x7F, volumes[mus_channel]])
+            },
+            // Pitch bend, 128 is the middle as 8192 is for MIDI.
+            2 => {
+                let bend = byte(data, &mut pos)? as u16 * 64;
+                Some(vec![0xE0 | channel, (bend & 0x7F) as u8, (bend >> 7) as u8 & 0x7F])
+            },
+            // System event.
+            3 => {
+                let controller = byte(data, &mut pos)?;
+                let midi = SYSTEM_EVENTS.get((controller as usize).wrapping_sub(10))
+                    .ok_or_else(|| format!("unknown MUS system event {}", controller))?;
+                Some(vec![0xB0 | channel, *midi, 0])
+            },
+            // Change controller.
+            4 => {
+                let controller = byte(data, &mut pos)?;
+                let value = byte(data, &mut pos)?.min(127);
+                match controller {
+                    0 => Some(vec![0xC0 | channel, value]),
+                    1..=9 => Some(vec![0xB0 | channel, CONTROLLERS[controller as usize], value]),
+                    _ => return Err(format!("unknown MUS controller {}", controller))
+                }
+            },
+            // End of measure, nothing happens.
+            5 => None,
+            // Score end.
+            6 => break,
+            _ => return Err(format!("unknown MUS event {}", kind))
+        };
+
+        if let Some(message) = message {
+            write_variable(&mut track, delay);
+            track.extend(message);
+            delay = 0;
+        }
+
+        // Adds up, events without a MIDI message (end of measure) still wait.
+        if last {
+            let mut time = 0;
+            loop {
+                let value = byte(data, &mut pos)?;
+                time = (time << 7) | (value & 0x7F) as u32;
+                if value & 0x80 == 0 {
+                    break;
+                }
+            }
+            delay += time;
+        }
+    }
+
+    write_variable(&mut track, delay);
+    track.extend([0xFF, 0x2F, 0x00]);
+
+    let mut midi = b"MThd".to_vec();
+    midi.extend(6u32.to_be_bytes());
+    midi.extend(0u16.to_be_bytes());
+    midi.extend(1u16.to_be_bytes());
+    midi.extend(TICKS_PER_QUARTER.to_be_bytes());
+    midi.extend(b"MTrk");
+    midi.extend((track.len() as u32).to_be_bytes());
+    midi.extend(track);
+
+    Ok(midi)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A MUS lump with the score right after the 16 byte header and no instrument list.
+    fn mus(score: &[u8]) -> Vec<u8> {
+        let mut data = b"MUS\x1A".to_vec();
+        for value in [score.len() as u16, 16, 1, 0, 0, 0] {
+            data.extend(value.to_le_bytes());
+        }
+        data.extend(score);
+        data
+    }
+
+    /// The MIDI file with the tempo and end of track around `events`.
+    fn midi(events: &[u8]) -> Vec<u8> {
+        let track = [&[0x00, 0xFF, 0x51, 0x03, 0x07, 0xA1, 0x20], events, &[0x00, 0xFF, 0x2F, 0x00]].concat();
+
+        let mut midi = b"MThd\0\0\0\x06\0\0\0\x01\0\x46MTrk".to_vec();
+        midi.extend((track.len() as u32).to_be_bytes());
+        midi.extend(track);
+        midi
+    }
+
+    #[test]
+    fn header_and_tempo() {
+        assert_eq!(mus_to_midi(&mus(&[0x60])).unwrap(), [
+            b'M', b'T', b'h', b'd', 0, 0, 0, 6, 0, 0, 0, 1, 0, 70,
+            b'M', b'T', b'r', b'k', 0, 0, 0, 15,
+            0x00, 0xFF, 0x51, 0x03, 0x07, 0xA1, 0x20,
+            0x00, 0xB0, 123, 0,
+            0x00, 0xFF, 0x2F, 0x00
+        ]);
+    }
+
+    #[test]
+    fn notes_keep_the_last_volume() {
+        let score = [0x10, 60, 0x10, 0x80 | 62, 80, 0x10, 64, 0x00, 60, 0x60];
+
+        assert_eq!(mus_to_midi(&mus(&score)).unwrap(), midi(&[
+            0x00, 0xB0, 123, 0,
+            0x00, 0x90, 60, 127,
+            0x00, 0x90, 62, 80,
+            0x00, 0x90, 64, 80,
+            0x00, 0x80, 60, 0
+        ]));
+    }
+
+    #[test]
+    fn delays_add_up() {
+        // 128 ticks after the note, then an end of measure 10 ticks later and the release right after it.
+        let score = [0x90, 60, 0x81, 0x00, 0xD0, 10, 0x00, 60, 0x60];
+
+        assert_eq!(mus_to_midi(&mus(&score)).unwrap(), midi(&[
+            0x00, 0xB0, 123, 0,
+            0x00, 0x90, 60, 127,
+            0x81, 0x0A, 0x80, 60, 0
+        ]));
+    }
+
+    #[test]
+    fn percussion_goes_to_channel_9() {
+        let score = [0x1F, 36, 0x11, 48, 0x61];
+
+        assert_eq!(mus_to_midi(&mus(&score)).unwrap(), midi(&[
+            0x00, 0xB9, 123, 0,
+            0x00, 0x99, 36, 127,
+            0x00, 0xB0, 123, 0,
+            0x00, 0x90, 48, 127
+        ]));
+
+        // The other channels go around it.
+        let score: Vec<u8> = (0..10).flat_map(|channel| [0x10 | channel, 60]).chain([0x60]).collect();
+        let events: Vec<u8> = [0, 1, 2, 3, 4, 5, 6, 7, 8, 10].iter()
+            .flat_map(|&channel| [0x00, 0xB0 | channel, 123, 0, 0x00, 0x90 | channel, 60, 127])
+            .collect();
+        assert_eq!(mus_to_midi(&mus(&score)).unwrap(), midi(&events));
+    }
+
+    #[test]
+    fn controllers() {
+        // Instrument, volume (clamped to 127), all notes off and the pitch bend in the middle.
+        let score = [0x40, 0, 5, 0x40, 3, 100, 0x40, 3, 200, 0x30, 11, 0x20, 128, 0x60];
+
+        assert_eq!(mus_to_midi(&mus(&score)).unwrap(), midi(&[
+            0x00, 0xB0, 123, 0,
+            0x00, 0xC0, 5,
+            0x00, 0xB0, 7, 100,
+            0x00, 0xB0, 7, 127,
+            0x00, 0xB0, 123, 0,
+            0x00, 0xE0, 0x00, 0x40
+        ]));
+    }
+
+    #[test]
+    fn broken_lumps() {
+        assert!(mus_to_midi(b"MThd\0\0\0\x06").is_err());
+        assert!(mus_to_midi(b"MUS\x1A\x01").is_err());
+
+        // Missing note, missing delay, unknown controller, system event and event.
+        assert!(mus_to_midi(&mus(&[0x10])).is_err());
+        assert!(mus_to_midi(&mus(&[0x90, 60, 0x81])).is_err());
+        assert!(mus_to_midi(&mus(&[0x40, 10, 0])).is_err());
+        assert!(mus_to_midi(&mus(&[0x30, 5])).is_err());
+        assert!(mus_to_midi(&mus(&[0x70])).is_err());
+    }
+}
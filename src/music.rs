@@ -0,0 +1,225 @@
+use std::fs;
+
+use sdl2::audio::{AudioCVT, AudioFormat, AudioSpecWAV};
+use sdl2::rwops::RWops;
+
+use crate::config::{MIX_RATE, MUSIC_FADE_TIME};
+use crate::midi::{self, Synth};
+use crate::mus::mus_to_midi;
+use crate::wad::Wad;
+
+/// A piece of music that plays in a loop, either decoded samples or MIDI events played by the synth.
+pub enum Song {
+    /// Interleaved stereo at MIX_RATE.
+    Samples { samples: Vec<i16>, position: usize },
+    /// Boxed, the synth is much bigger than the samples.
+    Midi(Box<Synth>)
+}
+
+impl Song {
+    /// Tells the format by its first bytes: MUS, MIDI, WAV or OGG.
+    pub fn from_bytes(data: &[u8]) -> Result<Song, String> {
+        let samples = match data.get(0..4) {
+            Some(b"MUS\x1A") => return Ok(Song::Midi(Box::new(Synth::new(midi::parse(&mus_to_midi(data)?)?)))),
+            Some(b"MThd") => return Ok(Song::Midi(Box::new(Synth::new(midi::parse(data)?)))),
+            Some(b"RIFF") => decode_wav(data)?,
+            Some(b"OggS") => decode_ogg(data)?,
+            _ => return Err("unknown music format, expected MUS, MIDI, WAV or OGG".to_string())
+        };
+
+        if samples.len() < 2 {
+            return Err("the music has no samples".to_string());
+        }
+        Ok(Song::Samples { samples, position: 0 })
+    }
+
+    pub fn from_file(filename: &str) -> Result<Song, String> {
+        let data = fs::read(filename).map_err(|e| format!("{}: {}", filename, e))?;
+        Song::from_bytes(&data).map_err(|e| format!("{}: {}", filename, e))
+    }
+
+    pub fn from_lump(wad: &Wad, name: &str) -> Result<Song, String> {
+        let lump = wad.lump(name).ok_or_else(|| format!("no music lump {}", name))?;
+        Song::from_bytes(&lump.data).map_err(|e| format!("{}: {}", name, e))
+    }
+
+    /// Adds the next frames to interleaved stereo `out`, starting over at the end.
+    fn mix_into(&mut self, out: &mut [f32], volume: f32) {
+        match self {
+            Song::Samples { samples, position } => {
+                for sample in out.iter_mut() {
+                    *sample += samples[*position] as f32 * volume;
+                    *position = (*position + 1) % samples.len();
+                }
+            },
+            Song::Midi(synth) => synth.render(out, volume * i16::MAX as f32)
+        }
+    }
+}
+
+/// 16-bit stereo at MIX_RATE out of whatever SDL can read.
+fn convert(format: AudioFormat, channels: u8, rate: i32, bytes: Vec<u8>) -> Result<Vec<i16>, String> {
+    let converter = AudioCVT::new(format, channels, rate, AudioFormat::s16_sys(), 2, MIX_RATE)?;
+
+    Ok(converter.convert(bytes)
+        .chunks_exact(2)
+        .map(|sample| i16::from_ne_bytes([sample[0], sample[1]]))
+        .collect())
+}
+
+fn decode_wav(data: &[u8]) -> Result<Vec<i16>, String> {
+    let wav = AudioSpecWAV::load_wav_rw(&mut RWops::from_bytes(data)?)?;
+    convert(wav.format, wav.channels, wav.freq, wav.buffer().to_vec())
+}
+
+/// SDL_mixer decodes OGG only with a device of its own open, so it gets opened just for loading.
+#[cfg(feature = "ogg")]
+fn decode_ogg(data: &[u8]) -> Result<Vec<i16>, String> {
+    use sdl2::mixer::{self, InitFlag, LoaderRWops, AUDIO_S16SYS};
+
+    use crate::config::MIX_SAMPLES;
+
+    let _context = mixer::init(InitFlag::OGG)?;
+    mixer::open_audio(MIX_RATE, AUDIO_S16SYS, 2, MIX_SAMPLES as i32)?;
+
+    let decoded = RWops::from_bytes(data).and_then(|rwops| rwops.load_wav()).and_then(|chunk| {
+        let (rate, format, channels) = mixer::query_spec()?;
+        if format != AUDIO_S16SYS {
+            return Err("SDL_mixer opened with another sample format".to_string());
+        }
+
+        // SAFETY: load_wav() only returns chunks it could load, abuf holds alen bytes until the chunk is dropped.
+        let bytes = unsafe { std::slice::from_raw_parts((*chunk.raw).abuf, (*chunk.raw).alen as usize).to_vec() };
+        Ok((rate, channels as u8, bytes))
+    });
+    mixer::close_audio();
+
+    let (rate, channels, bytes) = decoded?;
+    convert(AudioFormat::s16_sys(), channels, rate, bytes)
+}
+
+#[cfg(not(feature = "ogg"))]
+fn decode_ogg(_data: &[u8]) -> Result<Vec<i16>, String> {
+    Err("OGG needs SDL_mixer, build with --features ogg".to_string())
+}
+
+/// Runs in the mixer, the song that was playing fades out while the new one fades in.
+/// Songs are never dropped in the audio thread, freeing a synth or a decoded song takes too long there.
+#[derive(Default)]
+pub struct MusicPlayer {
+    current: Option<Song>,
+    fading: Option<Song>,
+    /// How loud the fading song was when it started to fade out, 0.0 - 1.0.
+    fading_from: f32,
+    /// 0.0 - 1.0 of the crossfade.
+    fade: f32,
+    /// The song that faded out, for the main thread to drop (take_finished()).
+    finished: Option<Song>,
+    /// 0.0 - 1.0.
+    pub volume: f32
+}
+
+impl MusicPlayer {
+    /// Crossfades to `song`, None fades to silence. In the middle of a crossfade the louder of the two songs
+    /// fades out from where it is, the other one is returned so the caller drops it.
+    pub fn start(&mut self, song: Option<Song>) -> Option<Song> {
+        let current = self.current.as_ref().map_or(0.0, |_| self.fade);
+        let fading = if self.fade < 1.0 {self.fading.as_ref().map_or(0.0, |_| self.fading_from * (1.0 - self.fade))} else {0.0};
+
+        let (louder, quieter, from) = if current >= fading {
+            (self.current.take(), self.fading.take(), current)
+        } else {
+            (self.fading.take(), self.current.take(), fading)
+        };
+
+        self.fading = louder;
+        self.fading_from = from;
+        self.current = song;
+        self.fade = 0.0;
+        quieter
+    }
+
+    /// The song that is done fading out, if there is one.
+    pub fn take_finished(&mut self) -> Option<Song> {
+        self.finished.take()
+    }
+
+    /// Adds the music to interleaved stereo `out`, the samples are 16-bit range.
+    pub fn mix(&mut self, out: &mut [f32]) {
+        if let Some(fading) = self.fading.as_mut().filter(|_| self.fade < 1.0) {
+            fading.mix_into(out, self.volume * self.fading_from * (1.0 - self.fade));
+        }
+        if let Some(current) = &mut self.current {
+            current.mix_into(out, self.volume * self.fade);
+        }
+
+        // Per chunk of samples, that's about 23 ms with MIX_SAMPLES of 1024.
+        self.fade = (self.fade + (out.len() / 2) as f32 / (MUSIC_FADE_TIME * MIX_RATE as f32)).min(1.0);
+
+        // Silent from here on, it waits in `fading` while the main thread hasn't taken the last one yet.
+        if self.fade >= 1.0 && self.finished.is_none() {
+            self.finished = self.fading.take();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn song(level: i16) -> Option<Song> {
+        Some(Song::Samples { samples: vec![level; 2], position: 0 })
+    }
+
+    fn level(song: Option<Song>) -> Option<i16> {
+        match song? {
+            Song::Samples { samples, .. } => Some(samples[0]),
+            Song::Midi(_) => None
+        }
+    }
+
+    /// Mixes `seconds` of music, returns the last frame.
+    fn play(player: &mut MusicPlayer, seconds: f32) -> f32 {
+        let mut out = vec![0.0; (seconds * MIX_RATE as f32) as usize * 2];
+        player.mix(&mut out);
+        out[out.len() - 1]
+    }
+
+    #[test]
+    fn crossfades_keep_the_louder_song() {
+        let mut player = MusicPlayer { volume: 1.0, ..MusicPlayer::default() };
+        assert!(player.start(song(1000)).is_none());
+        play(&mut player, MUSIC_FADE_TIME);
+
+        // A quarter into the fade to 2000, then 3000 comes. 1000 is louder and keeps fading out from there.
+        assert!(player.start(song(2000)).is_none());
+        play(&mut player, MUSIC_FADE_TIME / 4.0);
+        assert_eq!(level(player.start(song(3000))), Some(2000));
+        assert_eq!(play(&mut player, 0.0001).round(), 750.0);
+
+        // Three quarters into the fade to 3000, 3000 is the louder one now.
+        play(&mut player, MUSIC_FADE_TIME * 0.75);
+        assert_eq!(level(player.start(song(4000))), Some(1000));
+        play(&mut player, MUSIC_FADE_TIME);
+        assert_eq!(player.fade, 1.0);
+        assert_eq!(level(player.take_finished()), Some(3000));
+    }
+
+    #[test]
+    fn finished_songs_wait_for_the_main_thread() {
+        let mut player = MusicPlayer { volume: 1.0, ..MusicPlayer::default() };
+        player.start(song(1000));
+        play(&mut player, MUSIC_FADE_TIME);
+        player.start(song(2000));
+        play(&mut player, MUSIC_FADE_TIME);
+        player.start(song(3000));
+        play(&mut player, MUSIC_FADE_TIME);
+
+        // 1000 is still waiting to be taken, 2000 stays in the player without being heard.
+        assert_eq!(play(&mut player, 0.01), 3000.0);
+        assert_eq!(level(player.take_finished()), Some(1000));
+        play(&mut player, 0.01);
+        assert_eq!(level(player.take_finished()), Some(2000));
+        assert!(player.take_finished().is_none());
+    }
+}
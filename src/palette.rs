@@ -0,0 +1,232 @@
+use std::fs;
+
+use sdl2::pixels::Color;
+
+use crate::config::{FOG_COLOR, FOG_DISTANCE, LIGHT_LEVELS};
+use crate::data::LoadedTexture;
+
+/// 256 colors, everything in palette mode is an index into this.
+pub struct Palette {
+    pub colors: Vec<Color>
+}
+
+impl Palette {
+    /// Missing colors are filled up with black, extra ones are ignored.
+    pub fn new(mut colors: Vec<Color>) -> Self {
+        colors.resize(256, Color::BLACK);
+        Palette { colors }
+    }
+
+    /// Takes the first 256 pixels of an image, row by row (a 256x1 strip or a 16x16 grid).
+    pub fn from_png(filename: &str) -> Result<Self, String> {
+        let image = LoadedTexture::from_file(filename)?;
+        let colors = image.pixels.chunks_exact(4).take(256).map(|p| Color::RGB(p[0], p[1], p[2])).collect();
+
+        Ok(Palette::new(colors))
+    }
+
+    /// Raw RGB triplets like Doom's PLAYPAL, only the first palette is used.
+    pub fn from_playpal(filename: &str) -> Result<Self, String> {
+        let bytes = fs::read(filename).map_err(|e| format!("{}: {}", filename, e))?;
+        if bytes.len() < 768 {
+            return Err(format!("{}: A palette needs 768 bytes", filename));
+        }
+
+        Ok(Palette::new(bytes[..768].chunks_exact(3).map(|rgb| Color::RGB(rgb[0], rgb[1], rgb[2])).collect()))
+    }
+
+    /// Median cut over the pixels of the textures, used if no palette was given.
+    pub fn from_textures(textures: &[LoadedTexture]) -> Self {
+        let pixels: Vec<[u8; 3]> = textures.iter()
+            .flat_map(|texture| texture.pixels.chunks_exact(4).map(|p| [p[0], p[1], p[2]]))
+            .collect();
+
+        let mut boxes = vec![pixels];
+
+        // Keep splitting the box with the widest channel until there are enough colors.
+        while boxes.len() < 256 {
+            let widest = boxes.iter().enumerate()
+                .filter(|(_, pixels)| pixels.len() > 1)
+                .map(|(i, pixels)| {
+                    let (channel, range) = widest_channel(pixels);
+                    (i, channel, range)
+                })
+                .max_by_key(|&(_, _, range)| range);
+
+            let Some((index, channel, range)) = widest else {
+                break;
+            };
+            if range == 0 {
+                break;
+            }
+
+            let mut pixels = boxes.swap_remove(index);
+            pixels.sort_unstable_by_key(|p| p[channel]);
+            let upper = pixels.split_off(pixels.len() / 2);
+            boxes.push(pixels);
+            boxes.push(upper);
+        }
+
+        Palette::new(boxes.iter().map(|pixels| {
+            let count = pixels.len().max(1) as u32;
+            let sum = pixels.iter().fold([0u32; 3], |sum, p| [sum[0] + p[0] as u32, sum[1] + p[1] as u32, sum[2] + p[2] as u32]);
+            Color::RGB((sum[0] / count) as u8, (sum[1] / count) as u8, (sum[2] / count) as u8)
+        }).collect())
+    }
+
+    /// The index of the closest color.
+    pub fn nearest(&self, color: Color) -> u8 {
+        let mut best = 0;
+        let mut best_dist = i32::MAX;
+
+        for (i, c) in self.colors.iter().enumerate() {
+            // Weighted a bit towards green, the eye sees that best.
+            let dist = 3 * (c.r as i32 - color.r as i32).pow(2)
+                + 4 * (c.g as i32 - color.g as i32).pow(2)
+                + 2 * (c.b as i32 - color.b as i32).pow(2);
+
+            if dist < best_dist {
+                best_dist = dist;
+                best = i;
+            }
+        }
+
+        best as u8
+    }
+}
+
+/// The channel (r, g, b) with the largest spread and that spread.
+fn widest_channel(pixels: &[[u8; 3]]) -> (usize, u8) {
+    (0..3).map(|channel| {
+        let min = pixels.iter().map(|p| p[channel]).min().unwrap_or(0);
+        let max = pixels.iter().map(|p| p[channel]).max().unwrap_or(0);
+        (channel, max - min)
+    }).max_by_key(|&(_, range)| range).unwrap_or((0, 0))
+}
+
+/// Precomputed shading, `maps[level][index]` is the color index fading towards the fog color.
+/// Level 0 is full brightness, LIGHT_LEVELS-1 is completely fogged.
+pub struct Colormap {
+    pub maps: Vec<[u8; 256]>
+}
+
+impl Colormap {
+    pub fn new(palette: &Palette) -> Self {
+        let maps = (0..LIGHT_LEVELS).map(|level| {
+            let brightness = 1.0 - level as f64 / (LIGHT_LEVELS - 1) as f64;
+            let mut map = [0; 256];
+
+            for (i, color) in palette.colors.iter().enumerate() {
+                let mix = |channel: u8, fog: u8| (fog as f64 + (channel as f64 - fog as f64) * brightness) as u8;
+                map[i] = palette.nearest(Color::RGB(
+                    mix(color.r, FOG_COLOR.r),
+                    mix(color.g, FOG_COLOR.g),
+                    mix(color.b, FOG_COLOR.b)
+                ));
+            }
+
+            map
+        }).collect();
+
+        Colormap { maps }
+    }
+
+    /// The map for a light (0.0 - 1.0) at some distance, things far away fade into the fog.
+    pub fn map(&self, light: f64, dist: f64) -> &[u8; 256] {
+        let levels = LIGHT_LEVELS as f64;
+        let level = (1.0 - light) * levels + dist / FOG_DISTANCE * levels;
+
+        &self.maps[(level as usize).min(LIGHT_LEVELS - 1)]
+    }
+}
+
+/// A texture quantized to the palette.
+pub struct IndexedTexture {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>
+}
+
+impl IndexedTexture {
+    pub fn new(texture: &LoadedTexture, palette: &Palette) -> Self {
+        IndexedTexture {
+            width: texture.width,
+            height: texture.height,
+            pixels: texture.pixels.chunks_exact(4).map(|p| palette.nearest(Color::RGB(p[0], p[1], p[2]))).collect()
+        }
+    }
+
+    pub fn get_pixel(&self, x: usize, y: usize) -> u8 {
+        self.pixels.get(x + y * self.width as usize).copied().unwrap_or(0)
+    }
+}
+
+/// Framebuffer of palette indices, only turned into colors when it gets presented.
+pub struct IndexedBuffer {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>
+}
+
+impl IndexedBuffer {
+    pub fn new(width: u32, height: u32) -> Self {
+        IndexedBuffer {
+            width,
+            height,
+            pixels: vec![0; (width * height) as usize]
+        }
+    }
+
+    pub fn resize(&mut self, width: u32, height: u32) {
+        if self.width == width && self.height == height {
+            return;
+        }
+
+        self.width = width;
+        self.height = height;
+        self.pixels = vec![0; (width * height) as usize];
+    }
+
+    pub fn clear(&mut self, index: u8) {
+        self.pixels.fill(index);
+    }
+
+    /// Draws a vertical line, both ends are inclusive.
+    pub fn ver_line(&mut self, x: i32, draw_start: i32, draw_end: i32, index: u8) {
+        if x < 0 || x >= self.width as i32 {
+            return;
+        }
+
+        for y in draw_start.max(0)..=draw_end.min(self.height as i32 - 1) {
+            self.pixels[y as usize * self.width as usize + x as usize] = index;
+        }
+    }
+
+    /// Converts the indices into RGBA.
+    pub fn to_rgba(&self, palette: &Palette, out: &mut [u8]) {
+        for (index, pixel) in self.pixels.iter().zip(out.chunks_exact_mut(4)) {
+            let color = palette.colors[*index as usize];
+            pixel.copy_from_slice(&[color.r, color.g, color.b, 255]);
+        }
+    }
+}
+
+/// Everything palette mode needs, built once when it gets turned on.
+pub struct PaletteData {
+    pub palette: Palette,
+    pub colormap: Colormap,
+    /// Same order as GameData::textures.
+    pub textures: Vec<IndexedTexture>,
+    pub sky: Option<IndexedTexture>
+}
+
+impl PaletteData {
+    pub fn new(palette: Palette, textures: &[LoadedTexture], sky: Option<&LoadedTexture>) -> Self {
+        PaletteData {
+            colormap: Colormap::new(&palette),
+            textures: textures.iter().map(|texture| IndexedTexture::new(texture, &palette)).collect(),
+            sky: sky.map(|sky| IndexedTexture::new(sky, &palette)),
+            palette
+        }
+    }
+}
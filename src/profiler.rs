@@ -0,0 +1,321 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fs;
+use std::time::Instant;
+
+use sdl2::pixels::Color;
+
+use crate::config::{HUD_BASE_HEIGHT, PROFILE_FRAMES};
+use crate::font::{Align, Font, TextStyle};
+use crate::framebuffer::FrameBuffer;
+
+/// The parts of a frame that get timed, see scope!().
+#[derive(Clone, Copy)]
+pub enum Stage {
+    Input,
+    Simulation,
+    RayCasting,
+    Walls,
+    Floors,
+    Sprites,
+    Present
+}
+
+const STAGE_COUNT: usize = 7;
+
+impl Stage {
+    pub const ALL: [Stage; STAGE_COUNT] = [
+        Stage::Input,
+        Stage::Simulation,
+        Stage::RayCasting,
+        Stage::Walls,
+        Stage::Floors,
+        Stage::Sprites,
+        Stage::Present
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Stage::Input => "input",
+            Stage::Simulation => "simulation",
+            Stage::RayCasting => "ray casting",
+            Stage::Walls => "walls",
+            Stage::Floors => "floors",
+            Stage::Sprites => "sprites",
+            Stage::Present => "present"
+        }
+    }
+
+    fn color(&self) -> Color {
+        match self {
+            Stage::Input => Color::RGB(120, 120, 255),
+            Stage::Simulation => Color::RGB(255, 120, 255),
+            Stage::RayCasting => Color::RGB(255, 200, 0),
+            Stage::Walls => Color::RGB(255, 80, 80),
+            Stage::Floors => Color::RGB(80, 200, 80),
+            Stage::Sprites => Color::RGB(0, 200, 255),
+            Stage::Present => Color::RGB(200, 200, 200)
+        }
+    }
+}
+
+/// Milliseconds of a finished frame, the stages don't count the time of the stages nested in them.
+#[derive(Clone, Copy, Default)]
+pub struct FrameTimes {
+    pub total: f64,
+    pub stages: [f64; STAGE_COUNT]
+}
+
+/// A Chrome trace being recorded, written out once enough frames are in.
+struct Trace {
+    filename: String,
+    frames_left: usize,
+    events: Vec<String>
+}
+
+struct Profiler {
+    epoch: Instant,
+    frame_start: Instant,
+    current: FrameTimes,
+    /// Time of the scopes nested in every open scope, taken off their own time when they close.
+    #[cfg(feature = "profiler")]
+    nested: Vec<f64>,
+    /// The stages timed with accumulate!() this frame, when the first piece started and all of them added up.
+    #[cfg(feature = "profiler")]
+    pieces: [(Option<Instant>, f64); STAGE_COUNT],
+    history: VecDeque<FrameTimes>,
+    trace: Option<Trace>
+}
+
+impl Profiler {
+    fn new() -> Self {
+        Profiler {
+            epoch: Instant::now(),
+            frame_start: Instant::now(),
+            current: FrameTimes::default(),
+            #[cfg(feature = "profiler")]
+            nested: Vec::new(),
+            #[cfg(feature = "profiler")]
+            pieces: [(None, 0.0); STAGE_COUNT],
+            history: VecDeque::new(),
+            trace: None
+        }
+    }
+
+    /// Microseconds since the start, the time stamps of the trace.
+    fn timestamp(&self, instant: Instant) -> f64 {
+        instant.duration_since(self.epoch).as_secs_f64() * 1_000_000.0
+    }
+
+    fn trace_event(&mut self, name: &str, start: Instant, milliseconds: f64) {
+        let ts = self.timestamp(start);
+
+        if let Some(trace) = &mut self.trace {
+            trace.events.push(format!(
+                "{{\"name\":\"{}\",\"ph\":\"X\",\"ts\":{:.3},\"dur\":{:.3},\"pid\":1,\"tid\":1}}",
+                name, ts, milliseconds * 1000.0
+            ));
+        }
+    }
+}
+
+thread_local! {
+    static PROFILER: RefCell<Profiler> = RefCell::new(Profiler::new());
+}
+
+/// Times everything until the end of the block as a stage. Only with the `profiler` feature,
+/// without it the markers are gone and the overlay only has the frame times.
+#[cfg(feature = "profiler")]
+macro_rules! scope {
+    ($stage:expr) => {
+        let _scope = $crate::profiler::Scope::new($stage);
+    };
+}
+
+#[cfg(not(feature = "profiler"))]
+macro_rules! scope {
+    ($stage:expr) => {
+        let _ = $stage;
+    };
+}
+
+/// Like scope!(), for a stage that runs in many short pieces like the wall columns. The pieces only get added up,
+/// the trace gets a single event for all of them at the end of the frame.
+#[cfg(feature = "profiler")]
+macro_rules! accumulate {
+    ($stage:expr) => {
+        let _piece = $crate::profiler::Piece::new($stage);
+    };
+}
+
+#[cfg(not(feature = "profiler"))]
+macro_rules! accumulate {
+    ($stage:expr) => {
+        let _ = $stage;
+    };
+}
+
+pub(crate) use {accumulate, scope};
+
+/// Made by scope!(), the time gets added to the stage when it is dropped.
+#[cfg(feature = "profiler")]
+pub struct Scope {
+    stage: Stage,
+    start: Instant
+}
+
+#[cfg(feature = "profiler")]
+impl Scope {
+    pub fn new(stage: Stage) -> Self {
+        PROFILER.with(|profiler| profiler.borrow_mut().nested.push(0.0));
+        Scope { stage, start: Instant::now() }
+    }
+}
+
+#[cfg(feature = "profiler")]
+impl Drop for Scope {
+    fn drop(&mut self) {
+        let milliseconds = self.start.elapsed().as_secs_f64() * 1000.0;
+
+        PROFILER.with(|profiler| {
+            let mut profiler = profiler.borrow_mut();
+            let nested = profiler.nested.pop().unwrap_or(0.0);
+            profiler.current.stages[self.stage as usize] += milliseconds - nested;
+
+            if let Some(parent) = profiler.nested.last_mut() {
+                *parent += milliseconds;
+            }
+
+            profiler.trace_event(self.stage.name(), self.start, milliseconds);
+        });
+    }
+}
+
+/// Made by accumulate!(), cheaper than a Scope: no nesting of its own and no trace event.
+#[cfg(feature = "profiler")]
+pub struct Piece {
+    stage: Stage,
+    start: Instant
+}
+
+#[cfg(feature = "profiler")]
+impl Piece {
+    pub fn new(stage: Stage) -> Self {
+        Piece { stage, start: Instant::now() }
+    }
+}
+
+#[cfg(feature = "profiler")]
+impl Drop for Piece {
+    fn drop(&mut self) {
+        let milliseconds = self.start.elapsed().as_secs_f64() * 1000.0;
+
+        PROFILER.with(|profiler| {
+            let mut profiler = profiler.borrow_mut();
+            profiler.current.stages[self.stage as usize] += milliseconds;
+            if let Some(parent) = profiler.nested.last_mut() {
+                *parent += milliseconds;
+            }
+
+            let (first, total) = &mut profiler.pieces[self.stage as usize];
+            first.get_or_insert(self.start);
+            *total += milliseconds;
+        });
+    }
+}
+
+pub fn begin_frame() {
+    PROFILER.with(|profiler| {
+        let mut profiler = profiler.borrow_mut();
+        profiler.frame_start = Instant::now();
+        profiler.current = FrameTimes::default();
+    });
+}
+
+/// Keeps the times of the last PROFILE_FRAMES frames. Returns what happened to the trace once it is written.
+pub fn end_frame() -> Option<Result<String, String>> {
+    PROFILER.with(|profiler| {
+        let mut profiler = profiler.borrow_mut();
+        let start = profiler.frame_start;
+        profiler.current.total = start.elapsed().as_secs_f64() * 1000.0;
+
+        let frame = profiler.current;
+        profiler.history.push_back(frame);
+        if profiler.history.len() > PROFILE_FRAMES {
+            profiler.history.pop_front();
+        }
+
+        #[cfg(feature = "profiler")]
+        for stage in Stage::ALL {
+            if let (Some(first), milliseconds) = std::mem::replace(&mut profiler.pieces[stage as usize], (None, 0.0)) {
+                profiler.trace_event(stage.name(), first, milliseconds);
+            }
+        }
+        profiler.trace_event("frame", start, frame.total);
+
+        let trace = profiler.trace.as_mut()?;
+        trace.frames_left -= 1;
+        if trace.frames_left > 0 {
+            return None;
+        }
+
+        let trace = profiler.trace.take()?;
+        let json = format!("{{\"traceEvents\":[\n{}\n]}}\n", trace.events.join(",\n"));
+        Some(fs::write(&trace.filename, json)
+            .map(|_| format!("wrote {} ({} events)", trace.filename, trace.events.len()))
+            .map_err(|e| format!("{}: {}", trace.filename, e)))
+    })
+}
+
+/// Records the next frames as a Chrome trace (chrome://tracing or Perfetto).
+pub fn start_trace(frames: usize, filename: &str) {
+    PROFILER.with(|profiler| {
+        profiler.borrow_mut().trace = Some(Trace { filename: filename.to_string(), frames_left: frames.max(1), events: Vec::new() });
+    });
+}
+
+/// Frame time graph and the average time of every stage, in the top right corner under the FPS counter.
+pub fn draw_overlay(font: &Font, buffer: &mut FrameBuffer) {
+    let scale = (buffer.height / HUD_BASE_HEIGHT).max(1) as i32;
+    let graph_width = PROFILE_FRAMES as i32 * scale;
+    let graph_height = 40 * scale;
+    let line_height = font.line_height as i32 * scale;
+    let right = buffer.width as i32 - 2 * scale;
+    let left = right - graph_width;
+    let top = 12 * scale;
+
+    PROFILER.with(|profiler| {
+        let profiler = profiler.borrow();
+        let history = &profiler.history;
+        let count = history.len().max(1) as f64;
+
+        let rows = if cfg!(feature = "profiler") {STAGE_COUNT + 1} else {2};
+        buffer.fill_rect(left - 2 * scale, top - 2 * scale, graph_width + 4 * scale, graph_height + (rows as i32 + 1) * line_height + 4 * scale, Color::RGBA(0, 0, 0, 160));
+
+        // One bar per frame, 1 pixel per millisecond, the line is at 33 ms (30 fps).
+        for (i, frame) in history.iter().enumerate() {
+            let x = left + i as i32 * scale;
+            let bar = (frame.total * scale as f64).min(graph_height as f64) as i32;
+            let color = if frame.total < 1000.0 / 60.0 {Color::GREEN} else if frame.total < 1000.0 / 30.0 {Color::YELLOW} else {Color::RED};
+            buffer.fill_rect(x, top + graph_height - bar, scale, bar, color);
+        }
+        buffer.fill_rect(left, top + graph_height - (1000.0 / 30.0 * scale as f64) as i32, graph_width, scale, Color::RGBA(255, 255, 255, 128));
+
+        let average = history.iter().map(|frame| frame.total).sum::<f64>() / count;
+        let mut y = top + graph_height + 2 * scale;
+        let style = TextStyle::new(Color::WHITE, scale as u32).align(Align::Right);
+        font.draw(buffer, &format!("frame {:.2} ms", average), right, y, &style);
+
+        if !cfg!(feature = "profiler") {
+            y += line_height;
+            font.draw(buffer, "build with --features profiler", right, y, &style);
+            return;
+        }
+
+        for stage in Stage::ALL {
+            y += line_height;
+            let time = history.iter().map(|frame| frame.stages[stage as usize]).sum::<f64>() / count;
+            font.draw(buffer, &format!("{} {:.2} ms", stage.name(), time), right, y, &TextStyle::new(stage.color(), scale as u32).align(Align::Right));
+        }
+    });
+}
@@ -0,0 +1,38 @@
+/// Small xorshift generator, the same seed always gives the same game.
+pub struct Rng {
+    state: u64
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        // Xorshift never leaves 0.
+        Rng { state: seed.max(1) }
+    }
+
+    /// Where the sequence is, Rng::new() with it carries on from there.
+    pub fn state(&self) -> u64 {
+        self.state
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        self.state
+    }
+
+    /// 0 up to (not including) `n`.
+    pub fn below(&mut self, n: u32) -> u32 {
+        (self.next_u64() % n.max(1) as u64) as u32
+    }
+
+    /// 0.0 up to (not including) 1.0.
+    pub fn unit(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// True with the given probability (0.0 - 1.0).
+    pub fn chance(&mut self, probability: f64) -> bool {
+        self.unit() < probability
+    }
+}
@@ -1,143 +1,150 @@
-use sdl2::{pixels::Color, rect::Point, render::{Canvas, Texture}, video::Window};
+use std::collections::HashMap;
+
+use sdl2::{pixels::Color, rect::Point, render::Canvas, video::Window};
+
+use crate::data::{GameData, LoadedTexture, PlayerData, Vec2};
+use crate::framebuffer::FrameBuffer;
+use crate::palette::IndexedBuffer;
+use crate::profiler::{self, Stage};
+use crate::sky::{sky_color, sky_tex_x, sky_tex_y};
+use crate::sprite::draw_sprites;
+
+/// The color of a wall type in the non-textured mode.
+pub fn wall_color(wall: i32) -> Color {
+    match wall {
+        1 => Color::RED,
+        2 => Color::GREEN,
+        3 => Color::BLUE,
+        4 => Color::GRAY,
+        _ => Color::CYAN
+    }
+}
 
-use crate::{data::{GameData, PlayerData}, Vec2, WORLD_MAP};
+/// The texture of a wall type in the textured mode.
+pub fn wall_texture(game_data: &GameData, wall: i32) -> &LoadedTexture {
+    match wall {
+        1 => &game_data.textures[0],
+        2 => &game_data.textures[1],
+        3 => &game_data.textures[2],
+        4 => &game_data.textures[3],
+        _ => &game_data.textures[4]
+    }
+}
 
 pub fn ver_line(x: i32, draw_start: i32, draw_end: i32, color: Color, canvas: &mut Canvas<Window>) {
     canvas.set_draw_color(color);
     let _ = canvas.draw_line(Point::new(x, draw_start), Point::new(x, draw_end));
 }
 
+/// What render_view_canvas() draws on: the window in the game, a FrameBuffer in the benchmarks.
+pub trait DrawTarget {
+    fn draw_point(&mut self, x: i32, y: i32, color: Color);
+    fn ver_line(&mut self, x: i32, draw_start: i32, draw_end: i32, color: Color);
+}
 
-pub fn render_view(player: &PlayerData, buffer: &mut Texture, game_data: &GameData) {
-    let mut pos_x = player.pos.x;
-    let mut pos_y = player.pos.y;
-    
-    let mut dir_x = player.dir.x;
-    let mut dir_y = player.dir.y;
-
-    let mut plane_x = player.cam.x;
-    let mut plane_y = player.cam.y;
-
+impl DrawTarget for Canvas<Window> {
+    fn draw_point(&mut self, x: i32, y: i32, color: Color) {
+        self.set_draw_color(color);
+        let _ = Canvas::draw_point(self, Point::new(x, y));
+    }
 
-    for x in 0..game_data.width as i32 {
-        let mut camera_x = 2.0 * (x as f64) / (game_data.width as f64) - 1.0;
-        let mut ray_dir_x = dir_x + plane_x * camera_x;
-        let mut ray_dir_y = dir_y + plane_y * camera_x;
+    fn ver_line(&mut self, x: i32, draw_start: i32, draw_end: i32, color: Color) {
+        ver_line(x, draw_start, draw_end, color, self);
+    }
+}
 
-        let mut map_x: i32 = pos_x as i32;
-        let mut map_y: i32 = pos_y as i32;
+impl DrawTarget for FrameBuffer {
+    fn draw_point(&mut self, x: i32, y: i32, color: Color) {
+        self.set_pixel(x, y, color);
+    }
 
-        let mut side_dist_x: f64;
-        let mut side_dist_y: f64;
+    /// The untextured walls aren't clipped to the screen, the canvas does that itself.
+    fn ver_line(&mut self, x: i32, draw_start: i32, draw_end: i32, color: Color) {
+        FrameBuffer::ver_line(self, x, draw_start.max(0), draw_end.min(self.height as i32 - 1), color);
+    }
+}
 
-        let mut delta_dist_x: f64 = if ray_dir_x == 0.0 {1e30} else {(1.0/ray_dir_x).abs()};
-        let mut delta_dist_y: f64 = if ray_dir_y == 0.0 {1e30} else {(1.0/ray_dir_y).abs()};
 
-        let mut perp_wall_dist: f64;
+/// Grid renderer for palette mode, draws palette indices and shades them through the colormap.
+pub fn render_view(player: &PlayerData, buffer: &mut IndexedBuffer, game_data: &GameData, textured: bool) {
+    profiler::scope!(Stage::RayCasting);
+    let Some(palette_data) = &game_data.palette_data else {
+        return;
+    };
 
-        let mut step_x: i32;
-        let mut step_y: i32;
+    let width = buffer.width as i32;
+    let height = buffer.height as i32;
+    let horizon = height / 2 - player.ver;
+    let eye = player.eye();
 
-        let mut x_side = false;
+    // The grid walls always cover the whole column, one distance per column is enough for the sprites.
+    let mut z_buffer = vec![f64::INFINITY; width as usize];
 
-        if ray_dir_x < 0.0 {
-            step_x = -1;
-            side_dist_x = (pos_x - (map_x as f64)) * delta_dist_x;
-        } else {
-            step_x = 1;
-            side_dist_x = ((map_x as f64) + 1.0 - pos_x) * delta_dist_x;
-        }
+    for x in 0..width {
+        let camera_x = 2.0 * (x as f64) / (width as f64) - 1.0;
+        let ray_dir = Vec2::new(player.dir.x + player.cam.x * camera_x, player.dir.y + player.cam.y * camera_x);
 
-        if ray_dir_y < 0.0 {
-            step_y = -1;
-            side_dist_y = (pos_y - (map_y as f64)) * delta_dist_y;
-        } else {
-            step_y = 1;
-            side_dist_y = ((map_y as f64) + 1.0 - pos_y) * delta_dist_y;
-        }
+        let hit = game_data.map.cast_ray(&player.pos, &ray_dir);
+        z_buffer[x as usize] = hit.dist;
+        let lineheight: i32 = ((height as f64)/hit.dist) as i32;
 
-        loop {
-            if side_dist_x < side_dist_y {
-                side_dist_x += delta_dist_x;
-                map_x += step_x;
-                x_side = true;
-            } else {
-                side_dist_y += delta_dist_y;
-                map_y += step_y;
-                x_side = false;
-            }
+        // The walls go from 0.0 to 1.0, so they get split around the eye.
+        let wall_top = horizon - ((1.0 - eye) * lineheight as f64) as i32;
+        let draw_start = wall_top.clamp(0, height);
+        let draw_end = (horizon + (eye * lineheight as f64) as i32).min(height - 1);
 
-            if WORLD_MAP[map_x as usize][map_y as usize] != 0 {
-                break;
+        // The grid has no ceiling, so the sky fills everything above the walls.
+        if let Some(sky) = &palette_data.sky {
+            let tex_x = sky_tex_x(player, x, width, sky.width);
+            for y in 0..draw_start {
+                buffer.pixels[y as usize * width as usize + x as usize] = sky.get_pixel(tex_x, sky_tex_y(player, y, height, sky.height));
             }
         }
 
-        if x_side {
-            perp_wall_dist = (side_dist_x - delta_dist_x);
-        } else {
-            perp_wall_dist = (side_dist_y - delta_dist_y);
-        }
-
-        let lineheight: i32 = ((game_data.height as f64)/perp_wall_dist) as i32;
+        // Shading and fog are just a different colormap, the side walls are half as bright.
+        let colormap = palette_data.colormap.map(if hit.x_side {0.5} else {1.0}, hit.dist);
 
-        let mut draw_start: i32 = -lineheight / 2 + (game_data.height as i32) / 2;
-        if draw_start < 0 {
-            draw_start = 0;
-        }
-        let mut draw_end: i32 = lineheight / 2 + (game_data.height as i32) / 2;
-        if draw_end >= (game_data.height as i32) {
-            draw_end = (game_data.height as i32) - 1;
+        if !textured {
+            let index = palette_data.palette.nearest(wall_color(hit.wall));
+            buffer.ver_line(x, draw_start, draw_end, colormap[index as usize]);
+            continue;
         }
 
-        // Texture Handling
-        let mut texture = match WORLD_MAP[map_x as usize][map_y as usize] {
-            1 => game_data.textures.get(0).unwrap(),
-            2 => game_data.textures.get(1).unwrap(),
-            3 => game_data.textures.get(2).unwrap(),
-            4 => game_data.textures.get(3).unwrap(),
-            _ => game_data.textures.get(4).unwrap()
+        let texture = match hit.wall {
+            1..=4 => &palette_data.textures[hit.wall as usize - 1],
+            _ => &palette_data.textures[4]
         };
 
-        // The coordinate on the wall which corresponds to the x-coordinate on the texture.
-        let mut wall_x: f64;
-        if x_side {
-            wall_x = pos_y + perp_wall_dist * ray_dir_y;
-        } else {
-            wall_x = pos_x + perp_wall_dist * ray_dir_x;
-        }
-        wall_x -= wall_x.floor();
+        profiler::accumulate!(Stage::Walls);
 
         // The x-coordinate on the texture.
-        let mut tex_x = (wall_x * texture.width as f64) as u32;
+        let tex_x = (hit.wall_x * texture.width as f64) as usize;
 
         let step = texture.height as f64 / lineheight as f64;
-        let mut tex_pos = (draw_start as f64 - game_data.height as f64/2.0 + lineheight as f64 / 2.0);
-
-        let _ = buffer.with_lock(None, |buffer: &mut [u8], pitch: usize| {
-            for y in draw_start..draw_end {
-                let tex_y = tex_pos as u32 & (texture.height - 1);
-                tex_pos += step;
-
-                let (mut r, mut g, mut b, a) = texture.get_pixel(tex_x as usize, tex_y as usize).unwrap_or((255, 255, 255, 255));
+        let mut tex_pos = (draw_start - wall_top) as f64 * step;
 
-                if x_side {
-                    r /= 2;
-                    g /= 2;
-                    b /= 2;
-                }
-
-                let offset: usize = y as usize*pitch + x as usize*4;
+        for y in draw_start..=draw_end {
+            let tex_y = tex_pos as usize % texture.height as usize;
+            tex_pos += step;
 
-                buffer[offset] = r;
-                buffer[offset+1] = g;
-                buffer[offset+2] = b;
-                buffer[offset+3] = a;
-            }
-        });
+            let index = colormap[texture.get_pixel(tex_x, tex_y) as usize];
+            buffer.pixels[y as usize * width as usize + x as usize] = index;
+        }
     }
+
+    // Sprites are in true color, only a handful of colors so they get looked up once.
+    let mut nearest: HashMap<Color, u8> = HashMap::new();
+    draw_sprites(player, game_data, (width, height), |x, y, depth, color| {
+        if depth < z_buffer[x as usize] {
+            let index = *nearest.entry(color).or_insert_with(|| palette_data.palette.nearest(color));
+            buffer.pixels[y as usize * width as usize + x as usize] = index;
+        }
+    });
 }
 
-pub fn render_view_canvas(player: &PlayerData, canvas: &mut Canvas<Window>, game_data: &GameData, textured: bool) {
+pub fn render_view_canvas(player: &PlayerData, canvas: &mut impl DrawTarget, game_data: &GameData, textured: bool) {
+    profiler::scope!(Stage::RayCasting);
+
     let mut pos_x = player.pos.x;
     let mut pos_y = player.pos.y;
     
@@ -147,119 +154,66 @@ pub fn render_view_canvas(player: &PlayerData, canvas: &mut Canvas<Window>, game
     let mut plane_x = player.cam.x;
     let mut plane_y = player.cam.y;
 
+    let mut z_buffer = vec![f64::INFINITY; game_data.width as usize];
 
     for x in 0..game_data.width as i32 {
         let mut camera_x = 2.0 * (x as f64) / (game_data.width as f64) - 1.0;
         let mut ray_dir_x = dir_x + plane_x * camera_x;
         let mut ray_dir_y = dir_y + plane_y * camera_x;
 
-        let mut map_x: i32 = pos_x as i32;
-        let mut map_y: i32 = pos_y as i32;
-
-        let mut side_dist_x: f64;
-        let mut side_dist_y: f64;
-
-        let mut delta_dist_x: f64 = if ray_dir_x == 0.0 {1e30} else {(1.0/ray_dir_x).abs()};
-        let mut delta_dist_y: f64 = if ray_dir_y == 0.0 {1e30} else {(1.0/ray_dir_y).abs()};
-
-        let mut perp_wall_dist: f64;
-
-        let mut step_x: i32;
-        let mut step_y: i32;
+        let hit = game_data.map.cast_ray(&Vec2::new(pos_x, pos_y), &Vec2::new(ray_dir_x, ray_dir_y));
+        let perp_wall_dist = hit.dist;
+        z_buffer[x as usize] = perp_wall_dist;
+        let x_side = hit.x_side;
 
-        let mut x_side = false;
-
-        if ray_dir_x < 0.0 {
-            step_x = -1;
-            side_dist_x = (pos_x - (map_x as f64)) * delta_dist_x;
-        } else {
-            step_x = 1;
-            side_dist_x = ((map_x as f64) + 1.0 - pos_x) * delta_dist_x;
-        }
-
-        if ray_dir_y < 0.0 {
-            step_y = -1;
-            side_dist_y = (pos_y - (map_y as f64)) * delta_dist_y;
-        } else {
-            step_y = 1;
-            side_dist_y = ((map_y as f64) + 1.0 - pos_y) * delta_dist_y;
-        }
-
-        loop {
-            if side_dist_x < side_dist_y {
-                side_dist_x += delta_dist_x;
-                map_x += step_x;
-                x_side = true;
-            } else {
-                side_dist_y += delta_dist_y;
-                map_y += step_y;
-                x_side = false;
-            }
-
-            if WORLD_MAP[map_x as usize][map_y as usize] != 0 {
-                break;
-            }
-        }
+        let lineheight: i32 = ((game_data.height as f64)/perp_wall_dist) as i32;
 
-        if x_side {
-            perp_wall_dist = (side_dist_x - delta_dist_x);
-        } else {
-            perp_wall_dist = (side_dist_y - delta_dist_y);
-        }
+        // Looking up/down shears the view, everything moves with the horizon.
+        let horizon = (game_data.height as i32) / 2 - player.ver;
+        let eye = player.eye();
 
-        let lineheight: i32 = ((game_data.height as f64)/perp_wall_dist) as i32;
+        // The walls go from 0.0 to 1.0, so they get split around the eye.
+        let wall_top = horizon - ((1.0 - eye) * lineheight as f64) as i32;
 
-        let mut draw_start: i32 = -lineheight / 2 + (game_data.height as i32) / 2;
+        let mut draw_start: i32 = wall_top;
         if draw_start < 0 && textured {
             draw_start = 0;
         }
-        let mut draw_end: i32 = lineheight / 2 + (game_data.height as i32) / 2;
+        let mut draw_end: i32 = horizon + (eye * lineheight as f64) as i32;
         if draw_end >= (game_data.height as i32) && textured {
             draw_end = (game_data.height as i32) - 1;
         }
 
+        // Untextured walls aren't clipped yet, the sky must stay on the screen either way.
+        if let Some(sky) = &game_data.sky {
+            for y in 0..draw_start.clamp(0, game_data.height as i32) {
+                canvas.draw_point(x, y, sky_color(sky, player, x, y, game_data.width as i32, game_data.height as i32));
+            }
+        }
+
         if !textured {
-            let mut color = match WORLD_MAP[map_x as usize][map_y as usize] {
-                1 => Color::RED,
-                2 => Color::GREEN,
-                3 => Color::BLUE,
-                4 => Color::GRAY,
-                _ => Color::CYAN
-            };
+            let mut color = wall_color(hit.wall);
 
             if x_side {
                 let (r, g, b, a) = color.rgba();
                 color = Color::RGBA(r/2, g/2, b/2, a/2);
             }
 
-            ver_line(x, draw_start - player.ver, draw_end - player.ver, color, canvas);
+            canvas.ver_line(x, draw_start, draw_end, color);
 
             continue;
         }
 
         // Texture Handling
-        let texture = match WORLD_MAP[map_x as usize][map_y as usize] {
-            1 => game_data.textures.get(0).unwrap(),
-            2 => game_data.textures.get(1).unwrap(),
-            3 => game_data.textures.get(2).unwrap(),
-            4 => game_data.textures.get(3).unwrap(),
-            _ => game_data.textures.get(4).unwrap()
-        };
-
-        // The coordinate on the wall which corresponds to the x-coordinate on the texture.
-        let mut wall_x: f64;
-        if x_side {
-            wall_x = pos_y + perp_wall_dist * ray_dir_y;
-        } else {
-            wall_x = pos_x + perp_wall_dist * ray_dir_x;
-        }
-        wall_x -= wall_x.floor();
+        profiler::accumulate!(Stage::Walls);
+        let texture = wall_texture(game_data, hit.wall);
 
         // The x-coordinate on the texture.
-        let tex_x = (wall_x * texture.width as f64) as u32;
+        let tex_x = (hit.wall_x * texture.width as f64) as u32;
 
+        // Start at the part of the texture that belongs to the first visible row, the wall may reach past the screen.
         let step = texture.height as f64 / lineheight as f64;
-        let mut tex_pos = (draw_start as f64 - game_data.height as f64/2.0 + lineheight as f64 / 2.0);
+        let mut tex_pos = (draw_start - wall_top) as f64 * step;
 
         for y in draw_start..draw_end {
             let tex_y = tex_pos as u32 & (texture.height - 1);
@@ -272,8 +226,13 @@ pub fn render_view_canvas(player: &PlayerData, canvas: &mut Canvas<Window>, game
                 false => Color::RGBA(r, g, b, a)
             };
 
-            canvas.set_draw_color(color);
-            let _ = canvas.draw_point(Point::new(x, y));
+            canvas.draw_point(x, y, color);
         }
     }
+
+    draw_sprites(player, game_data, (game_data.width as i32, game_data.height as i32), |x, y, depth, color| {
+        if depth < z_buffer[x as usize] {
+            canvas.draw_point(x, y, color);
+        }
+    });
 }
@@ -0,0 +1,506 @@
+use std::fs;
+use std::path::Path;
+
+use crate::config::{SAVE_DIR, SAVE_VERSION};
+use crate::data::{GameData, MapSource, PlayerData, RenderMode, Vec2};
+use crate::entity::{Entity, ThingType, THING_TYPES};
+use crate::level::Level;
+use crate::random::Rng;
+use crate::sprite::State;
+use crate::trigger::{self, Mover};
+use crate::weapon::{Projectile, SPAWNED_TYPES, WEAPONS};
+
+/// The file of a save slot, slot names are kept to letters, digits and `_` so they stay inside SAVE_DIR.
+pub fn slot_path(slot: &str) -> Result<String, String> {
+    if slot.is_empty() || !slot.chars().all(|character| character.is_ascii_alphanumeric() || character == '_') {
+        return Err(format!("bad slot name {}, use letters, digits and _", slot));
+    }
+
+    Ok(format!("{}/{}.sav", SAVE_DIR, slot))
+}
+
+/// Writes everything needed to carry on exactly where the game is, one `key = values` line per thing:
+///
+/// version = 1
+/// map = wad "doom1.wad" E1M1
+/// player = <pos> <dir> <cam> <pitch> <z> ...
+/// trigger = 3
+/// mover = 5 ceiling 1.9375
+/// entity = POSS 7.5 7.5 0 3.14 walk 1 4 12 20 3 1 0
+/// path = 17 7,8 7,9
+pub fn save(slot: &str, player: &PlayerData, game_data: &GameData) -> Result<(), String> {
+    let path = slot_path(slot)?;
+    let text = write(player, game_data)?;
+
+    fs::create_dir_all(SAVE_DIR).map_err(|e| format!("{}: {}", SAVE_DIR, e))?;
+    fs::write(&path, text).map_err(|e| format!("{}: {}", path, e))
+}
+
+fn write(player: &PlayerData, game_data: &GameData) -> Result<String, String> {
+    let mut text = format!("# Save game, the first line has to stay the version.\nversion = {}\n", SAVE_VERSION);
+
+    // Paths are quoted, they can have spaces.
+    let quote = |file: &str| match file.contains('"') {
+        true => Err(format!("can't save, the path {} has a \"", file)),
+        false => Ok(format!("\"{}\"", file))
+    };
+    text += &match &game_data.map_source {
+        MapSource::Grid => "map = grid\n".to_string(),
+        MapSource::LevelFile(file) => format!("map = level {}\n", quote(file)?),
+        MapSource::Wad { file, map } => format!("map = wad {} {}\n", quote(file)?, map)
+    };
+
+    text += &format!(
+        "player = {} {} {} {} {} {} {} {} {} {} {} {} {} {} {} {} {} {} {}\n",
+        player.pos.x, player.pos.y, player.dir.x, player.dir.y, player.cam.x, player.cam.y, player.ver,
+        player.z, player.z_speed, player.on_ground, player.crouching, player.eye_height, player.bob,
+        player.health, player.armor, player.noclip, player.god, player.damage_flash, player.pickup_flash
+    );
+
+    let weapons = &player.weapons;
+    text += &format!(
+        "weapons = {} {} {} {} {}\n",
+        weapons.current, weapons.ammo[0], weapons.ammo[1], weapons.ammo[2], weapons.cooldown
+    );
+
+    let render_mode = match game_data.render_mode {
+        RenderMode::Grid => "grid",
+        RenderMode::Sectors => "sectors",
+        RenderMode::Bsp => "bsp"
+    };
+    text += &format!("view = {} {} {} {}\n", render_mode, game_data.textured, game_data.paletted, game_data.map_view);
+    text += &format!("rng = {}\ntick_time = {}\n", game_data.rng.state(), game_data.tick_time);
+
+    // Sector heights are what doors and lifts change, the rest of the level comes from the map.
+    if game_data.custom_level {
+        for (index, sector) in game_data.level.sectors.iter().enumerate() {
+            text += &format!("sector = {} {} {}\n", index, sector.floor, sector.ceiling);
+        }
+    }
+
+    for (index, _) in game_data.level.triggers.iter().enumerate().filter(|(_, trigger)| trigger.fired) {
+        text += &format!("trigger = {}\n", index);
+    }
+    for mover in &game_data.movers {
+        text += &format!("mover = {} {} {}\n", mover.sector, if mover.ceiling {"ceiling"} else {"floor"}, mover.target);
+    }
+
+    for entity in game_data.entities.iter().filter(|entity| !entity.removed) {
+        text += &format!(
+            "entity = {} {} {} {} {} {} {} {} {} {} {} {} {}",
+            entity.sprite, entity.pos.x, entity.pos.y, entity.z, entity.angle, entity.state.name(), entity.frame,
+            entity.tics, entity.state_ticks, entity.health, entity.tags, entity.alerted, entity.cooldown
+        );
+
+        if let Some(projectile) = entity.projectile {
+            text += &format!(" {} {} {}", projectile.velocity.x, projectile.velocity.y, projectile.damage);
+        }
+        text += "\n";
+
+        // The path of a monster goes with the entity before it, without it the monster would look for a new one.
+        if !entity.path.is_empty() || entity.repath > 0 {
+            text += &format!("path = {}", entity.repath);
+            for (x, y) in &entity.path {
+                text += &format!(" {},{}", x, y);
+            }
+            text += "\n";
+        }
+    }
+
+    Ok(text)
+}
+
+/// Everything out of a save file, nothing of the game gets touched until all of it could be read.
+struct SaveGame {
+    map_source: MapSource,
+    player: PlayerData,
+    render_mode: RenderMode,
+    textured: bool,
+    paletted: bool,
+    map_view: bool,
+    rng: Rng,
+    tick_time: f64,
+    sectors: Vec<(usize, f64, f64)>,
+    /// Triggers that went off and the floors and ceilings still moving.
+    triggers: Vec<usize>,
+    movers: Vec<Mover>,
+    entities: Vec<Entity>
+}
+
+/// The values of a line, every read says which line went wrong.
+struct Values<'a> {
+    rest: &'a str,
+    error: &'a dyn Fn(&str) -> String
+}
+
+impl<'a> Values<'a> {
+    fn word(&mut self) -> Result<&'a str, String> {
+        let rest = self.rest.trim_start();
+        if rest.is_empty() {
+            return Err((self.error)("line ends too early"));
+        }
+
+        let (word, rest) = rest.split_at(rest.find(char::is_whitespace).unwrap_or(rest.len()));
+        self.rest = rest;
+        Ok(word)
+    }
+
+    /// A path in quotes, or a single word like older saves wrote them.
+    fn path(&mut self) -> Result<&'a str, String> {
+        let Some(quoted) = self.rest.trim_start().strip_prefix('"') else {
+            return self.word();
+        };
+
+        let (path, rest) = quoted.split_once('"').ok_or_else(|| (self.error)("the path has no closing \""))?;
+        self.rest = rest;
+        Ok(path)
+    }
+
+    fn parse<T: std::str::FromStr>(&mut self) -> Result<T, String> {
+        let word = self.word()?;
+        word.parse().map_err(|_| (self.error)(&format!("can't read {}", word)))
+    }
+
+    fn is_empty(&self) -> bool {
+        self.rest.trim().is_empty()
+    }
+}
+
+/// Everything from a `#` on is a comment, unless the `#` is in a quoted path.
+fn strip_comment(line: &str) -> &str {
+    let mut quoted = false;
+
+    for (index, character) in line.char_indices() {
+        match character {
+            '"' => quoted = !quoted,
+            '#' if !quoted => return &line[..index],
+            _ => {}
+        }
+    }
+
+    line
+}
+
+/// Finds the thing type an entity was made from by its sprite, the sprites are unique.
+fn thing_type(sprite: &str) -> Option<&'static ThingType> {
+    THING_TYPES.iter().chain(SPAWNED_TYPES).find(|thing_type| thing_type.sprite == sprite)
+}
+
+fn read(path: &str) -> Result<SaveGame, String> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(_) if !Path::new(path).exists() => return Err(format!("{} doesn't exist, nothing saved in that slot", path)),
+        Err(e) => return Err(format!("{}: {}", path, e))
+    };
+
+    let mut save = SaveGame {
+        map_source: MapSource::Grid,
+        player: PlayerData::new(Vec2::new(0.0, 0.0), Vec2::new(1.0, 0.0), Vec2::new(0.0, 0.66)),
+        render_mode: RenderMode::Grid,
+        textured: false,
+        paletted: false,
+        map_view: false,
+        rng: Rng::new(1),
+        tick_time: 0.0,
+        sectors: Vec::new(),
+        triggers: Vec::new(),
+        movers: Vec::new(),
+        entities: Vec::new()
+    };
+    let mut version = None;
+    let (mut has_map, mut has_player) = (false, false);
+
+    for (number, line) in text.lines().enumerate() {
+        let line = strip_comment(line).trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let error = |message: &str| format!("{}:{}: corrupted save, {}", path, number + 1, message);
+
+        let (key, value) = line.split_once('=').ok_or_else(|| error("expected key = value"))?;
+        let mut values = Values { rest: value, error: &error };
+        let key = key.trim();
+
+        if version.is_none() {
+            if key != "version" {
+                return Err(error("not a save file, it has to start with the version"));
+            }
+
+            let number: u32 = values.parse()?;
+            if number != SAVE_VERSION {
+                return Err(format!("{} is a version {} save, this game only reads version {}", path, number, SAVE_VERSION));
+            }
+            version = Some(number);
+            continue;
+        }
+
+        match key {
+            "map" => {
+                save.map_source = match values.word()? {
+                    "grid" => MapSource::Grid,
+                    "level" => MapSource::LevelFile(values.path()?.to_string()),
+                    "wad" => MapSource::Wad { file: values.path()?.to_string(), map: values.word()?.to_string() },
+                    other => return Err(error(&format!("unknown map kind {}", other)))
+                };
+                has_map = true;
+            },
+            "player" => {
+                let player = &mut save.player;
+                player.pos = Vec2::new(values.parse()?, values.parse()?);
+                player.dir = Vec2::new(values.parse()?, values.parse()?);
+                player.cam = Vec2::new(values.parse()?, values.parse()?);
+                player.ver = values.parse()?;
+                player.z = values.parse()?;
+                player.z_speed = values.parse()?;
+                player.on_ground = values.parse()?;
+                player.crouching = values.parse()?;
+                player.eye_height = values.parse()?;
+                player.bob = values.parse()?;
+                player.health = values.parse()?;
+                player.armor = values.parse()?;
+                player.noclip = values.parse()?;
+                player.god = values.parse()?;
+                player.damage_flash = values.parse()?;
+                player.pickup_flash = values.parse()?;
+                has_player = true;
+            },
+            "weapons" => {
+                let weapons = &mut save.player.weapons;
+                weapons.current = values.parse()?;
+                if weapons.current >= WEAPONS.len() {
+                    return Err(error(&format!("there is no weapon {}", weapons.current)));
+                }
+                weapons.ammo = [values.parse()?, values.parse()?, values.parse()?];
+                weapons.cooldown = values.parse()?;
+            },
+            "view" => {
+                save.render_mode = match values.word()? {
+                    "grid" => RenderMode::Grid,
+                    "sectors" => RenderMode::Sectors,
+                    "bsp" => RenderMode::Bsp,
+                    other => return Err(error(&format!("unknown render mode {}", other)))
+                };
+                save.textured = values.parse()?;
+                save.paletted = values.parse()?;
+                save.map_view = values.parse()?;
+            },
+            "rng" => save.rng = Rng::new(values.parse()?),
+            "tick_time" => save.tick_time = values.parse()?,
+            "sector" => save.sectors.push((values.parse()?, values.parse()?, values.parse()?)),
+            "trigger" => save.triggers.push(values.parse()?),
+            "mover" => {
+                let sector = values.parse()?;
+                let ceiling = match values.word()? {
+                    "floor" => false,
+                    "ceiling" => true,
+                    other => return Err(error(&format!("unknown plane {}", other)))
+                };
+                save.movers.push(Mover { sector, ceiling, target: values.parse()? });
+            },
+            "entity" => {
+                let sprite = values.word()?;
+                let thing_type = thing_type(sprite).ok_or_else(|| error(&format!("unknown thing {}", sprite)))?;
+
+                let pos = Vec2::new(values.parse()?, values.parse()?);
+                let z = values.parse()?;
+                let mut entity = Entity::new(pos, values.parse()?, thing_type);
+                entity.z = z;
+
+                let state = values.word()?;
+                entity.state = State::from_name(state).ok_or_else(|| error(&format!("unknown state {}", state)))?;
+                entity.frame = values.parse()?;
+                entity.tics = values.parse()?;
+                entity.state_ticks = values.parse()?;
+                entity.health = values.parse()?;
+                entity.tags = values.parse()?;
+                entity.alerted = values.parse()?;
+                entity.cooldown = values.parse()?;
+
+                if !values.is_empty() {
+                    entity.projectile = Some(Projectile { velocity: Vec2::new(values.parse()?, values.parse()?), damage: values.parse()? });
+                }
+
+                save.entities.push(entity);
+            },
+            "path" => {
+                let entity = save.entities.last_mut().ok_or_else(|| error("a path without an entity"))?;
+                entity.repath = values.parse()?;
+
+                while !values.is_empty() {
+                    let cell = values.word()?;
+                    let (x, y) = cell.split_once(',').ok_or_else(|| error(&format!("can't read the cell {}", cell)))?;
+                    let parse = |number: &str| number.parse().map_err(|_| error(&format!("can't read the cell {}", cell)));
+                    entity.path.push((parse(x)?, parse(y)?));
+                }
+            },
+            _ => return Err(error(&format!("unknown key {}", key)))
+        }
+
+        if !values.is_empty() {
+            return Err(error("too many values"));
+        }
+    }
+
+    if version.is_none() {
+        return Err(format!("{} is empty", path));
+    }
+    if !has_map || !has_player {
+        return Err(format!("{}: corrupted save, the map or the player is missing", path));
+    }
+
+    Ok(save)
+}
+
+/// The sectors and triggers of the save have to be in the map, or the save doesn't belong to it.
+fn check(path: &str, save: &SaveGame, level: &Level) -> Result<(), String> {
+    let mut sectors = save.sectors.iter().map(|(index, _, _)| *index).chain(save.movers.iter().map(|mover| mover.sector));
+    if let Some(index) = sectors.find(|&index| index >= level.sectors.len()) {
+        return Err(format!("{}: the map has no sector {}, the save doesn't belong to it", path, index));
+    }
+    if let Some(&index) = save.triggers.iter().find(|&&index| index >= level.triggers.len()) {
+        return Err(format!("{}: the map has no trigger {}, the save doesn't belong to it", path, index));
+    }
+
+    Ok(())
+}
+
+/// Loads the map of the save, then puts everything back the way it was saved.
+/// The save gets checked against its map first, so a save that doesn't fit leaves the game alone.
+pub fn load(slot: &str, player: &mut PlayerData, game_data: &mut GameData) -> Result<(), String> {
+    let path = slot_path(slot)?;
+    let save = read(&path)?;
+    check(&path, &save, &save.map_source.read_level()?)?;
+
+    // Even the current map gets loaded again, so the switches and doors of the game so far are undone.
+    game_data.load_map(save.map_source)?;
+
+    for (index, floor, ceiling) in save.sectors {
+        game_data.level.sectors[index].floor = floor;
+        game_data.level.sectors[index].ceiling = ceiling;
+    }
+    for index in save.triggers {
+        trigger::restore(&mut game_data.level, index);
+    }
+    game_data.movers = save.movers;
+
+    // The settings stay as they are, only what is part of the game comes back.
+    *player = save.player;
+    game_data.render_mode = save.render_mode;
+    game_data.textured = save.textured;
+    game_data.map_view = save.map_view;
+    if save.paletted != game_data.paletted {
+        game_data.toggle_palette_mode();
+    }
+    game_data.rng = save.rng;
+    game_data.tick_time = save.tick_time;
+    game_data.entities = save.entities;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_text(name: &str, text: &str) -> Result<SaveGame, String> {
+        let path = std::env::temp_dir().join(name);
+        fs::write(&path, text).unwrap();
+        let save = read(path.to_str().unwrap());
+        fs::remove_file(&path).unwrap();
+        save
+    }
+
+    /// What a save has to bring back of an entity, Debug prints floats so they read back the same.
+    fn entity_state(entity: &Entity) -> String {
+        format!(
+            "{} {:?} {:?} {:?} {:?} {} {} {} {} {} {} {} {:?} {} {:?}",
+            entity.sprite, entity.pos.x, entity.pos.y, entity.z, entity.angle, entity.state.name(), entity.frame, entity.tics,
+            entity.state_ticks, entity.health, entity.alerted, entity.cooldown, entity.path, entity.repath,
+            entity.projectile.map(|projectile| (projectile.velocity.x, projectile.velocity.y, projectile.damage))
+        )
+    }
+
+    #[test]
+    fn saves_read_back_the_same() {
+        let mut game_data = GameData::new(false, 320, 200, crate::map::Map::from_grid(&[[0; 4]; 4], None));
+        game_data.render_mode = RenderMode::Sectors;
+        game_data.textured = true;
+        game_data.rng = Rng::new(987654321);
+        game_data.tick_time = 0.0123;
+        game_data.movers = vec![Mover { sector: 0, ceiling: true, target: 1.0 / 3.0 }];
+
+        let mut monster = Entity::new(Vec2::new(2.25, 1.0 / 3.0), 0.7, crate::entity::thing_type(3001).unwrap());
+        monster.state = State::Pain;
+        monster.frame = 1;
+        monster.health = 17;
+        monster.alerted = true;
+        monster.cooldown = 9;
+        monster.path = vec![(2, 1), (2, 2), (3, 2)];
+        monster.repath = 12;
+
+        let mut rocket = Entity::new(Vec2::new(1.1, 2.9), -2.5, &crate::weapon::ROCKET);
+        rocket.z = 0.4;
+        rocket.projectile = Some(Projectile { velocity: Vec2::new(-8.8, -6.6), damage: 160 });
+
+        let mut removed = Entity::new(Vec2::new(1.5, 1.5), 0.0, &crate::weapon::PUFF);
+        removed.removed = true;
+        game_data.entities = vec![monster, rocket, removed];
+
+        let mut player = game_data.new_player();
+        player.pos = Vec2::new(1.75, 2.125);
+        player.z = 0.1;
+        player.crouching = true;
+        player.health = 42;
+        player.armor = 7;
+        player.weapons.current = 2;
+        player.weapons.ammo = [3, 0, 9];
+        player.weapons.cooldown = 5;
+
+        let save = read_text("round_trip.sav", &write(&player, &game_data).unwrap()).unwrap();
+
+        assert!(save.map_source == MapSource::Grid);
+        assert!(save.render_mode == RenderMode::Sectors && save.textured && !save.paletted && !save.map_view);
+        assert_eq!((save.rng.state(), save.tick_time), (game_data.rng.state(), 0.0123));
+        assert_eq!(save.movers.iter().map(|mover| (mover.sector, mover.ceiling, mover.target)).collect::<Vec<_>>(), [(0, true, 1.0 / 3.0)]);
+
+        let saved = &save.player;
+        assert_eq!((saved.pos.x, saved.pos.y, saved.dir.x, saved.cam.y, saved.z), (1.75, 2.125, player.dir.x, player.cam.y, 0.1));
+        assert_eq!((saved.crouching, saved.health, saved.armor), (true, 42, 7));
+        assert_eq!((saved.weapons.current, saved.weapons.ammo, saved.weapons.cooldown), (2, [3, 0, 9], 5));
+
+        // The removed puff is gone, the others come back with their path and flight.
+        let entities: Vec<_> = game_data.entities[..2].iter().map(entity_state).collect();
+        assert_eq!(save.entities.iter().map(entity_state).collect::<Vec<_>>(), entities);
+    }
+
+    #[test]
+    fn paths_can_have_spaces() {
+        let player = "player = 1.5 1.5 -1 0 0 0.66 0 0 0 true false 0.5 0 100 0 false false 0 0\n";
+
+        let save = read_text("quoted.sav", &format!("version = {}\nmap = wad \"my wads/doom #1.wad\" E1M1 # comment\n{}", SAVE_VERSION, player)).unwrap();
+        assert!(save.map_source == MapSource::Wad { file: "my wads/doom #1.wad".to_string(), map: "E1M1".to_string() });
+
+        // Saves from before the quotes still load.
+        let save = read_text("unquoted.sav", &format!("version = {}\nmap = level levels/demo.lvl\n{}", SAVE_VERSION, player)).unwrap();
+        assert!(save.map_source == MapSource::LevelFile("levels/demo.lvl".to_string()));
+
+        assert!(read_text("unclosed.sav", &format!("version = {}\nmap = level \"levels/demo.lvl\n{}", SAVE_VERSION, player)).is_err());
+    }
+
+    #[test]
+    fn saves_have_to_fit_their_map() {
+        let level = MapSource::Grid.read_level().unwrap();
+        let mut save = read_text("fits.sav", &format!(
+            "version = {}\nmap = grid\nplayer = 1.5 1.5 -1 0 0 0.66 0 0 0 true false 0.5 0 100 0 false false 0 0\n", SAVE_VERSION
+        )).unwrap();
+        assert!(check("fits.sav", &save, &level).is_ok());
+
+        save.sectors.push((level.sectors.len(), 0.0, 1.0));
+        assert!(check("fits.sav", &save, &level).is_err());
+
+        save.sectors.clear();
+        save.triggers.push(level.triggers.len());
+        assert!(check("fits.sav", &save, &level).is_err());
+    }
+}
@@ -0,0 +1,346 @@
+use std::collections::VecDeque;
+
+use sdl2::pixels::Color;
+
+use crate::config::{CEILING_COLOR, FLOOR_COLOR, MAX_SECTOR_VISITS, NEAR_PLANE};
+use crate::data::{GameData, PlayerData, Vec2};
+use crate::framebuffer::FrameBuffer;
+use crate::map::{point_segment_dist, Map};
+use crate::profiler::{self, Stage};
+use crate::rendering::{wall_color, wall_texture};
+use crate::sky::draw_sky_column;
+use crate::sprite::draw_sprites;
+
+/// One edge of a sector, either a solid wall or a portal into another sector.
+pub struct SectorWall {
+    pub a: Vec2<f64>,
+    pub b: Vec2<f64>,
+    pub portal: Option<usize>,
+    pub wall: i32
+}
+
+/// A convex area with its walls in counter-clockwise order, so the inside is to the left of every wall.
+pub struct Sector {
+    pub floor: f64,
+    pub ceiling: f64,
+    /// Open to the sky instead of having a ceiling.
+    pub sky: bool,
+    pub walls: Vec<SectorWall>
+}
+
+impl Sector {
+    pub fn contains(&self, pos: &Vec2<f64>) -> bool {
+        self.walls.iter().all(|wall| side(&wall.a, &wall.b, pos) >= 0.0)
+    }
+
+    /// How far the position is from the closest wall, 0.0 on one.
+    fn distance(&self, pos: &Vec2<f64>) -> f64 {
+        self.walls.iter().map(|wall| point_segment_dist(pos, &wall.a, &wall.b)).fold(f64::INFINITY, f64::min)
+    }
+}
+
+pub struct SectorMap {
+    pub sectors: Vec<Sector>
+}
+
+impl SectorMap {
+    /// Turns every open cell of the grid into a square sector, with portals between neighbouring open cells.
+    /// Segment tiles can't be expressed by a single convex sector and get converted into solid blocks,
+    /// which is also how the Sectors mode collides with them (see can_move()).
+    pub fn from_grid(map: &Map) -> Self {
+        let is_open = |x: i32, y: i32| map.tile(x, y) == 0;
+        let index = |x: i32, y: i32| x as usize * map.height + y as usize;
+
+        // Sector indices of the open cells, the same layout as the tiles.
+        let mut cell_sector = vec![None; map.width * map.height];
+        let mut count = 0;
+        for x in 0..map.width as i32 {
+            for y in 0..map.height as i32 {
+                if is_open(x, y) {
+                    cell_sector[index(x, y)] = Some(count);
+                    count += 1;
+                }
+            }
+        }
+
+        let mut sectors = Vec::with_capacity(count);
+        for x in 0..map.width as i32 {
+            for y in 0..map.height as i32 {
+                if !is_open(x, y) {
+                    continue;
+                }
+
+                let (fx, fy) = (x as f64, y as f64);
+                let edges = [
+                    ((fx, fy), (fx + 1.0, fy), (x, y - 1)),
+                    ((fx + 1.0, fy), (fx + 1.0, fy + 1.0), (x + 1, y)),
+                    ((fx + 1.0, fy + 1.0), (fx, fy + 1.0), (x, y + 1)),
+                    ((fx, fy + 1.0), (fx, fy), (x - 1, y))
+                ];
+
+                let walls = edges.iter().map(|&(a, b, (nx, ny))| {
+                    let (portal, wall) = if is_open(nx, ny) {
+                        (cell_sector[index(nx, ny)], 0)
+                    } else {
+                        (None, map.segments(nx, ny).first().map_or(map.tile(nx, ny), |segment| segment.wall))
+                    };
+
+                    SectorWall { a: Vec2::new(a.0, a.1), b: Vec2::new(b.0, b.1), portal, wall }
+                }).collect();
+
+                sectors.push(Sector { floor: 0.0, ceiling: 1.0, sky: map.sky.is_some(), walls });
+            }
+        }
+
+        SectorMap { sectors }
+    }
+
+    /// The sector the given position is in.
+    pub fn locate(&self, pos: &Vec2<f64>) -> Option<usize> {
+        self.sectors.iter().position(|sector| sector.contains(pos))
+    }
+
+    /// Like locate(), but outside of every sector (noclip, a segment tile entered in another mode) it is
+    /// the closest one, so the renderer still has somewhere to start. None only without any sectors.
+    pub fn locate_nearest(&self, pos: &Vec2<f64>) -> Option<usize> {
+        self.locate(pos).or_else(|| {
+            self.sectors.iter()
+                .enumerate()
+                .min_by(|(_, a), (_, b)| a.distance(pos).total_cmp(&b.distance(pos)))
+                .map(|(index, _)| index)
+        })
+    }
+
+    /// Only positions inside of a sector can be walked to, the blocks the segment tiles became included.
+    pub fn can_move(&self, to: &Vec2<f64>) -> bool {
+        self.locate(to).is_some()
+    }
+}
+
+/// >0 if the point is to the left of a-b.
+fn side(a: &Vec2<f64>, b: &Vec2<f64>, pos: &Vec2<f64>) -> f64 {
+    (b.x - a.x) * (pos.y - a.y) - (b.y - a.y) * (pos.x - a.x)
+}
+
+/// A wall end point in camera space, z is the depth and u the texture coordinate along the wall.
+struct ViewPoint {
+    x: f64,
+    z: f64,
+    u: f64
+}
+
+/// Build-style portal renderer, walks from the players sector through the portals while keeping
+/// track of which part of every column is still free to draw into.
+pub fn render_sectors(player: &PlayerData, buffer: &mut FrameBuffer, game_data: &GameData, textured: bool) {
+    profiler::scope!(Stage::RayCasting);
+    let sector_map = &game_data.sectors;
+    let Some(start) = sector_map.locate_nearest(&player.pos) else {
+        return;
+    };
+
+    let width = buffer.width as i32;
+    let height = buffer.height as i32;
+    let horizon = (height / 2 - player.ver) as f64;
+    let eye = player.eye();
+
+    let mut y_top = vec![0; width as usize];
+    let mut y_bottom = vec![height - 1; width as usize];
+    let mut visits = vec![0; sector_map.sectors.len()];
+
+    let mut queue = VecDeque::new();
+    queue.push_back((start, 0, width - 1));
+
+    // The inverse of the camera matrix, same as for sprites in a raycaster.
+    let inv_det = 1.0 / (player.cam.x * player.dir.y - player.dir.x * player.cam.y);
+    let to_view = |p: &Vec2<f64>, u: f64| {
+        let rel_x = p.x - player.pos.x;
+        let rel_y = p.y - player.pos.y;
+
+        ViewPoint {
+            x: inv_det * (player.dir.y * rel_x - player.dir.x * rel_y),
+            z: inv_det * (-player.cam.y * rel_x + player.cam.x * rel_y),
+            u
+        }
+    };
+
+    while let Some((index, x0, x1)) = queue.pop_front() {
+        if visits[index] >= MAX_SECTOR_VISITS {
+            continue;
+        }
+        visits[index] += 1;
+
+        let sector = &sector_map.sectors[index];
+
+        for wall in &sector.walls {
+            // Only walls facing the player, this also skips the portal we came through.
+            if side(&wall.a, &wall.b, &player.pos) <= 0.0 {
+                continue;
+            }
+
+            let length = ((wall.b.x - wall.a.x).powi(2) + (wall.b.y - wall.a.y).powi(2)).sqrt();
+            let mut a = to_view(&wall.a, 0.0);
+            let mut b = to_view(&wall.b, length);
+
+            if a.z < NEAR_PLANE && b.z < NEAR_PLANE {
+                continue;
+            }
+
+            // Clip against the near plane.
+            if a.z < NEAR_PLANE || b.z < NEAR_PLANE {
+                let t = (NEAR_PLANE - a.z) / (b.z - a.z);
+                let clipped = ViewPoint {
+                    x: a.x + (b.x - a.x) * t,
+                    z: NEAR_PLANE,
+                    u: a.u + (b.u - a.u) * t
+                };
+
+                if a.z < NEAR_PLANE {
+                    a = clipped;
+                } else {
+                    b = clipped;
+                }
+            }
+
+            let mut screen_a = (width as f64 / 2.0) * (1.0 + a.x / a.z);
+            let mut screen_b = (width as f64 / 2.0) * (1.0 + b.x / b.z);
+            if screen_a > screen_b {
+                std::mem::swap(&mut a, &mut b);
+                std::mem::swap(&mut screen_a, &mut screen_b);
+            }
+
+            let x_begin = (screen_a.ceil() as i32).max(x0);
+            let x_end = (screen_b.ceil() as i32 - 1).min(x1);
+            if x_begin > x_end {
+                continue;
+            }
+
+            let neighbour = wall.portal.map(|portal| &sector_map.sectors[portal]);
+            let x_side = (wall.b.x - wall.a.x).abs() < (wall.b.y - wall.a.y).abs();
+
+            for x in x_begin..=x_end {
+                let column = x as usize;
+                if y_top[column] > y_bottom[column] {
+                    continue;
+                }
+
+                // Perspective correct interpolation of the depth and texture coordinate.
+                let t = ((x as f64 - screen_a) / (screen_b - screen_a)).clamp(0.0, 1.0);
+                let inv_z = (1.0 / a.z) + ((1.0 / b.z) - (1.0 / a.z)) * t;
+                let z = 1.0 / inv_z;
+                let u = ((a.u / a.z) + ((b.u / b.z) - (a.u / a.z)) * t) * z;
+
+                let scale = height as f64 / z;
+                let project = |world_y: f64| (horizon - (world_y - eye) * scale) as i32;
+                let clip = |y: i32| y.max(y_top[column]).min(y_bottom[column]);
+
+                let ceiling_y = clip(project(sector.ceiling));
+                let floor_y = clip(project(sector.floor));
+
+                match &game_data.sky {
+                    Some(sky) if sector.sky => draw_sky_column(buffer, sky, player, x, y_top[column], ceiling_y - 1),
+                    _ => buffer.ver_line(x, y_top[column], ceiling_y - 1, CEILING_COLOR)
+                }
+                buffer.ver_line(x, floor_y + 1, y_bottom[column], FLOOR_COLOR);
+
+                let column_wall = ColumnWall { x, wall: wall.wall, x_side, u, horizon, eye, scale, z };
+
+                match neighbour {
+                    Some(next) => {
+                        let next_ceiling_y = clip(project(next.ceiling));
+                        let next_floor_y = clip(project(next.floor));
+
+                        // Upper and lower walls, where the neighbouring ceiling or floor steps.
+                        if next_ceiling_y > ceiling_y {
+                            draw_wall_column(buffer, game_data, &column_wall, ceiling_y, next_ceiling_y - 1, textured);
+                        }
+                        if next_floor_y < floor_y {
+                            draw_wall_column(buffer, game_data, &column_wall, next_floor_y + 1, floor_y, textured);
+                        }
+
+                        y_top[column] = ceiling_y.max(next_ceiling_y);
+                        y_bottom[column] = floor_y.min(next_floor_y);
+                    },
+                    None => {
+                        draw_wall_column(buffer, game_data, &column_wall, ceiling_y, floor_y, textured);
+
+                        // Nothing behind a solid wall can be seen.
+                        y_top[column] = height;
+                        y_bottom[column] = -1;
+                    }
+                }
+            }
+
+            if let Some(portal) = wall.portal {
+                queue.push_back((portal, x_begin, x_end));
+            }
+        }
+    }
+
+    draw_sprites(player, game_data, (width, height), |x, y, depth, color| buffer.set_pixel_in_front(x, y, depth, color));
+}
+
+/// The part of a wall that falls into a single screen column.
+struct ColumnWall {
+    x: i32,
+    wall: i32,
+    x_side: bool,
+    u: f64,
+    horizon: f64,
+    eye: f64,
+    scale: f64,
+    z: f64
+}
+
+fn draw_wall_column(buffer: &mut FrameBuffer, game_data: &GameData, column: &ColumnWall, draw_start: i32, draw_end: i32, textured: bool) {
+    profiler::accumulate!(Stage::Walls);
+    buffer.set_depth(column.x, draw_start, draw_end, column.z);
+
+    if !textured {
+        let mut color = wall_color(column.wall);
+
+        if column.x_side {
+            let (r, g, b, a) = color.rgba();
+            color = Color::RGBA(r/2, g/2, b/2, a);
+        }
+
+        buffer.ver_line(column.x, draw_start, draw_end, color);
+        return;
+    }
+
+    let texture = wall_texture(game_data, column.wall);
+    let tex_x = (column.u.fract() * texture.width as f64) as usize;
+
+    for y in draw_start..=draw_end {
+        // The height in the world that this pixel shows, textures repeat every unit.
+        let world_y = column.eye + (column.horizon - y as f64) / column.scale;
+        let tex_y = ((1.0 - world_y).rem_euclid(1.0) * texture.height as f64) as usize;
+
+        let (r, g, b, a) = texture.get_pixel(tex_x, tex_y).unwrap_or((255, 255, 255, 255));
+
+        let color = match column.x_side {
+            true => Color::RGBA(r/2, g/2, b/2, a),
+            false => Color::RGBA(r, g, b, a)
+        };
+
+        buffer.set_pixel(column.x, y, color);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn segment_tiles_are_solid() {
+        // An open cell next to a diagonal at (1, 2), indexed [x][y].
+        let map = Map::from_grid(&[[1, 1, 1, 1], [1, 0, 6, 1], [1, 1, 1, 1]], None);
+        let sector_map = SectorMap::from_grid(&map);
+
+        assert!(sector_map.can_move(&Vec2::new(1.5, 1.5)));
+        assert!(!sector_map.can_move(&Vec2::new(1.5, 2.5)));
+
+        // Standing in it anyway (noclip) still renders from the open cell next to it.
+        assert_eq!(sector_map.locate(&Vec2::new(1.5, 2.5)), None);
+        assert_eq!(sector_map.locate_nearest(&Vec2::new(1.5, 2.5)), Some(0));
+    }
+}
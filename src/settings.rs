@@ -0,0 +1,148 @@
+use std::fs;
+
+use crate::config::{
+    DEFAULT_FOV, MAX_FOV, MIN_FOV, MOUSE_SENSITIVITY_X, MUSIC_VOLUME, SOUND_VOLUME, MOUSE_SENSITIVITY_Y, MOUSE_SMOOTHING, PAD_DEAD_ZONE, PAD_LOOK_SPEED, PAD_RESPONSE_CURVE, PAD_TURN_SPEED
+};
+use crate::input::{Action, Bindings};
+
+/// User settings, read from a `key = value` file. Everything missing keeps its default.
+pub struct Settings {
+    /// Radians of turning per pixel of mouse movement.
+    pub mouse_sensitivity_x: f64,
+    /// Pixels of pitch per pixel of mouse movement.
+    pub mouse_sensitivity_y: f64,
+    /// 0.0 turns smoothing off, the closer to 1.0 the more the movement gets spread over the next frames.
+    pub mouse_smoothing: f64,
+    pub invert_y: bool,
+    /// Part of the stick range that is ignored (0.0 - 1.0).
+    pub pad_dead_zone: f64,
+    /// Exponent for the right stick, > 1.0 gives finer aiming near the center.
+    pub pad_response_curve: f64,
+    /// Radians per second and pitch pixels per second with the right stick all the way out.
+    pub pad_turn_speed: f64,
+    pub pad_look_speed: f64,
+    /// Horizontal field of view in degrees.
+    pub fov: f64,
+    /// 0.0 - 1.0, and whether sounds behind walls are quieter.
+    pub sound_volume: f64,
+    pub sound_occlusion: bool,
+    /// 0.0 - 1.0.
+    pub music_volume: f64,
+    /// Frames per second in the top right corner.
+    pub show_fps: bool,
+    /// Frame time graph and the time of every stage, see profiler.rs.
+    pub show_profiler: bool,
+    pub bindings: Bindings
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            mouse_sensitivity_x: MOUSE_SENSITIVITY_X,
+            mouse_sensitivity_y: MOUSE_SENSITIVITY_Y,
+            mouse_smoothing: MOUSE_SMOOTHING,
+            invert_y: false,
+            pad_dead_zone: PAD_DEAD_ZONE,
+            pad_response_curve: PAD_RESPONSE_CURVE,
+            pad_turn_speed: PAD_TURN_SPEED,
+            pad_look_speed: PAD_LOOK_SPEED,
+            fov: DEFAULT_FOV,
+            sound_volume: SOUND_VOLUME,
+            sound_occlusion: true,
+            music_volume: MUSIC_VOLUME,
+            show_fps: false,
+            show_profiler: false,
+            bindings: Bindings::default()
+        }
+    }
+}
+
+impl Settings {
+    /// Reads the settings file, `#` starts a comment:
+    ///
+    /// mouse_sensitivity_x = 0.003
+    /// mouse_sensitivity_y = 1.0
+    /// mouse_smoothing = 0.5
+    /// invert_y = true
+    /// pad_dead_zone = 0.2
+    /// fov = 90
+    /// sound_volume = 0.5
+    /// music_volume = 0.3
+    /// show_fps = true
+    /// bind.move_forward = W, Up
+    /// pad.fire = righttrigger, rightshoulder
+    pub fn from_file(filename: &str) -> Result<Self, String> {
+        let text = fs::read_to_string(filename).map_err(|e| format!("{}: {}", filename, e))?;
+        let mut settings = Settings::default();
+
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let error = |message: &str| format!("{}:{}: {}", filename, number + 1, message);
+
+            let (key, value) = line.split_once('=').ok_or_else(|| error("expected key = value"))?;
+            let (key, value) = (key.trim(), value.trim());
+
+            let number = || value.parse::<f64>().map_err(|e| error(&e.to_string()));
+            let flag = || value.parse::<bool>().map_err(|e| error(&e.to_string()));
+
+            match key {
+                "mouse_sensitivity_x" => settings.mouse_sensitivity_x = number()?,
+                "mouse_sensitivity_y" => settings.mouse_sensitivity_y = number()?,
+                "mouse_smoothing" => settings.mouse_smoothing = number()?.clamp(0.0, 0.99),
+                "invert_y" => settings.invert_y = flag()?,
+                "pad_dead_zone" => settings.pad_dead_zone = number()?.clamp(0.0, 0.95),
+                "pad_response_curve" => settings.pad_response_curve = number()?.max(0.1),
+                "pad_turn_speed" => settings.pad_turn_speed = number()?,
+                "pad_look_speed" => settings.pad_look_speed = number()?,
+                "fov" => settings.fov = number()?.clamp(MIN_FOV, MAX_FOV),
+                "sound_volume" => settings.sound_volume = number()?.clamp(0.0, 1.0),
+                "sound_occlusion" => settings.sound_occlusion = flag()?,
+                "music_volume" => settings.music_volume = number()?.clamp(0.0, 1.0),
+                "show_fps" => settings.show_fps = flag()?,
+                "show_profiler" => settings.show_profiler = flag()?,
+                _ => {
+                    let unknown = || error(&format!("unknown setting {}", key));
+
+                    if let Some(action) = key.strip_prefix("bind.") {
+                        let action = Action::from_name(action).ok_or_else(unknown)?;
+                        settings.bindings.bind(action, Bindings::parse_keys(value).map_err(|e| error(&e))?);
+                    } else {
+                        let action = key.strip_prefix("pad.").and_then(Action::from_name).ok_or_else(unknown)?;
+                        settings.bindings.bind_pad(action, Bindings::parse_pad(value).map_err(|e| error(&e))?);
+                    }
+                }
+            }
+        }
+
+        Ok(settings)
+    }
+
+    /// Writes every setting, so the file doubles as a list of what can be changed.
+    pub fn save(&self, filename: &str) -> Result<(), String> {
+        let mut text = format!(
+            "mouse_sensitivity_x = {}\nmouse_sensitivity_y = {}\nmouse_smoothing = {}\ninvert_y = {}\n\n",
+            self.mouse_sensitivity_x, self.mouse_sensitivity_y, self.mouse_smoothing, self.invert_y
+        );
+        text += &format!(
+            "pad_dead_zone = {}\npad_response_curve = {}\npad_turn_speed = {}\npad_look_speed = {}\n\n",
+            self.pad_dead_zone, self.pad_response_curve, self.pad_turn_speed, self.pad_look_speed
+        );
+        text += &format!("sound_volume = {}\nsound_occlusion = {}\nmusic_volume = {}\n\n", self.sound_volume, self.sound_occlusion, self.music_volume);
+        text += &format!("fov = {}\nshow_fps = {}\nshow_profiler = {}\n\n", self.fov, self.show_fps, self.show_profiler);
+
+        for action in Action::ALL {
+            text += &format!("bind.{} = {}\n", action.name(), self.bindings.keys_to_string(action));
+        }
+        text += "\n";
+
+        for action in Action::ALL {
+            text += &format!("pad.{} = {}\n", action.name(), self.bindings.pad_to_string(action));
+        }
+
+        fs::write(filename, text).map_err(|e| format!("{}: {}", filename, e))
+    }
+}
@@ -0,0 +1,47 @@
+use std::f64::consts::TAU;
+
+use sdl2::pixels::Color;
+
+use crate::config::SKY_HEIGHT;
+use crate::data::{LoadedTexture, PlayerData};
+use crate::framebuffer::FrameBuffer;
+
+/// Column of the panoramic sky texture for a screen column, the texture wraps around once per full turn.
+pub fn sky_tex_x(player: &PlayerData, x: i32, width: i32, tex_width: u32) -> usize {
+    let camera_x = 2.0 * (x as f64) / (width as f64) - 1.0;
+    let plane_length = (player.cam.x.powi(2) + player.cam.y.powi(2)).sqrt();
+
+    // Every column looks in its own direction, looking right turns clockwise so the angle shrinks.
+    let angle = player.dir.y.atan2(player.dir.x) - (camera_x * plane_length).atan();
+    let u = (-angle / TAU).rem_euclid(1.0);
+
+    (u * tex_width as f64) as usize % tex_width as usize
+}
+
+/// Row of the sky texture for a screen row, the bottom of the texture sits on the horizon
+/// and it reaches SKY_HEIGHT screen heights up, so it moves with the pitch.
+pub fn sky_tex_y(player: &PlayerData, y: i32, height: i32, tex_height: u32) -> usize {
+    let horizon = (height / 2 - player.ver) as f64;
+    let v = 1.0 - (horizon - y as f64) / (height as f64 * SKY_HEIGHT);
+
+    (v.clamp(0.0, 1.0) * (tex_height - 1) as f64) as usize
+}
+
+pub fn sky_color(sky: &LoadedTexture, player: &PlayerData, x: i32, y: i32, width: i32, height: i32) -> Color {
+    let tex_x = sky_tex_x(player, x, width, sky.width);
+    let tex_y = sky_tex_y(player, y, height, sky.height);
+
+    let (r, g, b, a) = sky.get_pixel(tex_x, tex_y).unwrap_or((0, 0, 0, 255));
+    Color::RGBA(r, g, b, a)
+}
+
+/// Fills a column of the framebuffer with the sky, both ends are inclusive.
+pub fn draw_sky_column(buffer: &mut FrameBuffer, sky: &LoadedTexture, player: &PlayerData, x: i32, draw_start: i32, draw_end: i32) {
+    let height = buffer.height as i32;
+    let tex_x = sky_tex_x(player, x, buffer.width as i32, sky.width);
+
+    for y in draw_start.max(0)..=draw_end.min(height - 1) {
+        let (r, g, b, a) = sky.get_pixel(tex_x, sky_tex_y(player, y, height, sky.height)).unwrap_or((0, 0, 0, 255));
+        buffer.set_pixel(x, y, Color::RGBA(r, g, b, a));
+    }
+}
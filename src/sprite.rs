@@ -0,0 +1,178 @@
+use std::collections::HashMap;
+use std::f64::consts::{FRAC_PI_4, FRAC_PI_8, PI, TAU};
+
+use sdl2::pixels::Color;
+
+use crate::config::{NEAR_PLANE, WAD_SCALE};
+use crate::data::{GameData, LoadedTexture, PlayerData, RenderMode, Vec2};
+use crate::bsp::sector_at;
+use crate::profiler::{self, Stage};
+
+/// What an entity is doing, every state has its own animation.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    Idle,
+    Walk,
+    Attack,
+    Pain,
+    Death
+}
+
+impl State {
+    pub const ALL: [State; 5] = [State::Idle, State::Walk, State::Attack, State::Pain, State::Death];
+
+    /// The name used in save files.
+    pub fn name(&self) -> &'static str {
+        match self {
+            State::Idle => "idle",
+            State::Walk => "walk",
+            State::Attack => "attack",
+            State::Pain => "pain",
+            State::Death => "death"
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<State> {
+        State::ALL.into_iter().find(|state| state.name() == name)
+    }
+}
+
+/// The frame letters of a state, shown one after another for `tics` ticks each.
+/// Death stops on its last frame, everything else loops.
+pub struct Animation {
+    pub state: State,
+    pub frames: &'static str,
+    pub tics: u32
+}
+
+/// All loaded sprite frames, named like Doom's sprite lumps.
+/// `POSSA1` is frame A seen from rotation 1 (the front), `POSSA0` is the same from every side
+/// and `POSSA2A8` is used for rotation 2 and mirrored for rotation 8.
+#[derive(Default)]
+pub struct Sprites {
+    textures: Vec<LoadedTexture>,
+    /// Sprite and frame (`POSSA`) => the texture and whether it gets mirrored, for rotations 1-8.
+    frames: HashMap<String, [Option<(usize, bool)>; 8]>
+}
+
+impl Sprites {
+    /// Adds a lump, names that don't follow the pattern are ignored.
+    pub fn add(&mut self, name: &str, texture: LoadedTexture) {
+        let name = name.as_bytes();
+        if name.len() < 6 || !name.is_ascii() {
+            return;
+        }
+
+        let index = self.textures.len();
+        let mut used = false;
+
+        // The mirrored pair comes second.
+        for (pair, flipped) in [(4, false), (6, true)] {
+            let Some(&[frame, rotation]) = name.get(pair..pair + 2) else {
+                continue;
+            };
+
+            let key = format!("{}{}", String::from_utf8_lossy(&name[..4]), frame as char);
+            let rotations = self.frames.entry(key).or_default();
+
+            match rotation {
+                b'0' => *rotations = [Some((index, flipped)); 8],
+                b'1'..=b'8' => rotations[(rotation - b'1') as usize] = Some((index, flipped)),
+                _ => continue
+            }
+
+            used = true;
+        }
+
+        if used {
+            self.textures.push(texture);
+        }
+    }
+
+    pub fn contains(&self, sprite: &str) -> bool {
+        self.frames.keys().any(|key| key.starts_with(sprite))
+    }
+
+    /// The picture for a rotation (0 - 7, 0 is the front), and if it has to be drawn mirrored.
+    /// Frames that are missing fall back to the first one, so a single picture still works.
+    pub fn get(&self, sprite: &str, frame: char, rotation: usize) -> Option<(&LoadedTexture, bool)> {
+        let rotations = self.frames.get(&format!("{}{}", sprite, frame))
+            .or_else(|| self.frames.get(&format!("{}A", sprite)))?;
+
+        let (index, flipped) = rotations[rotation].or_else(|| rotations.iter().flatten().next().copied())?;
+        Some((&self.textures[index], flipped))
+    }
+}
+
+/// Which of the 8 rotations the viewer sees, 0 when the entity looks right at them, counting counter-clockwise.
+pub fn rotation(viewer: &Vec2<f64>, pos: &Vec2<f64>, angle: f64) -> usize {
+    let to_entity = (pos.y - viewer.y).atan2(pos.x - viewer.x);
+    ((to_entity - angle + PI + FRAC_PI_8).rem_euclid(TAU) / FRAC_PI_4) as usize % 8
+}
+
+/// Draws every entity as a billboard standing on the floor, from back to front.
+/// `plot(x, y, depth, color)` has to skip pixels that are behind an already drawn wall.
+pub fn draw_sprites(player: &PlayerData, game_data: &GameData, (width, height): (i32, i32), mut plot: impl FnMut(i32, i32, f64, Color)) {
+    profiler::scope!(Stage::Sprites);
+    let horizon = (height / 2 - player.ver) as f64;
+    let eye = player.eye();
+
+    // The inverse of the camera matrix, same as for the walls.
+    let inv_det = 1.0 / (player.cam.x * player.dir.y - player.dir.x * player.cam.y);
+
+    let mut visible: Vec<(f64, f64, usize)> = game_data.entities.iter().enumerate().filter_map(|(i, entity)| {
+        let rel_x = entity.pos.x - player.pos.x;
+        let rel_y = entity.pos.y - player.pos.y;
+
+        let view_x = inv_det * (player.dir.y * rel_x - player.dir.x * rel_y);
+        let z = inv_det * (-player.cam.y * rel_x + player.cam.x * rel_y);
+
+        (z > NEAR_PLANE).then_some((z, view_x, i))
+    }).collect();
+
+    // Far ones first, so closer sprites get drawn over them.
+    visible.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+    for (z, view_x, i) in visible {
+        let entity = &game_data.entities[i];
+        let Some((texture, flipped)) = game_data.sprite_frame(entity, &player.pos) else {
+            continue;
+        };
+
+        let scale = height as f64 / z;
+        let screen_x = width as f64 / 2.0 * (1.0 + view_x / z);
+
+        // Sprites are drawn at the same size as the walls, 64 pixels per unit.
+        let sprite_width = texture.width as f64 * WAD_SCALE * scale;
+        let sprite_height = texture.height as f64 * WAD_SCALE * scale;
+
+        let (floor, _) = game_data.floor_and_ceiling(&entity.pos);
+        let bottom = horizon + (eye - floor - entity.z) * scale;
+        let top = bottom - sprite_height;
+        let left = screen_x - sprite_width / 2.0;
+
+        let light = match game_data.render_mode {
+            RenderMode::Bsp => sector_at(game_data, &entity.pos).light as f64 / 255.0,
+            RenderMode::Grid | RenderMode::Sectors => 1.0
+        };
+
+        for x in (left.ceil().max(0.0) as i32)..((left + sprite_width).ceil().min(width as f64) as i32) {
+            let tex_x = (((x as f64 - left) / sprite_width * texture.width as f64) as usize).min(texture.width as usize - 1);
+            let tex_x = if flipped {texture.width as usize - 1 - tex_x} else {tex_x};
+
+            for y in (top.ceil().max(0.0) as i32)..(bottom.ceil().min(height as f64) as i32) {
+                let tex_y = ((y as f64 - top) / sprite_height * texture.height as f64) as usize;
+                let Some((r, g, b, a)) = texture.get_pixel(tex_x, tex_y) else {
+                    continue;
+                };
+
+                // Transparent pixels, the pictures in pics/ use black for that.
+                if a == 0 || (r, g, b) == (0, 0, 0) {
+                    continue;
+                }
+
+                plot(x, y, z, Color::RGB((r as f64 * light) as u8, (g as f64 * light) as u8, (b as f64 * light) as u8));
+            }
+        }
+    }
+}
@@ -0,0 +1,496 @@
+use std::fs;
+
+use crate::audio::Sound;
+use crate::config::{DOOR_LIP, EPISODE_FILE, MESSAGE_TICKS, PLANE_SPEED, TICK_RATE, USE_RANGE};
+use crate::data::{GameData, MapSource, PlayerData, Vec2};
+use crate::level::Level;
+use crate::map::intersect;
+use crate::menu::Screen;
+
+/// What a switch or a walk-over trigger does.
+#[derive(Clone, PartialEq)]
+pub enum TriggerAction {
+    /// Raises the ceiling of the sectors with the tag to just below the lowest ceiling around them
+    /// and leaves it there. Tag 0 is the sector behind the switch line, like Doom's doors.
+    Door(u16),
+    /// Moves the floor or the ceiling of the sectors with the tag to a height.
+    Floor(u16, f64),
+    Ceiling(u16, f64),
+    /// Every texture of the lines with the tag becomes this one, the level file's way to change a tile.
+    Tile(u16, String),
+    Secret,
+    /// Loads the next map of the episode list.
+    Exit
+}
+
+impl TriggerAction {
+    /// `door <tag>`, `floor <tag> <height>`, `ceiling <tag> <height>`, `tile <tag> <texture>`, `secret` or `exit`.
+    pub fn parse(words: &[&str]) -> Result<Self, String> {
+        let tag = || -> Result<u16, String> {
+            words.get(1).ok_or("missing tag")?.parse().map_err(|_| format!("bad tag {}", words[1]))
+        };
+        let height = || -> Result<f64, String> {
+            words.get(2).ok_or("missing height")?.parse().map_err(|_| format!("bad height {}", words[2]))
+        };
+
+        let (action, count) = match words.first() {
+            Some(&"door") => (TriggerAction::Door(tag()?), 2),
+            Some(&"floor") => (TriggerAction::Floor(tag()?, height()?), 3),
+            Some(&"ceiling") => (TriggerAction::Ceiling(tag()?, height()?), 3),
+            Some(&"tile") => (TriggerAction::Tile(tag()?, words.get(2).ok_or("missing texture")?.to_string()), 3),
+            Some(&"secret") => (TriggerAction::Secret, 1),
+            Some(&"exit") => (TriggerAction::Exit, 1),
+            Some(other) => return Err(format!("unknown action {}", other)),
+            None => return Err("missing action".to_string())
+        };
+
+        if words.len() > count {
+            return Err("too many values".to_string());
+        }
+        Ok(action)
+    }
+}
+
+/// How a trigger gets set off.
+#[derive(Clone, PartialEq)]
+pub enum Activation {
+    /// Using the line, index into Level::linedefs. Its front side shows `texture` afterwards,
+    /// None swaps Doom's SW1 textures for their SW2 versions.
+    Switch { line: usize, texture: Option<String> },
+    /// Walking into a grid cell.
+    Cell { x: i32, y: i32 },
+    /// Walking into a sector, Doom's secret sectors.
+    Sector(usize)
+}
+
+/// An action declared by the map, every trigger only goes off once.
+pub struct Trigger {
+    pub activation: Activation,
+    pub action: TriggerAction,
+    pub fired: bool
+}
+
+impl Trigger {
+    pub fn new(activation: Activation, action: TriggerAction) -> Self {
+        Trigger { activation, action, fired: false }
+    }
+}
+
+/// A floor or ceiling on its way to a new height.
+pub struct Mover {
+    pub sector: usize,
+    pub ceiling: bool,
+    pub target: f64
+}
+
+/// Uses what the player faces, sets off the triggers of the cell and sector the player is in and moves
+/// floors and ceilings. Called every tick.
+pub fn tick(player: &mut PlayerData, game_data: &mut GameData) {
+    move_planes(game_data);
+
+    if std::mem::take(&mut player.using) {
+        if let Some(line) = used_line(player, &game_data.level) {
+            let switches: Vec<usize> = triggers_where(&game_data.level, |activation| {
+                matches!(activation, Activation::Switch { line: switch, .. } if *switch == line)
+            });
+            if let Some(&first) = switches.first() {
+                game_data.audio.play(Sound::Switch, None);
+                press_switch(&mut game_data.level, first);
+            }
+
+            for index in switches {
+                if fire(index, Some(line), player, game_data) {
+                    return;
+                }
+            }
+        }
+    }
+
+    let cell = (player.pos.x.floor() as i32, player.pos.y.floor() as i32);
+    let sector = game_data.custom_level.then(|| game_data.bsp.locate(&player.pos).sector);
+    // Standing still in a trigger whose exit couldn't be loaded doesn't try it again every tick.
+    if game_data.walked_in.replace((cell, sector)) == Some((cell, sector)) {
+        return;
+    }
+    let walked = triggers_where(&game_data.level, |activation| match *activation {
+        Activation::Cell { x, y } => (x, y) == cell,
+        Activation::Sector(index) => Some(index) == sector,
+        Activation::Switch { .. } => false
+    });
+
+    for index in walked {
+        if fire(index, None, player, game_data) {
+            return;
+        }
+    }
+}
+
+/// Indices of the triggers that haven't gone off yet and match.
+fn triggers_where(level: &Level, matches: impl Fn(&Activation) -> bool) -> Vec<usize> {
+    level.triggers.iter()
+        .enumerate()
+        .filter(|(_, trigger)| !trigger.fired && matches(&trigger.activation))
+        .map(|(index, _)| index)
+        .collect()
+}
+
+/// The closest line in front of the player within USE_RANGE that is a wall or a switch,
+/// open two-sided lines in between don't get in the way.
+fn used_line(player: &PlayerData, level: &Level) -> Option<usize> {
+    let reach = Vec2::new(player.dir.x * USE_RANGE, player.dir.y * USE_RANGE);
+    let is_switch = |index: usize| level.triggers.iter().any(|trigger| {
+        matches!(trigger.activation, Activation::Switch { line, .. } if line == index)
+    });
+
+    level.linedefs.iter()
+        .enumerate()
+        .filter(|(index, linedef)| linedef.back.is_none() || is_switch(*index))
+        .filter_map(|(index, linedef)| {
+            let (t, u) = intersect(&player.pos, &reach, &level.vertices[linedef.start], &level.vertices[linedef.end])?;
+            ((0.0..=1.0).contains(&t) && (0.0..=1.0).contains(&u)).then_some((index, t))
+        })
+        .min_by(|(_, a), (_, b)| a.total_cmp(b))
+        .map(|(index, _)| index)
+}
+
+/// Shows the pressed texture on the front side of a switch line.
+fn press_switch(level: &mut Level, index: usize) {
+    let Activation::Switch { line, texture } = &level.triggers[index].activation else {
+        return;
+    };
+    let Some(side) = level.linedefs[*line].front else {
+        return;
+    };
+    let side = &mut level.sidedefs[side];
+
+    match texture {
+        Some(texture) => {
+            let slot = [&mut side.middle, &mut side.lower, &mut side.upper].into_iter().find(|name| !name.is_empty());
+            if let Some(slot) = slot {
+                *slot = texture.clone();
+            }
+        },
+        None => {
+            for slot in [&mut side.middle, &mut side.lower, &mut side.upper] {
+                if let Some(rest) = slot.strip_prefix("SW1") {
+                    *slot = format!("SW2{}", rest);
+                }
+            }
+        }
+    }
+}
+
+/// Sets off a trigger, true if it loaded another map and the old level is gone.
+fn fire(index: usize, line: Option<usize>, player: &mut PlayerData, game_data: &mut GameData) -> bool {
+    game_data.level.triggers[index].fired = true;
+    let level = &game_data.level;
+
+    match level.triggers[index].action.clone() {
+        TriggerAction::Door(tag) => {
+            let movers = tagged_sectors(level, tag, line).into_iter()
+                .filter_map(|sector| Some(Mover { sector, ceiling: true, target: lowest_ceiling_around(level, sector)? - DOOR_LIP }))
+                .collect();
+            start_movers(game_data, movers, Sound::Door);
+        },
+        TriggerAction::Floor(tag, target) => {
+            let movers = tagged_sectors(level, tag, line).into_iter().map(|sector| Mover { sector, ceiling: false, target }).collect();
+            start_movers(game_data, movers, Sound::Lift);
+        },
+        TriggerAction::Ceiling(tag, target) => {
+            let movers = tagged_sectors(level, tag, line).into_iter().map(|sector| Mover { sector, ceiling: true, target }).collect();
+            start_movers(game_data, movers, Sound::Lift);
+        },
+        TriggerAction::Tile(tag, texture) => change_tiles(&mut game_data.level, tag, &texture),
+        TriggerAction::Secret => player.message = Some(("A secret is revealed!", MESSAGE_TICKS)),
+        TriggerAction::Exit => {
+            if exit_level(player, game_data) {
+                return true;
+            }
+
+            // Can be used or walked into again, there might be nothing wrong with the map next time.
+            game_data.level.triggers[index].fired = false;
+        }
+    }
+
+    false
+}
+
+/// Sectors with the tag, tag 0 on a switch line is the sector on its back side.
+fn tagged_sectors(level: &Level, tag: u16, line: Option<usize>) -> Vec<usize> {
+    if tag == 0 {
+        return line.and_then(|line| level.linedefs[line].back)
+            .map(|side| vec![level.sidedefs[side].sector])
+            .unwrap_or_default();
+    }
+
+    (0..level.sectors.len()).filter(|&index| level.sectors[index].tag == tag).collect()
+}
+
+/// The lowest ceiling of the sectors next to this one, None if it has no neighbours.
+fn lowest_ceiling_around(level: &Level, sector: usize) -> Option<f64> {
+    level.linedefs.iter()
+        .filter_map(|linedef| {
+            let front = level.sidedefs[linedef.front?].sector;
+            let back = level.sidedefs[linedef.back?].sector;
+            match (front == sector, back == sector) {
+                (true, false) => Some(back),
+                (false, true) => Some(front),
+                _ => None
+            }
+        })
+        .map(|neighbour| level.sectors[neighbour].ceiling)
+        .min_by(f64::total_cmp)
+}
+
+/// A new move of a plane replaces the one it is already doing, the sound comes from the middle of the first sector.
+fn start_movers(game_data: &mut GameData, movers: Vec<Mover>, sound: Sound) {
+    if let Some(first) = movers.first() {
+        let origin = sector_center(&game_data.level, first.sector);
+        game_data.audio.play(sound, origin);
+    }
+
+    for mover in movers {
+        game_data.movers.retain(|other| other.sector != mover.sector || other.ceiling != mover.ceiling);
+        game_data.movers.push(mover);
+    }
+}
+
+/// Average of the corners of the sector's lines.
+fn sector_center(level: &Level, sector: usize) -> Option<Vec2<f64>> {
+    let corners: Vec<&Vec2<f64>> = level.linedefs.iter()
+        .filter(|linedef| linedef.front.into_iter().chain(linedef.back).any(|side| level.sidedefs[side].sector == sector))
+        .flat_map(|linedef| [&level.vertices[linedef.start], &level.vertices[linedef.end]])
+        .collect();
+
+    (!corners.is_empty()).then(|| {
+        let count = corners.len() as f64;
+        Vec2::new(corners.iter().map(|corner| corner.x).sum::<f64>() / count, corners.iter().map(|corner| corner.y).sum::<f64>() / count)
+    })
+}
+
+/// Tag 0 changes nothing, most lines don't have a tag.
+fn change_tiles(level: &mut Level, tag: u16, texture: &str) {
+    let sides: Vec<usize> = level.linedefs.iter()
+        .filter(|linedef| tag != 0 && linedef.tag == tag)
+        .flat_map(|linedef| linedef.front.into_iter().chain(linedef.back))
+        .collect();
+
+    for side in sides {
+        let side = &mut level.sidedefs[side];
+        for slot in [&mut side.middle, &mut side.lower, &mut side.upper].into_iter().filter(|name| !name.is_empty()) {
+            *slot = texture.to_string();
+        }
+    }
+}
+
+/// Moves floors and ceilings PLANE_SPEED towards their target, done ones stop.
+fn move_planes(game_data: &mut GameData) {
+    let level = &mut game_data.level;
+
+    game_data.movers.retain(|mover| {
+        let sector = &mut level.sectors[mover.sector];
+        let height = if mover.ceiling {&mut sector.ceiling} else {&mut sector.floor};
+        let step = PLANE_SPEED / TICK_RATE;
+
+        *height = if (mover.target - *height).abs() <= step {mover.target} else {*height + step.copysign(mover.target - *height)};
+        *height != mover.target
+    });
+}
+
+/// Puts back what the triggers that already went off changed about the textures, for loading a saved game.
+/// The heights are in the save themselves.
+pub fn restore(level: &mut Level, index: usize) {
+    level.triggers[index].fired = true;
+
+    if matches!(level.triggers[index].activation, Activation::Switch { .. }) {
+        press_switch(level, index);
+    }
+    if let TriggerAction::Tile(tag, texture) = level.triggers[index].action.clone() {
+        change_tiles(level, tag, &texture);
+    }
+}
+
+/// The map after the current one in EPISODE_FILE. WAD maps that aren't in the list go on to the next
+/// map number (E1M1 -> E1M2, MAP01 -> MAP02).
+fn next_map(game_data: &GameData) -> Option<String> {
+    let current = match &game_data.map_source {
+        MapSource::Grid => return None,
+        MapSource::LevelFile(file) => file.clone(),
+        MapSource::Wad { map, .. } => map.clone()
+    };
+
+    // Without an episode list only the WAD maps go on.
+    map_after(&current, &fs::read_to_string(EPISODE_FILE).unwrap_or_default())
+}
+
+/// The map after `current` in the text of an episode list, or by its map number.
+fn map_after(current: &str, episode: &str) -> Option<String> {
+    let maps: Vec<&str> = episode.lines()
+        .map(|line| line.split('#').next().unwrap_or("").trim())
+        .filter(|line| !line.is_empty())
+        .collect();
+
+    if let Some(position) = maps.iter().position(|map| map.eq_ignore_ascii_case(current)) {
+        return maps.get(position + 1).map(|map| map.to_string());
+    }
+
+    if let Some(number) = current.strip_prefix("MAP").and_then(|number| number.parse::<u32>().ok()) {
+        return (number < 32).then(|| format!("MAP{:02}", number + 1));
+    }
+
+    match current.as_bytes() {
+        [b'E', episode, b'M', map] if episode.is_ascii_digit() && (b'1'..b'8').contains(map) => {
+            Some(format!("E{}M{}", *episode as char, (map + 1) as char))
+        },
+        _ => None
+    }
+}
+
+/// Where a map of the episode list comes from, .lvl files or maps of the WAD that is loaded.
+fn map_source(map: &str, game_data: &GameData) -> Result<MapSource, String> {
+    if map.ends_with(".lvl") {
+        return Ok(MapSource::LevelFile(map.to_string()));
+    }
+
+    let file = game_data.wad_file.clone().ok_or_else(|| format!("{} needs a WAD, start with --wad <file>", map))?;
+    Ok(MapSource::Wad { file, map: map.to_uppercase() })
+}
+
+/// Tells how many secrets were found and loads the next map, after the last one the title menu comes up.
+/// False if the next map couldn't be loaded, the current one stays and the console has the reason.
+fn exit_level(player: &mut PlayerData, game_data: &mut GameData) -> bool {
+    let secrets = game_data.level.triggers.iter().filter(|trigger| trigger.action == TriggerAction::Secret);
+    let (found, total) = secrets.fold((0, 0), |(found, total), trigger| (found + trigger.fired as u32, total + 1));
+
+    let Some(map) = next_map(game_data) else {
+        game_data.console.print(&format!("level done, {} of {} secrets found", found, total));
+        game_data.console.print("that was the last map of the episode");
+        game_data.menu.open(Screen::Title);
+        return true;
+    };
+
+    match map_source(&map, game_data).and_then(|source| game_data.load_map(source)) {
+        Ok(()) => {
+            game_data.console.print(&format!("level done, {} of {} secrets found", found, total));
+            if let Some(start) = game_data.level.player_start() {
+                player.place(start.pos, start.angle);
+            }
+            true
+        },
+        Err(error) => {
+            game_data.console.print(&format!("can't load {}: {}", map, error));
+            player.message = Some(("Can't load the next map, see the console", MESSAGE_TICKS));
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::level::{LevelSector, Linedef, Sidedef};
+    use crate::map::Map;
+
+    fn sector(tag: u16) -> LevelSector {
+        LevelSector { floor: 0.0, ceiling: 1.0, floor_flat: String::new(), ceiling_flat: String::new(), light: 255, special: 0, tag }
+    }
+
+    fn side(sector: usize) -> Sidedef {
+        Sidedef { x_offset: 0.0, y_offset: 0.0, upper: String::new(), lower: String::new(), middle: String::new(), sector }
+    }
+
+    /// Three sectors, the first two are tagged 1 and line 0 goes from sector 0 to sector 2.
+    fn game_data() -> GameData {
+        let mut game_data = GameData::new(false, 320, 200, Map::from_grid(&[[0; 3]; 3], None));
+        let level = &mut game_data.level;
+
+        level.vertices = vec![Vec2::new(0.0, 0.0), Vec2::new(0.0, 1.0)];
+        level.sectors = vec![sector(1), sector(1), sector(0)];
+        level.sidedefs = vec![side(0), side(2)];
+        level.linedefs = vec![Linedef { start: 0, end: 1, flags: 0, special: 0, tag: 0, front: Some(0), back: Some(1) }];
+        game_data
+    }
+
+    #[test]
+    fn parses_actions() {
+        assert!(TriggerAction::parse(&["door", "3"]).unwrap() == TriggerAction::Door(3));
+        assert!(TriggerAction::parse(&["floor", "1", "0.5"]).unwrap() == TriggerAction::Floor(1, 0.5));
+        assert!(TriggerAction::parse(&["tile", "2", "eagle"]).unwrap() == TriggerAction::Tile(2, "eagle".to_string()));
+        assert!(TriggerAction::parse(&["exit"]).unwrap() == TriggerAction::Exit);
+
+        assert_eq!(TriggerAction::parse(&["door"]).err().unwrap(), "missing tag");
+        assert_eq!(TriggerAction::parse(&["door", "x"]).err().unwrap(), "bad tag x");
+        assert_eq!(TriggerAction::parse(&["ceiling", "1"]).err().unwrap(), "missing height");
+        assert_eq!(TriggerAction::parse(&["ceiling", "1", "up"]).err().unwrap(), "bad height up");
+        assert_eq!(TriggerAction::parse(&["tile", "1"]).err().unwrap(), "missing texture");
+        assert_eq!(TriggerAction::parse(&["door", "1", "2"]).err().unwrap(), "too many values");
+        assert_eq!(TriggerAction::parse(&["secret", "1"]).err().unwrap(), "too many values");
+        assert_eq!(TriggerAction::parse(&["lift", "1"]).err().unwrap(), "unknown action lift");
+        assert_eq!(TriggerAction::parse(&[]).err().unwrap(), "missing action");
+    }
+
+    #[test]
+    fn finds_the_next_map() {
+        let episode = "# comment\nlevels/demo.lvl  # first\n\ne1m5\nlevels/demo2.lvl\n";
+
+        assert_eq!(map_after("levels/demo.lvl", episode).as_deref(), Some("e1m5"));
+        assert_eq!(map_after("E1M5", episode).as_deref(), Some("levels/demo2.lvl"));
+        assert_eq!(map_after("levels/demo2.lvl", episode), None);
+        assert_eq!(map_after("levels/other.lvl", episode), None);
+
+        // Not in the list, by the map number up to the last map of an episode.
+        assert_eq!(map_after("E1M1", episode).as_deref(), Some("E1M2"));
+        assert_eq!(map_after("E2M7", "").as_deref(), Some("E2M8"));
+        assert_eq!(map_after("E1M8", ""), None);
+        assert_eq!(map_after("MAP09", "").as_deref(), Some("MAP10"));
+        assert_eq!(map_after("MAP31", "").as_deref(), Some("MAP32"));
+        assert_eq!(map_after("MAP32", ""), None);
+
+        let mut game_data = game_data();
+        game_data.map_source = MapSource::Grid;
+        assert_eq!(next_map(&game_data), None);
+    }
+
+    #[test]
+    fn planes_stop_at_their_target() {
+        let mut game_data = game_data();
+        let step = PLANE_SPEED / TICK_RATE;
+        game_data.movers = vec![Mover { sector: 0, ceiling: false, target: step * 2.5 }, Mover { sector: 1, ceiling: true, target: 1.0 - step }];
+
+        move_planes(&mut game_data);
+        assert_eq!((game_data.level.sectors[0].floor, game_data.level.sectors[1].ceiling), (step, 1.0 - step));
+        assert_eq!(game_data.movers.len(), 1);
+
+        move_planes(&mut game_data);
+        move_planes(&mut game_data);
+        assert_eq!(game_data.level.sectors[0].floor, step * 2.5);
+        assert!(game_data.movers.is_empty());
+
+        move_planes(&mut game_data);
+        assert_eq!(game_data.level.sectors[0].floor, step * 2.5);
+    }
+
+    #[test]
+    fn tag_0_is_the_sector_behind_the_switch() {
+        let level = &game_data().level;
+
+        assert_eq!(tagged_sectors(level, 0, Some(0)), [2]);
+        assert!(tagged_sectors(level, 0, None).is_empty());
+        assert_eq!(tagged_sectors(level, 1, Some(0)), [0, 1]);
+        assert!(tagged_sectors(level, 5, None).is_empty());
+    }
+
+    #[test]
+    fn failed_exits_can_be_tried_again() {
+        let mut game_data = game_data();
+        let mut player = game_data.new_player();
+        // E1M1 isn't in the episode list, E1M2 needs a WAD that isn't loaded.
+        game_data.map_source = MapSource::Wad { file: "doom.wad".to_string(), map: "E1M1".to_string() };
+        game_data.level.triggers = vec![Trigger::new(Activation::Switch { line: 0, texture: None }, TriggerAction::Exit)];
+
+        assert!(!fire(0, Some(0), &mut player, &mut game_data));
+        assert!(!game_data.level.triggers[0].fired);
+        assert!(player.message.is_some());
+        assert_eq!(game_data.level.sectors.len(), 3);
+    }
+}
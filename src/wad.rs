@@ -0,0 +1,602 @@
+use std::fs;
+
+use sdl2::pixels::Color;
+
+use crate::config::WAD_SCALE;
+use crate::data::{LoadedTexture, Vec2};
+use crate::level::{Level, LevelSector, Linedef, Sidedef, Thing};
+use crate::trigger::{Activation, Trigger, TriggerAction};
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum WadKind {
+    Iwad,
+    Pwad
+}
+
+pub struct Lump {
+    pub name: String,
+    pub data: Vec<u8>
+}
+
+/// A Doom WAD file, the directory is read into a list of lumps in the order they are stored.
+pub struct Wad {
+    pub kind: WadKind,
+    pub lumps: Vec<Lump>
+}
+
+impl Wad {
+    pub fn from_file(filename: &str) -> Result<Self, String> {
+        let bytes = fs::read(filename).map_err(|e| format!("{}: {}", filename, e))?;
+        Wad::from_bytes(&bytes)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        let kind = match bytes.get(0..4) {
+            Some(b"IWAD") => WadKind::Iwad,
+            Some(b"PWAD") => WadKind::Pwad,
+            _ => return Err("Not a WAD file".to_string())
+        };
+
+        let lump_count = read_offset(bytes, 4).map_err(|_| "Broken WAD header")?;
+        let directory = read_offset(bytes, 8).map_err(|_| "Broken WAD header")?;
+
+        // The whole directory has to be in the file before anything gets allocated for it.
+        lump_count.checked_mul(16)
+            .and_then(|size| read_slice(bytes, directory, size).ok())
+            .ok_or("The WAD directory points outside of the file")?;
+
+        let mut lumps = Vec::with_capacity(lump_count);
+        for i in 0..lump_count {
+            let entry = directory + i * 16;
+            let pos = read_offset(bytes, entry)?;
+            let size = read_offset(bytes, entry + 4)?;
+
+            let data = read_slice(bytes, pos, size)
+                .map_err(|_| format!("Lump {} points outside of the file", i))?;
+
+            lumps.push(Lump {
+                name: read_name(bytes, entry + 8)?,
+                data: data.to_vec()
+            });
+        }
+
+        Ok(Wad { kind, lumps })
+    }
+
+    /// Finds a lump by name, later lumps replace earlier ones just like in Doom.
+    pub fn lump(&self, name: &str) -> Option<&Lump> {
+        self.lumps.iter().rev().find(|lump| lump.name == name)
+    }
+
+    /// The data lumps of a map follow directly after its marker lump (E1M1, MAP01, ...).
+    fn map_lump(&self, map: &str, name: &str) -> Result<&Lump, String> {
+        let marker = self.lumps.iter().rposition(|lump| lump.name == map)
+            .ok_or_else(|| format!("Map {} not found", map))?;
+
+        self.lumps.iter()
+            .skip(marker + 1)
+            .take(10)
+            .find(|lump| lump.name == name)
+            .ok_or_else(|| format!("Map {} has no {} lump", map, name))
+    }
+
+    pub fn read_level(&self, map: &str) -> Result<Level, String> {
+        let vertices = records(&self.map_lump(map, "VERTEXES")?.data, 4, |data| Ok(Vec2::new(
+            read_i16(data, 0)? as f64 * WAD_SCALE,
+            read_i16(data, 2)? as f64 * WAD_SCALE
+        )))?;
+
+        let linedefs = records(&self.map_lump(map, "LINEDEFS")?.data, 14, |data| {
+            let side = |pos| read_u16(data, pos).map(|side| if side == 0xFFFF {None} else {Some(side as usize)});
+
+            Ok(Linedef {
+                start: read_u16(data, 0)? as usize,
+                end: read_u16(data, 2)? as usize,
+                flags: read_u16(data, 4)?,
+                special: read_u16(data, 6)?,
+                tag: read_u16(data, 8)?,
+                front: side(10)?,
+                back: side(12)?
+            })
+        })?;
+
+        let sidedefs = records(&self.map_lump(map, "SIDEDEFS")?.data, 30, |data| Ok(Sidedef {
+            x_offset: read_i16(data, 0)? as f64 * WAD_SCALE,
+            y_offset: read_i16(data, 2)? as f64 * WAD_SCALE,
+            upper: read_texture_name(data, 4)?,
+            lower: read_texture_name(data, 12)?,
+            middle: read_texture_name(data, 20)?,
+            sector: read_u16(data, 28)? as usize
+        }))?;
+
+        let sectors = records(&self.map_lump(map, "SECTORS")?.data, 26, |data| Ok(LevelSector {
+            floor: read_i16(data, 0)? as f64 * WAD_SCALE,
+            ceiling: read_i16(data, 2)? as f64 * WAD_SCALE,
+            floor_flat: read_name(data, 4)?,
+            ceiling_flat: read_name(data, 12)?,
+            light: read_i16(data, 20)?.clamp(0, 255) as u8,
+            special: read_u16(data, 22)?,
+            tag: read_u16(data, 24)?
+        }))?;
+
+        let things = records(&self.map_lump(map, "THINGS")?.data, 10, |data| Ok(Thing {
+            pos: Vec2::new(read_i16(data, 0)? as f64 * WAD_SCALE, read_i16(data, 2)? as f64 * WAD_SCALE),
+            angle: (read_i16(data, 4)? as f64).to_radians(),
+            kind: read_u16(data, 6)?,
+            flags: read_u16(data, 8)?
+        }))?;
+
+        // Catch broken references here, so the renderer can index without checking.
+        if sectors.is_empty() {
+            return Err(format!("Map {} has no sectors", map));
+        }
+        for linedef in &linedefs {
+            if linedef.start >= vertices.len() || linedef.end >= vertices.len() {
+                return Err(format!("Map {} has a linedef with a missing vertex", map));
+            }
+            if linedef.front.iter().chain(linedef.back.iter()).any(|&side| side >= sidedefs.len()) {
+                return Err(format!("Map {} has a linedef with a missing sidedef", map));
+            }
+        }
+        if sidedefs.iter().any(|side| side.sector >= sectors.len()) {
+            return Err(format!("Map {} has a sidedef with a missing sector", map));
+        }
+
+        // Ultimate Doom's episode 4 has no music lumps of its own.
+        let music = Some(music_name(map)).filter(|name| self.lump(name).is_some());
+
+        let triggers = linedefs.iter().enumerate()
+            .filter_map(|(line, linedef)| Some(Trigger::new(Activation::Switch { line, texture: None }, line_action(linedef)?)))
+            .chain((0..sectors.len()).filter(|&index| sectors[index].special == SECRET_SECTOR).map(|index| {
+                Trigger::new(Activation::Sector(index), TriggerAction::Secret)
+            }))
+            .collect();
+
+        Ok(Level { vertices, linedefs, sidedefs, sectors, things, sky: Some(sky_name(map).to_string()), music, triggers })
+    }
+
+    /// A DMX sound effect (DSPISTOL, ...): format 3, the sample rate and 8-bit unsigned samples.
+    /// The 16 padding bytes on both ends of the samples get cut off.
+    pub fn read_sound(&self, name: &str) -> Result<(u32, Vec<u8>), String> {
+        let data = &self.lump(name).ok_or_else(|| format!("No {} lump", name))?.data;
+        if read_u16(data, 0)? != 3 {
+            return Err(format!("{} isn't a sound", name));
+        }
+
+        let rate = read_u16(data, 2)? as u32;
+        let count = read_i32(data, 4)?.max(0) as usize;
+        let samples = read_slice(data, 8, count).map_err(|_| format!("{} is too short", name))?;
+        let samples = samples.get(16..count.saturating_sub(16)).unwrap_or(samples);
+
+        Ok((rate, samples.to_vec()))
+    }
+
+    /// The first of the PLAYPAL palettes, the others are used for damage and pickup flashes.
+    pub fn read_palette(&self) -> Result<Vec<Color>, String> {
+        let playpal = match (self.lump("PLAYPAL"), self.kind) {
+            (Some(playpal), _) => playpal,
+            (None, WadKind::Pwad) => return Err("No PLAYPAL lump, PWADs need the palette of an IWAD".to_string()),
+            (None, WadKind::Iwad) => return Err("No PLAYPAL lump".to_string())
+        };
+
+        playpal.data.get(0..768)
+            .map(|data| data.chunks_exact(3).map(|rgb| Color::RGB(rgb[0], rgb[1], rgb[2])).collect())
+            .ok_or_else(|| "PLAYPAL is too short".to_string())
+    }
+
+    /// Builds the composite wall textures out of TEXTURE1/TEXTURE2 and their patches.
+    pub fn read_textures(&self, palette: &[Color]) -> Result<Vec<(String, LoadedTexture)>, String> {
+        let pnames = self.lump("PNAMES").ok_or("No PNAMES lump")?;
+        let patch_names = (0..read_i32(&pnames.data, 0)?.max(0) as usize)
+            .map(|i| read_name(&pnames.data, 4 + i * 8))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut textures = Vec::new();
+
+        for lump in ["TEXTURE1", "TEXTURE2"].iter().filter_map(|name| self.lump(name)) {
+            let data = &lump.data;
+
+            for i in 0..read_i32(data, 0)?.max(0) as usize {
+                let offset = read_offset(data, 4 + i * 4)?;
+
+                let name = read_name(data, offset)?;
+                let width = read_i16(data, offset + 12)?.max(1) as u32;
+                let height = read_i16(data, offset + 14)?.max(1) as u32;
+                check_size(&name, width, height)?;
+                let patch_count = read_i16(data, offset + 20)?.max(0) as usize;
+
+                let mut pixels = vec![0; width as usize * height as usize * 4];
+
+                for p in 0..patch_count {
+                    let entry = offset + 22 + p * 10;
+                    let origin_x = read_i16(data, entry)? as i32;
+                    let origin_y = read_i16(data, entry + 2)? as i32;
+                    let patch = read_u16(data, entry + 4)? as usize;
+
+                    // Missing patches are skipped, the texture just stays transparent there.
+                    let Some(patch_lump) = patch_names.get(patch).and_then(|name| self.lump(name)) else {
+                        continue;
+                    };
+
+                    draw_patch(&patch_lump.data, palette, &mut pixels, width, height, origin_x, origin_y)?;
+                }
+
+                textures.push((name, LoadedTexture::new(width, height, pixels)));
+            }
+        }
+
+        Ok(textures)
+    }
+
+    /// Flats are raw 64x64 palette indices between the F_START and F_END markers.
+    pub fn read_flats(&self, palette: &[Color]) -> Result<Vec<(String, LoadedTexture)>, String> {
+        let mut flats = Vec::new();
+        let mut inside = false;
+
+        for lump in &self.lumps {
+            // PWADs often use FF_START/FF_END, the IWAD also has F1_START etc. inside.
+            if lump.name.ends_with("F_START") {
+                inside = true;
+            } else if lump.name.ends_with("F_END") {
+                inside = false;
+            } else if inside && lump.data.len() == 4096 {
+                let pixels = lump.data.iter().flat_map(|&index| {
+                    let color = palette_color(palette, index);
+                    [color.r, color.g, color.b, 255]
+                }).collect();
+
+                flats.push((lump.name.clone(), LoadedTexture::new(64, 64, pixels)));
+            }
+        }
+
+        Ok(flats)
+    }
+
+    /// Sprite frames are patches between S_START and S_END, named like POSSA1 (sprite, frame, angle).
+    pub fn read_sprites(&self, palette: &[Color]) -> Result<Vec<(String, LoadedTexture)>, String> {
+        let mut sprites = Vec::new();
+        let mut inside = false;
+
+        for lump in &self.lumps {
+            if lump.name.ends_with("S_START") {
+                inside = true;
+            } else if lump.name.ends_with("S_END") {
+                inside = false;
+            } else if inside && lump.data.len() > 8 {
+                let width = read_u16(&lump.data, 0)?.max(1) as u32;
+                let height = read_u16(&lump.data, 2)?.max(1) as u32;
+                check_size(&lump.name, width, height)?;
+
+                let mut pixels = vec![0; width as usize * height as usize * 4];
+                draw_patch(&lump.data, palette, &mut pixels, width, height, 0, 0)?;
+
+                sprites.push((lump.name.clone(), LoadedTexture::new(width, height, pixels)));
+            }
+        }
+
+        Ok(sprites)
+    }
+}
+
+/// Textures and sprites bigger than this are broken, Doom's own top out at a few hundred pixels.
+/// Checked before the pixels get allocated, the size alone could ask for gigabytes.
+const MAX_PICTURE_SIZE: u32 = 4096;
+
+fn check_size(name: &str, width: u32, height: u32) -> Result<(), String> {
+    if width > MAX_PICTURE_SIZE || height > MAX_PICTURE_SIZE {
+        return Err(format!("{} is {}x{}, more than {} pixels", name, width, height, MAX_PICTURE_SIZE));
+    }
+    Ok(())
+}
+
+/// Draws a patch (columns of posts) into an RGBA texture, everything outside of the texture is cut off.
+fn draw_patch(patch: &[u8], palette: &[Color], pixels: &mut [u8], width: u32, height: u32, origin_x: i32, origin_y: i32) -> Result<(), String> {
+    let patch_width = read_u16(patch, 0)? as usize;
+
+    for column in 0..patch_width {
+        let x = origin_x + column as i32;
+        let mut pos = read_offset(patch, 8 + column * 4)?;
+
+        loop {
+            let [top_delta] = read_bytes(patch, pos).map_err(|_| "Patch column runs out of the lump")?;
+            if top_delta == 0xFF {
+                break;
+            }
+
+            let [length] = read_bytes(patch, pos + 1).map_err(|_| "Patch column runs out of the lump")?;
+            let length = length as usize;
+            let post = read_slice(patch, pos + 3, length).map_err(|_| "Patch post runs out of the lump")?;
+
+            for (i, &index) in post.iter().enumerate() {
+                let y = origin_y + top_delta as i32 + i as i32;
+                if x < 0 || y < 0 || x >= width as i32 || y >= height as i32 {
+                    continue;
+                }
+
+                let color = palette_color(palette, index);
+                let offset = (y as usize * width as usize + x as usize) * 4;
+                pixels[offset..offset + 4].copy_from_slice(&[color.r, color.g, color.b, 255]);
+            }
+
+            // Top delta, length, padding, the post and padding again.
+            pos += length + 4;
+        }
+    }
+
+    Ok(())
+}
+
+/// Music lumps of Doom 2's MAP01 - MAP32, Doom 1 names them after the map (D_E1M1).
+const DOOM2_MUSIC: [&str; 32] = [
+    "RUNNIN", "STALKS", "COUNTD", "BETWEE", "DOOM", "THE_DA", "SHAWN", "DDTBLU",
+    "IN_CIT", "DEAD", "STLKS2", "THEDA2", "DOOM2", "DDTBL2", "RUNNI2", "DEAD2",
+    "STLKS3", "ROMERO", "SHAWN2", "MESSAG", "COUNT2", "DDTBL3", "AMPIE", "THEDA3",
+    "ADRIAN", "MESSG2", "ROMER2", "TENSE", "SHAWN3", "OPENIN", "EVIL", "ULTIMA"
+];
+
+/// D_E1M1 for E1M1, D_RUNNIN for MAP01.
+fn music_name(map: &str) -> String {
+    match map.strip_prefix("MAP").and_then(|number| number.parse::<usize>().ok()) {
+        Some(number) => format!("D_{}", DOOM2_MUSIC.get(number.wrapping_sub(1)).unwrap_or(&"RUNNIN")),
+        None => format!("D_{}", map)
+    }
+}
+
+/// Sector special of Doom's secrets.
+const SECRET_SECTOR: u16 = 9;
+
+/// The line specials that can be used, as trigger actions. Doors open and stay open (keys aren't checked),
+/// walk-over lines and everything else aren't supported.
+fn line_action(linedef: &Linedef) -> Option<TriggerAction> {
+    match linedef.special {
+        // Manual doors, they open the sector behind them.
+        1 | 26 | 27 | 28 | 31 | 32 | 33 | 34 | 117 | 118 => Some(TriggerAction::Door(0)),
+        // Switches that open the doors with their tag.
+        29 | 63 | 103 | 111 | 114 => (linedef.tag != 0).then_some(TriggerAction::Door(linedef.tag)),
+        11 | 51 => Some(TriggerAction::Exit),
+        _ => None
+    }
+}
+
+/// Maps don't name their sky, Doom picks it by episode and Doom II by map number.
+fn sky_name(map: &str) -> &'static str {
+    if let Some(number) = map.strip_prefix("MAP").and_then(|number| number.parse::<u32>().ok()) {
+        return match number {
+            0..=11 => "SKY1",
+            12..=20 => "SKY2",
+            _ => "SKY3"
+        };
+    }
+
+    match map.get(..2) {
+        Some("E2") => "SKY2",
+        Some("E3") => "SKY3",
+        Some("E4") => "SKY4",
+        _ => "SKY1"
+    }
+}
+
+fn palette_color(palette: &[Color], index: u8) -> Color {
+    palette.get(index as usize).copied().unwrap_or(Color::BLACK)
+}
+
+/// Splits a lump into fixed size records and parses every one of them.
+fn records<T>(data: &[u8], size: usize, parse: impl Fn(&[u8]) -> Result<T, String>) -> Result<Vec<T>, String> {
+    data.chunks_exact(size).map(parse).collect()
+}
+
+/// `length` bytes at `pos`, an error instead of a panic however big both of them are.
+fn read_slice(data: &[u8], pos: usize, length: usize) -> Result<&[u8], String> {
+    pos.checked_add(length)
+        .and_then(|end| data.get(pos..end))
+        .ok_or_else(|| format!("Unexpected end of data at {}", pos))
+}
+
+fn read_bytes<const N: usize>(data: &[u8], pos: usize) -> Result<[u8; N], String> {
+    read_slice(data, pos, N)?.try_into().map_err(|_| format!("Unexpected end of data at {}", pos))
+}
+
+fn read_i16(data: &[u8], pos: usize) -> Result<i16, String> {
+    read_bytes(data, pos).map(i16::from_le_bytes)
+}
+
+fn read_u16(data: &[u8], pos: usize) -> Result<u16, String> {
+    read_bytes(data, pos).map(u16::from_le_bytes)
+}
+
+fn read_i32(data: &[u8], pos: usize) -> Result<i32, String> {
+    read_bytes(data, pos).map(i32::from_le_bytes)
+}
+
+/// Offsets, sizes and counts are signed in the file, negative ones are broken.
+fn read_offset(data: &[u8], pos: usize) -> Result<usize, String> {
+    let value = read_i32(data, pos)?;
+    usize::try_from(value).map_err(|_| format!("Negative offset {} at {}", value, pos))
+}
+
+/// Lump and texture names are 8 bytes, padded with zeros and case insensitive.
+fn read_name(data: &[u8], pos: usize) -> Result<String, String> {
+    let bytes: [u8; 8] = read_bytes(data, pos)?;
+    let length = bytes.iter().position(|&b| b == 0).unwrap_or(8);
+
+    Ok(String::from_utf8_lossy(&bytes[..length]).to_uppercase())
+}
+
+/// Like read_name(), but "-" (no texture) becomes an empty name.
+fn read_texture_name(data: &[u8], pos: usize) -> Result<String, String> {
+    read_name(data, pos).map(|name| if name == "-" {String::new()} else {name})
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn name(name: &str) -> Vec<u8> {
+        let mut bytes = name.as_bytes().to_vec();
+        bytes.resize(8, 0);
+        bytes
+    }
+
+    fn le(values: &[i32], size: usize) -> Vec<u8> {
+        values.iter().flat_map(|value| value.to_le_bytes()[..size].to_vec()).collect()
+    }
+
+    /// A PWAD with the lumps right after the header and the directory at the end.
+    fn build(lumps: &[(&str, Vec<u8>)]) -> Vec<u8> {
+        let data_size: usize = lumps.iter().map(|(_, data)| data.len()).sum();
+        let mut bytes = b"PWAD".to_vec();
+        bytes.extend(le(&[lumps.len() as i32, 12 + data_size as i32], 4));
+
+        let mut directory = Vec::new();
+        for (lump_name, data) in lumps {
+            directory.extend(le(&[bytes.len() as i32, data.len() as i32], 4));
+            directory.extend(name(lump_name));
+            bytes.extend(data);
+        }
+        bytes.extend(directory);
+        bytes
+    }
+
+    /// A square room with a door switch on its first line and a 2x2 texture with one 1x2 patch.
+    fn test_wad() -> Vec<u8> {
+        let playpal: Vec<u8> = (0..=255u8).flat_map(|i| [i, i / 2, 255 - i]).collect();
+
+        let vertices = le(&[0, 0, 64, 0, 64, 64, 0, 64], 2);
+        let linedefs = [[0, 1, 1, 1, 0, 0, -1], [1, 2, 1, 0, 0, 1, -1], [2, 3, 1, 0, 0, 2, -1], [3, 0, 1, 0, 0, 3, -1]]
+            .iter()
+            .flat_map(|linedef| le(linedef, 2))
+            .collect();
+        let sidedefs = (0..4).flat_map(|_| [le(&[0, 0], 2), name("-"), name("-"), name("WALL"), le(&[0], 2)].concat()).collect();
+        let sectors = [le(&[0, 128], 2), name("FLOOR"), name("CEIL"), le(&[160, 0, 0], 2)].concat();
+        let things = le(&[32, 32, 90, 1, 7], 2);
+
+        let pnames = [le(&[1], 4), name("PATCH1")].concat();
+        let texture1 = [le(&[1, 8], 4), name("WALL"), le(&[0], 4), le(&[2, 2], 2), le(&[0], 4), le(&[1], 2), le(&[1, 0, 0, 1, 0], 2)].concat();
+        let patch = [le(&[1, 2, 0, 0], 2), le(&[12], 4), vec![0, 2, 0, 5, 6, 0, 0xFF]].concat();
+
+        build(&[
+            ("PLAYPAL", playpal),
+            ("MAP01", Vec::new()),
+            ("THINGS", things),
+            ("LINEDEFS", linedefs),
+            ("SIDEDEFS", sidedefs),
+            ("VERTEXES", vertices),
+            ("SECTORS", sectors),
+            ("PNAMES", pnames),
+            ("TEXTURE1", texture1),
+            ("PATCH1", patch)
+        ])
+    }
+
+    #[test]
+    fn reads_the_directory() {
+        let wad = Wad::from_bytes(&test_wad()).unwrap();
+
+        assert!(wad.kind == WadKind::Pwad);
+        assert_eq!(wad.lumps.len(), 10);
+        assert_eq!(wad.lump("PNAMES").unwrap().data.len(), 12);
+    }
+
+    #[test]
+    fn reads_a_map() {
+        let level = Wad::from_bytes(&test_wad()).unwrap().read_level("MAP01").unwrap();
+
+        let vertices: Vec<(f64, f64)> = level.vertices.iter().map(|vertex| (vertex.x, vertex.y)).collect();
+        assert_eq!(vertices, [(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)]);
+
+        let linedefs: Vec<_> = level.linedefs.iter().map(|linedef| (linedef.start, linedef.end, linedef.special, linedef.front, linedef.back)).collect();
+        assert_eq!(linedefs, [(0, 1, 1, Some(0), None), (1, 2, 0, Some(1), None), (2, 3, 0, Some(2), None), (3, 0, 0, Some(3), None)]);
+
+        assert_eq!(level.sidedefs[0].middle, "WALL");
+        assert_eq!(level.sidedefs[0].upper, "");
+        assert_eq!((level.sectors[0].floor, level.sectors[0].ceiling, level.sectors[0].light), (0.0, 2.0, 160));
+        assert_eq!((level.things[0].pos.x, level.things[0].pos.y, level.things[0].kind), (0.5, 0.5, 1));
+        assert_eq!(level.sky.as_deref(), Some("SKY1"));
+
+        assert_eq!(level.triggers.len(), 1);
+        assert!(level.triggers[0].activation == Activation::Switch { line: 0, texture: None });
+        assert!(level.triggers[0].action == TriggerAction::Door(0));
+    }
+
+    #[test]
+    fn maps_need_a_sector() {
+        let lumps = ["THINGS", "LINEDEFS", "SIDEDEFS", "VERTEXES", "SECTORS"].map(|lump| (lump, Vec::new()));
+        let wad = Wad::from_bytes(&build(&[&[("MAP01", Vec::new())], &lumps[..]].concat())).unwrap();
+
+        assert!(wad.read_level("MAP01").is_err());
+    }
+
+    #[test]
+    fn reads_the_palette() {
+        let palette = Wad::from_bytes(&test_wad()).unwrap().read_palette().unwrap();
+
+        assert_eq!(palette.len(), 256);
+        assert_eq!(palette[0], Color::RGB(0, 0, 255));
+        assert_eq!(palette[200], Color::RGB(200, 100, 55));
+    }
+
+    #[test]
+    fn builds_composite_textures() {
+        let wad = Wad::from_bytes(&test_wad()).unwrap();
+        let textures = wad.read_textures(&wad.read_palette().unwrap()).unwrap();
+
+        assert_eq!(textures.len(), 1);
+        let (name, texture) = &textures[0];
+        assert_eq!(name, "WALL");
+        assert_eq!((texture.width, texture.height), (2, 2));
+
+        // The patch is one column wide at x 1, x 0 stays transparent.
+        assert_eq!(texture.pixels, [
+            0, 0, 0, 0,   5, 2, 250, 255,
+            0, 0, 0, 0,   6, 3, 249, 255
+        ]);
+    }
+
+    #[test]
+    fn rejects_broken_headers() {
+        assert!(Wad::from_bytes(b"PWAD\x01\0").is_err());
+        assert!(Wad::from_bytes(b"WAD!\0\0\0\0\x0C\0\0\0").is_err());
+
+        // Far more lumps than the file could hold, negative counts and directories.
+        assert!(Wad::from_bytes(&[b"PWAD".to_vec(), le(&[i32::MAX, 12], 4)].concat()).is_err());
+        assert!(Wad::from_bytes(&[b"PWAD".to_vec(), le(&[-1, 12], 4)].concat()).is_err());
+        assert!(Wad::from_bytes(&[b"PWAD".to_vec(), le(&[1, -16], 4)].concat()).is_err());
+    }
+
+    #[test]
+    fn rejects_broken_lumps() {
+        let directory = |pos: i32, size: i32| [b"PWAD".to_vec(), le(&[1, 12, pos, size], 4), name("LUMP")].concat();
+
+        assert!(Wad::from_bytes(&directory(0, 12)).is_ok());
+        assert!(Wad::from_bytes(&directory(0, 1000)).is_err());
+        assert!(Wad::from_bytes(&directory(-1, 4)).is_err());
+        assert!(Wad::from_bytes(&directory(4, -1)).is_err());
+        assert!(Wad::from_bytes(&directory(i32::MAX, i32::MAX)).is_err());
+
+        let mut truncated = test_wad();
+        truncated.truncate(truncated.len() - 1);
+        assert!(Wad::from_bytes(&truncated).is_err());
+    }
+
+    #[test]
+    fn rejects_huge_pictures() {
+        let texture1 = [le(&[1, 8], 4), name("HUGE"), le(&[0], 4), le(&[32767, 32767], 2), le(&[0], 4), le(&[0], 2)].concat();
+        let wad = Wad::from_bytes(&build(&[("PNAMES", le(&[0], 4)), ("TEXTURE1", texture1)])).unwrap();
+        assert!(wad.read_textures(&[]).is_err());
+
+        let sprite = [le(&[65535, 65535, 0, 0], 2), vec![0; 4]].concat();
+        let wad = Wad::from_bytes(&build(&[("S_START", Vec::new()), ("HUGEA0", sprite), ("S_END", Vec::new())])).unwrap();
+        assert!(wad.read_sprites(&[]).is_err());
+    }
+
+    #[test]
+    fn rejects_broken_patches() {
+        let palette = vec![Color::BLACK; 256];
+        let mut pixels = vec![0; 16];
+
+        // Column offsets that are negative or past the lump, and a post longer than the lump.
+        assert!(draw_patch(&[le(&[1, 2, 0, 0], 2), le(&[-8], 4)].concat(), &palette, &mut pixels, 2, 2, 0, 0).is_err());
+        assert!(draw_patch(&[le(&[1, 2, 0, 0], 2), le(&[i32::MAX], 4)].concat(), &palette, &mut pixels, 2, 2, 0, 0).is_err());
+        assert!(draw_patch(&[le(&[1, 2, 0, 0], 2), le(&[12], 4), vec![0, 200, 0, 5]].concat(), &palette, &mut pixels, 2, 2, 0, 0).is_err());
+    }
+}
@@ -0,0 +1,414 @@
+use crate::ai;
+use crate::audio::Sound;
+use crate::config::{PLAYER_RADIUS, TICK_RATE, WAD_SCALE};
+use crate::data::{GameData, PlayerData, Vec2};
+use crate::entity::{Entity, ThingType, STILL};
+use crate::sprite::{Animation, State};
+
+/// The kinds of ammo, several weapons can share one.
+#[derive(Clone, Copy)]
+pub enum Ammo {
+    Bullets,
+    Shells,
+    Rockets
+}
+
+pub enum Attack {
+    /// Hits right away, damage is rolled from 1 up to the given number.
+    Hitscan { damage: u32, range: f64 },
+    /// Spawns an entity that flies in a straight line, speed in grid units per second.
+    Projectile { thing_type: &'static ThingType, speed: f64, damage: u32 }
+}
+
+/// Pistol, shotgun and rocket launcher, the order of WEAPONS is the switching order.
+pub struct WeaponType {
+    /// The first-person sprite like a thing's, frame A is the weapon at rest.
+    pub sprite: &'static str,
+    pub pic: &'static str,
+    /// Frames shown while the weapon fires, spread over the ticks between shots.
+    pub fire_frames: &'static str,
+    pub ammo: Ammo,
+    pub ammo_per_shot: u32,
+    /// Ticks between shots.
+    pub fire_ticks: u32,
+    /// Shots per trigger pull, every one gets its own spread.
+    pub pellets: u32,
+    /// Largest angle a shot can be off, in radians.
+    pub spread: f64,
+    pub attack: Attack,
+    pub sound: Sound
+}
+
+pub const WEAPONS: [WeaponType; 3] = [
+    WeaponType { sprite: "PISG", pic: "pistol", fire_frames: "BCB", ammo: Ammo::Bullets, ammo_per_shot: 1, fire_ticks: 14, pellets: 1, spread: 0.02, attack: Attack::Hitscan { damage: 15, range: 32.0 }, sound: Sound::Pistol },
+    WeaponType { sprite: "SHTG", pic: "shotgun", fire_frames: "BCDCB", ammo: Ammo::Shells, ammo_per_shot: 1, fire_ticks: 37, pellets: 7, spread: 0.1, attack: Attack::Hitscan { damage: 15, range: 32.0 }, sound: Sound::Shotgun },
+    WeaponType { sprite: "MISG", pic: "launcher", fire_frames: "B", ammo: Ammo::Rockets, ammo_per_shot: 1, fire_ticks: 20, pellets: 1, spread: 0.0, attack: Attack::Projectile { thing_type: &ROCKET, speed: 11.0, damage: 160 }, sound: Sound::RocketLaunch }
+];
+
+const PUFF_ANIMATION: &[Animation] = &[Animation { state: State::Idle, frames: "ABCD", tics: 4 }];
+
+/// Flies until it hits something, see fly().
+pub const ROCKET: ThingType = ThingType {
+    kind: 0, sprite: "MISL", pic: "rocket", radius: 11.0 * WAD_SCALE, height: 8.0 * WAD_SCALE, tags: 0, update: Some(fly), animations: STILL, monster: None, pickup: None
+};
+
+/// Shown where a shot hits, goes away once its animation is over.
+pub const PUFF: ThingType = ThingType {
+    kind: 0, sprite: "PUFF", pic: "puff", radius: 0.0, height: 0.0, tags: 0, update: Some(fade), animations: PUFF_ANIMATION, monster: None, pickup: None
+};
+
+/// Thing types that aren't placed in levels but still need sprites.
+pub const SPAWNED_TYPES: [&ThingType; 2] = [&ROCKET, &PUFF];
+
+/// Height shots are fired at, about the middle of the view.
+const SHOT_HEIGHT: f64 = 0.4;
+
+/// What the player is carrying and doing with it.
+pub struct Weapons {
+    /// Index into WEAPONS.
+    pub current: usize,
+    /// Indexed by Ammo.
+    pub ammo: [u32; 3],
+    /// Ticks until the next shot.
+    pub cooldown: u32,
+    /// The fire button is held, the shots happen in tick().
+    pub firing: bool
+}
+
+impl Default for Weapons {
+    fn default() -> Self {
+        Weapons { current: 0, ammo: [50, 20, 10], cooldown: 0, firing: false }
+    }
+}
+
+impl Weapons {
+    pub fn weapon(&self) -> &'static WeaponType {
+        &WEAPONS[self.current]
+    }
+
+    pub fn ammo_left(&self) -> u32 {
+        self.ammo[self.weapon().ammo as usize]
+    }
+
+    /// How far the current shot is (0.0 - 1.0), None while the weapon is ready.
+    pub fn fire_progress(&self) -> Option<f64> {
+        (self.cooldown > 0).then(|| 1.0 - self.cooldown as f64 / self.weapon().fire_ticks as f64)
+    }
+
+    /// Switches to the next weapon, not while it is still firing.
+    pub fn next_weapon(&mut self) {
+        if self.cooldown == 0 {
+            self.current = (self.current + 1) % WEAPONS.len();
+        }
+    }
+}
+
+/// What a shot hit.
+pub enum Target {
+    /// The normal points away from the wall, towards the shooter.
+    Wall { normal: Vec2<f64> },
+    /// Index into GameData::entities.
+    Entity(usize)
+}
+
+pub struct Hit {
+    pub point: Vec2<f64>,
+    pub dist: f64,
+    pub target: Target
+}
+
+/// Shoots a ray and returns the first wall or living entity on it, entities are circles of their radius.
+pub fn hitscan(game_data: &GameData, from: &Vec2<f64>, angle: f64, range: f64) -> Option<Hit> {
+    let dir = Vec2::new(angle.cos(), angle.sin());
+    let (wall_dist, normal) = game_data.cast_wall(from, &dir);
+    let mut closest = Hit { point: Vec2::new(from.x + dir.x * wall_dist, from.y + dir.y * wall_dist), dist: wall_dist, target: Target::Wall { normal } };
+
+    for (index, entity) in game_data.entities.iter().enumerate().filter(|(_, entity)| entity.is_alive()) {
+        let to_entity = Vec2::new(entity.pos.x - from.x, entity.pos.y - from.y);
+        let along = to_entity.x * dir.x + to_entity.y * dir.y;
+        let off = (to_entity.x - dir.x * along).hypot(to_entity.y - dir.y * along);
+
+        if along <= 0.0 || off >= entity.radius {
+            continue;
+        }
+
+        let dist = along - (entity.radius.powi(2) - off.powi(2)).sqrt();
+        if dist < closest.dist {
+            closest = Hit { point: Vec2::new(from.x + dir.x * dist, from.y + dir.y * dist), dist, target: Target::Entity(index) };
+        }
+    }
+
+    (closest.dist <= range).then_some(closest)
+}
+
+/// Fires the current weapon if the trigger is held, it is ready and there is ammo. Called every tick.
+pub fn tick(player: &mut PlayerData, game_data: &mut GameData) {
+    let weapons = &mut player.weapons;
+    weapons.cooldown = weapons.cooldown.saturating_sub(1);
+
+    let weapon = weapons.weapon();
+    if !weapons.firing || weapons.cooldown > 0 || weapons.ammo_left() < weapon.ammo_per_shot {
+        return;
+    }
+
+    weapons.ammo[weapon.ammo as usize] -= weapon.ammo_per_shot;
+    weapons.cooldown = weapon.fire_ticks;
+
+    let pos = player.pos;
+    let aim = player.dir.y.atan2(player.dir.x);
+    ai::alert(game_data, &pos);
+    game_data.audio.play(weapon.sound, None);
+
+    for _ in 0..weapon.pellets {
+        let angle = aim + (game_data.rng.unit() * 2.0 - 1.0) * weapon.spread;
+
+        match weapon.attack {
+            Attack::Hitscan { damage, range } => {
+                let Some(hit) = hitscan(game_data, &pos, angle, range) else {
+                    continue;
+                };
+
+                if let Target::Entity(index) = hit.target {
+                    let damage = 1 + game_data.rng.below(damage);
+                    hurt(game_data, index, damage);
+                }
+
+                spawn_puff(game_data, &hit);
+            },
+            Attack::Projectile { thing_type, speed, damage } => {
+                let dir = Vec2::new(angle.cos(), angle.sin());
+                let start = Vec2::new(pos.x + dir.x * (PLAYER_RADIUS + thing_type.radius), pos.y + dir.y * (PLAYER_RADIUS + thing_type.radius));
+
+                let mut entity = Entity::new(start, angle, thing_type);
+                entity.z = SHOT_HEIGHT;
+                entity.projectile = Some(Projectile { velocity: Vec2::new(dir.x * speed, dir.y * speed), damage });
+                game_data.entities.push(entity);
+            }
+        }
+    }
+}
+
+/// Damages an entity, monsters cry out when it makes them flinch or die.
+fn hurt(game_data: &mut GameData, index: usize, damage: u32) {
+    let entity = &mut game_data.entities[index];
+    let sound = match (entity.hurt(damage as i32, &mut game_data.rng), entity.monster) {
+        (Some(State::Pain), Some(monster)) => monster.sounds.pain,
+        (Some(State::Death), Some(monster)) => monster.sounds.death,
+        _ => return
+    };
+
+    let pos = entity.pos;
+    game_data.audio.play(sound, Some(pos));
+}
+
+/// Puts a puff where a shot hit, a little bit in front of walls so it doesn't get hidden by them.
+fn spawn_puff(game_data: &mut GameData, hit: &Hit) {
+    let point = match hit.target {
+        Target::Wall { normal } => Vec2::new(hit.point.x + normal.x * 0.05, hit.point.y + normal.y * 0.05),
+        Target::Entity(_) => hit.point
+    };
+
+    let mut puff = Entity::new(point, 0.0, &PUFF);
+    puff.z = SHOT_HEIGHT;
+    game_data.entities.push(puff);
+}
+
+/// Movement of a flying entity.
+#[derive(Clone, Copy)]
+pub struct Projectile {
+    /// Grid units per second.
+    pub velocity: Vec2<f64>,
+    pub damage: u32
+}
+
+/// Update hook of projectiles: moves them and blows them up on the first wall or living entity they touch.
+pub fn fly(index: usize, _player: &mut PlayerData, game_data: &mut GameData) {
+    let entity = &game_data.entities[index];
+    let Some(projectile) = entity.projectile else {
+        return;
+    };
+
+    let from = entity.pos;
+    let step = Vec2::new(projectile.velocity.x / TICK_RATE, projectile.velocity.y / TICK_RATE);
+    let to = Vec2::new(from.x + step.x, from.y + step.y);
+    let radius = entity.radius;
+
+    let target = game_data.entities.iter().position(|other| {
+        other.is_alive() && (to.x - other.pos.x).hypot(to.y - other.pos.y) < other.radius + radius
+    });
+
+    // `t` is measured in steps, anything up to 1.0 is hit during this tick.
+    let (wall_t, normal) = game_data.cast_wall(&from, &step);
+
+    let hit = if let Some(target) = target {
+        Hit { point: to, dist: 1.0, target: Target::Entity(target) }
+    } else if wall_t <= 1.0 {
+        Hit { point: Vec2::new(from.x + step.x * wall_t, from.y + step.y * wall_t), dist: wall_t, target: Target::Wall { normal } }
+    } else {
+        game_data.entities[index].pos = to;
+        return;
+    };
+
+    if let Target::Entity(target) = hit.target {
+        let damage = 1 + game_data.rng.below(projectile.damage);
+        hurt(game_data, target, damage);
+    }
+
+    game_data.entities[index].removed = true;
+    game_data.audio.play(Sound::Explosion, Some(from));
+    spawn_puff(game_data, &hit);
+}
+
+/// Update hook of puffs.
+pub fn fade(index: usize, _player: &mut PlayerData, game_data: &mut GameData) {
+    let entity = &mut game_data.entities[index];
+    if entity.state_ticks >= entity.animation_length() {
+        entity.removed = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entity::{self, thing_type};
+    use crate::map::Map;
+    use crate::random::Rng;
+
+    /// A room with one wall tile at (5, 1), indexed [x][y].
+    const GRID: [[i32; 5]; 8] = [
+        [1, 1, 1, 1, 1],
+        [1, 0, 0, 0, 1],
+        [1, 0, 0, 0, 1],
+        [1, 0, 0, 0, 1],
+        [1, 0, 0, 0, 1],
+        [1, 1, 0, 0, 1],
+        [1, 0, 0, 0, 1],
+        [1, 1, 1, 1, 1]
+    ];
+
+    fn game_data() -> GameData {
+        GameData::new(false, 320, 200, Map::from_grid(&GRID, None))
+    }
+
+    fn monster(x: f64, y: f64) -> Entity {
+        Entity::new(Vec2::new(x, y), 0.0, thing_type(3004).unwrap())
+    }
+
+    fn rocket(x: f64, y: f64) -> Entity {
+        let mut rocket = Entity::new(Vec2::new(x, y), 0.0, &ROCKET);
+        rocket.projectile = Some(Projectile { velocity: Vec2::new(11.0, 0.0), damage: 160 });
+        rocket
+    }
+
+    fn close(a: &Vec2<f64>, x: f64, y: f64) -> bool {
+        (a.x - x).abs() < 1e-9 && (a.y - y).abs() < 1e-9
+    }
+
+    #[test]
+    fn shots_stop_at_walls() {
+        let game_data = game_data();
+        let from = Vec2::new(1.5, 2.5);
+
+        let hit = hitscan(&game_data, &from, 0.0, 32.0).unwrap();
+        let Target::Wall { normal } = hit.target else { panic!("should hit the wall") };
+        assert!(close(&hit.point, 7.0, 2.5) && close(&normal, -1.0, 0.0));
+        assert!((hit.dist - 5.5).abs() < 1e-9);
+
+        let hit = hitscan(&game_data, &from, std::f64::consts::FRAC_PI_2, 32.0).unwrap();
+        let Target::Wall { normal } = hit.target else { panic!("should hit the wall") };
+        assert!(close(&hit.point, 1.5, 4.0) && close(&normal, 0.0, -1.0));
+
+        assert!(hitscan(&game_data, &from, 0.0, 5.0).is_none());
+    }
+
+    #[test]
+    fn shots_hit_the_nearest_entity() {
+        let mut game_data = game_data();
+        game_data.entities = vec![monster(5.5, 2.5), monster(3.5, 2.5 + 0.1), monster(6.5, 1.5), monster(2.5, 2.5)];
+        let radius = game_data.entities[0].radius;
+        game_data.entities[3].health = 0;
+
+        // The dead one is passed, the other two are in line and the closer one gets hit off its middle.
+        let hit = hitscan(&game_data, &Vec2::new(1.5, 2.5), 0.0, 32.0).unwrap();
+        assert!(matches!(hit.target, Target::Entity(1)));
+        let dist = 2.0 - (radius.powi(2) - 0.01).sqrt();
+        assert!((hit.dist - dist).abs() < 1e-9 && close(&hit.point, 1.5 + dist, 2.5));
+
+        // Behind the wall tile.
+        let hit = hitscan(&game_data, &Vec2::new(1.5, 1.5), 0.0, 32.0).unwrap();
+        assert!(matches!(hit.target, Target::Wall { .. }) && close(&hit.point, 5.0, 1.5));
+    }
+
+    #[test]
+    fn rockets_blow_up_on_walls() {
+        let mut game_data = game_data();
+        let mut player = game_data.new_player();
+        game_data.entities = vec![rocket(4.0, 2.5)];
+
+        for _ in 0..TICK_RATE as usize {
+            if game_data.entities[0].removed {
+                break;
+            }
+            fly(0, &mut player, &mut game_data);
+            assert!(game_data.entities[0].pos.x < 7.0);
+        }
+
+        // The puff is just in front of the wall.
+        assert!(game_data.entities[0].removed);
+        assert_eq!(game_data.entities.len(), 2);
+        assert!(close(&game_data.entities[1].pos, 7.0 - 0.05, 2.5));
+    }
+
+    #[test]
+    fn rockets_blow_up_on_entities() {
+        let mut game_data = game_data();
+        let mut player = game_data.new_player();
+        game_data.entities = vec![rocket(2.0, 2.5), monster(4.0, 2.5)];
+        let health = game_data.entities[1].health;
+
+        for _ in 0..TICK_RATE as usize {
+            if game_data.entities[0].removed {
+                break;
+            }
+            fly(0, &mut player, &mut game_data);
+        }
+
+        // Blown up where it touched the monster, not at the wall behind it.
+        assert!(game_data.entities[0].removed && game_data.entities[0].pos.x < 4.0);
+        assert!(game_data.entities[1].health < health);
+        assert_eq!(game_data.entities.len(), 3);
+        assert!((game_data.entities[2].pos.x - 4.0).abs() < game_data.entities[1].radius + ROCKET.radius);
+    }
+
+    /// Ammo left, the health of the monsters and the random state after firing the shotgun for some ticks.
+    fn play(seed: u64, ticks: u32) -> (u32, Vec<i32>, u64) {
+        let mut game_data = game_data();
+        game_data.rng = Rng::new(seed);
+        game_data.entities = vec![monster(3.5, 2.5), monster(4.5, 3.5), monster(6.5, 2.5)];
+
+        let mut player = game_data.new_player();
+        player.pos = Vec2::new(1.5, 2.5);
+        player.dir = Vec2::new(1.0, 0.0);
+        player.god = true;
+        player.weapons.current = 1;
+        player.weapons.firing = true;
+
+        for _ in 0..ticks {
+            tick(&mut player, &mut game_data);
+            entity::tick(&mut player, &mut game_data);
+        }
+
+        let health = game_data.entities.iter().filter(|entity| entity.monster.is_some()).map(|entity| entity.health).collect();
+        (player.weapons.ammo_left(), health, game_data.rng.state())
+    }
+
+    #[test]
+    fn the_same_seed_shoots_the_same() {
+        let first = play(1234, 100);
+
+        assert_eq!(first, play(1234, 100));
+        // Three shots, and the pellets hit something.
+        assert_eq!(first.0, Weapons::default().ammo[Ammo::Shells as usize] - 3);
+        assert!(first.1.iter().any(|&health| health < thing_type(3004).unwrap().monster.unwrap().health));
+    }
+}